@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use failure::Error;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use reqwest::header::RANGE;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use url::Url;
+use url::percent_encoding::{PATH_SEGMENT_ENCODE_SET, utf8_percent_encode};
+
+use data::{Movie, TvEpisode, TvSeries, TvShow, WatchProgress};
+
+use crate::error::CliError;
+
+/// The Carolus server the CLI talks to.
+#[derive(Clone)]
+pub struct Server {
+    client: Client,
+    /// Without a time limit, for downloads and requests that take the server a while.
+    transfer_client: Client,
+    base: Url,
+    token: Option<String>,
+}
+
+/// Whether a title is of a movie or a tv show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Movie,
+    TvShow,
+}
+
+impl Server {
+    pub fn new(host: Option<&str>, token: Option<&str>) -> Result<Self, Error> {
+        let host = host.ok_or_else(|| CliError::Usage { message: "no server was given, use -h, set CAROLUS_SERVER_URL or keep one in a profile with set-token".to_owned() })?;
+        Ok(Server {
+            client: Client::new(),
+            transfer_client: Client::builder().timeout(None).build()?,
+            base: parse_server_url(host)?,
+            token: token.map(str::to_owned),
+        })
+    }
+
+    /// Reads a page of the JSON API, given relative to the server.
+    pub fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T, Error> {
+        let request = self.client.get(self.url(path)?).query(query);
+        Ok(self.send(request)?.json()?)
+    }
+
+    /// Like [get](#method.get), but waits as long as the server takes.
+    pub fn get_slow<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T, Error> {
+        let request = self.transfer_client.get(self.url(path)?).query(query);
+        Ok(self.send(request)?.json()?)
+    }
+
+    /// Starts downloading a file, or the bytes from `start` to `end` (inclusive) of it.
+    pub fn download(&self, path: &str, range: Option<(u64, u64)>) -> Result<Response, Error> {
+        let mut request = self.transfer_client.get(self.url(path)?);
+        if let Some((start, end)) = range {
+            request = request.header(RANGE, format!("bytes={}-{}", start, end));
+        }
+        self.send(request)
+    }
+
+    /// Sends JSON to the API, to a path relative to the server.
+    pub fn post<T: Serialize>(&self, path: &str, body: &T) -> Result<(), Error> {
+        let request = self.client.post(self.url(path)?).json(body);
+        self.send(request)?;
+        Ok(())
+    }
+
+    /// Sends JSON to the API and reads the JSON it replies with, waiting as long as the server takes.
+    pub fn post_slow<T: Serialize, R: DeserializeOwned>(&self, path: &str, body: &T) -> Result<R, Error> {
+        let request = self.transfer_client.post(self.url(path)?).json(body);
+        Ok(self.send(request)?.json()?)
+    }
+
+    /// Turns a URL the server gave, relative to it, into one a player can open,
+    /// with the token in the query as players can't be relied on to send headers.
+    pub fn player_url(&self, path: &str) -> Result<String, Error> {
+        let mut url = self.url(path)?;
+        if let Some(ref token) = self.token {
+            url.query_pairs_mut().append_pair("token", token);
+        }
+        Ok(url.to_string())
+    }
+
+    /// The year to look a title up with: the one given, or else the year of the only movie or
+    /// tv show with that title. Without a year the server takes the first title that matches,
+    /// which is a guess when there are several, so they are listed instead.
+    pub fn resolve_year(&self, kind: Kind, title: &str, year: Option<u16>) -> Result<Option<u16>, Error> {
+        if year.is_some() {
+            return Ok(year);
+        }
+        let results: SearchResults = self.get("/api/search", &[("q", title)])?;
+        let mut years =
+            match kind {
+                Kind::Movie => results.movies.iter().filter(|m| m.title.eq_ignore_ascii_case(title)).map(|m| m.year).collect::<Vec<_>>(),
+                Kind::TvShow => results.tv_shows.iter().filter(|s| s.title.eq_ignore_ascii_case(title)).map(|s| s.year).collect::<Vec<_>>(),
+            };
+        years.sort();
+        years.dedup();
+        if years.len() > 1 {
+            let years = years.iter().map(|y| y.map_or_else(|| "no year".to_owned(), |y| y.to_string())).collect::<Vec<_>>();
+            return Err(CliError::Ambiguous { title: title.to_owned(), years: years.join(", ") }.into());
+        }
+        Ok(years.pop().and_then(|y| y))
+    }
+
+    /// A URL on the server from a path relative to it, which keeps any path the server is under.
+    fn url(&self, path: &str) -> Result<Url, Error> {
+        Ok(Url::parse(&format!("{}{}", self.base.as_str().trim_end_matches('/'), path))?)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let request =
+            match self.token {
+                Some(ref token) => request.bearer_auth(token),
+                None => request,
+            };
+        let response = request.send()
+            .map_err(|e| CliError::Connection { url: self.base.to_string(), cause: e.to_string() })?;
+        self::response(response)
+    }
+}
+
+/// Checks the server URL is one that requests can be sent to.
+fn parse_server_url(host: &str) -> Result<Url, CliError> {
+    let invalid = |reason: String| CliError::Usage { message: format!("'{}' is not a server URL: {}", host, reason) };
+    if !host.contains("://") {
+        return Err(invalid("it needs to start with http:// or https://".to_owned()));
+    }
+    let url = Url::parse(host).map_err(|e| invalid(e.to_string()))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(invalid("it needs to start with http:// or https://".to_owned()));
+    }
+    if url.host().is_none() {
+        return Err(invalid("it has no host".to_owned()));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid("it can't have a query or fragment".to_owned()));
+    }
+    Ok(url)
+}
+
+/// Fails with the server's message when it couldn't do what was asked.
+fn response(mut response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message =
+        match response.json::<ErrorPayload>() {
+            Ok(error) => error.message,
+            Err(_) => format!("the server replied {}", status),
+        };
+    Err(match status {
+        StatusCode::NOT_FOUND => CliError::NotFound { message },
+        _ => CliError::Server { status: status.as_u16(), message },
+    }.into())
+}
+
+/// A path on the server from its segments, escaping what can't be in a path segment as it is,
+/// such as the `/`, `?`, `#` and `%` that titles can have in them.
+pub fn api_path(segments: &[&str]) -> String {
+    segments.iter().map(|s| format!("/{}", utf8_percent_encode(s, PATH_SEGMENT_ENCODE_SET))).collect()
+}
+
+/// What the server says went wrong.
+#[derive(Deserialize)]
+struct ErrorPayload {
+    message: String,
+}
+
+/// `/api/movies`
+#[derive(Deserialize)]
+pub struct AllMovies {
+    pub movies: Vec<Movie>,
+}
+
+/// `/api/tv`
+#[derive(Deserialize)]
+pub struct AllTvShows {
+    pub tv_shows: Vec<TvShow>,
+}
+
+/// `/api/movie/{movie}`
+#[derive(Deserialize)]
+pub struct MovieDetails {
+    pub movie: Movie,
+}
+
+/// `/api/tv/{tv_show}`
+#[derive(Deserialize)]
+pub struct TvShowDetails {
+    pub tv_show: TvShow,
+}
+
+/// `/api/tv/{tv_show}/{series}`
+#[derive(Deserialize)]
+pub struct TvSeriesDetails {
+    pub tv_show: TvShow,
+    pub tv_series: TvSeries,
+}
+
+/// `/api/tv/{tv_show}/{series}/{episode}`
+#[derive(Deserialize)]
+pub struct TvEpisodeDetails {
+    pub tv_show: TvShow,
+    pub tv_series: TvSeries,
+    pub tv_episode: TvEpisode,
+}
+
+/// `/api/progress`
+#[derive(Deserialize)]
+pub struct AllProgress {
+    /// By the path of the first file of a version.
+    pub progress: HashMap<String, WatchProgress>,
+}
+
+/// `/api/search?q=...`
+#[derive(Deserialize)]
+pub struct SearchResults {
+    pub movies: Vec<Movie>,
+    pub tv_shows: Vec<TvShow>,
+}
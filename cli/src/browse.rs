@@ -0,0 +1,170 @@
+use clap::ArgMatches;
+use failure::{Error, bail};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use data::{Extra, MediaVersion};
+
+use crate::api::{AllMovies, AllTvShows, Kind, MovieDetails, SearchResults, Server, TvEpisodeDetails, TvSeriesDetails, TvShowDetails, api_path};
+use crate::config::Profile;
+use crate::output::{self, Format, Table};
+
+/// Reads from the API, printing the JSON as it is when that is what was asked for.
+fn fetch<T: DeserializeOwned>(server: &Server, format: Format, path: &str, query: &[(&str, &str)]) -> Result<Option<T>, Error> {
+    let value = server.get::<Value>(path, query)?;
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(value)?))
+}
+
+/// The year to look the title up with, as `?year=`, which has to be given when several have the title.
+fn year_query(server: &Server, kind: Kind, matches: &ArgMatches) -> Result<Option<String>, Error> {
+    let year = server.resolve_year(kind, matches.value_of("title").unwrap(), crate::number_arg(matches, "year")?)?;
+    Ok(year.map(|y| y.to_string()))
+}
+
+fn query(year: &Option<String>) -> Vec<(&'static str, &str)> {
+    year.as_ref().map(|year| ("year", year.as_str())).into_iter().collect()
+}
+
+pub fn handle_list(server: &Server, profile: &Profile, matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("movies", Some(matches)) => {
+            let format = Format::from_matches(matches, profile)?;
+            if let Some(all) = fetch::<AllMovies>(server, format, "/api/movies", &[])? {
+                let mut table = Table::new(vec!["Title", "Year", "Versions", "Extras"]);
+                for movie in all.movies {
+                    table.row(vec![movie.title, output::year(movie.year), movie.versions.len().to_string(), movie.extras.len().to_string()]);
+                }
+                table.print(format)?;
+            }
+        },
+        ("tv", Some(matches)) => {
+            let format = Format::from_matches(matches, profile)?;
+            if let Some(all) = fetch::<AllTvShows>(server, format, "/api/tv", &[])? {
+                let mut table = Table::new(vec!["Title", "Year", "Series", "Episodes", "Extras"]);
+                for tv_show in all.tv_shows {
+                    let episodes = tv_show.series.iter().map(|s| s.episodes.len()).sum::<usize>();
+                    table.row(vec![tv_show.title, output::year(tv_show.year), tv_show.series.len().to_string(), episodes.to_string(), tv_show.extras.len().to_string()]);
+                }
+                table.print(format)?;
+            }
+        },
+        (command, _) => bail!("unhandled command: list {}", command),
+    }
+    Ok(())
+}
+
+pub fn handle_info(server: &Server, profile: &Profile, matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("movie", Some(matches)) => {
+            let format = Format::from_matches(matches, profile)?;
+            let path = api_path(&["api", "movie", matches.value_of("title").unwrap()]);
+            let year = year_query(server, Kind::Movie, matches)?;
+            if let Some(details) = fetch::<MovieDetails>(server, format, &path, &query(&year))? {
+                let movie = details.movie;
+                output::title(format, &movie.year.map_or(movie.title.to_owned(), |y| format!("{} ({})", movie.title, y)))?;
+                print_versions(format, &movie.versions)?;
+                print_extras(format, &movie.extras)?;
+            }
+        },
+        ("tv", Some(matches)) => {
+            let format = Format::from_matches(matches, profile)?;
+            let title = matches.value_of("title").unwrap();
+            let series = crate::number_arg(matches, "series")?;
+            let episode = crate::number_arg(matches, "episode")?;
+            let year = year_query(server, Kind::TvShow, matches)?;
+            match (series, episode) {
+                (Some(series), Some(episode)) => {
+                    let path = api_path(&["api", "tv", title, &series.to_string(), &episode.to_string()]);
+                    if let Some(details) = fetch::<TvEpisodeDetails>(server, format, &path, &query(&year))? {
+                        output::title(format, &format!("{}: Series {} Episode {}", details.tv_show.title, details.tv_series.series_number, details.tv_episode.episode_number))?;
+                        print_versions(format, &details.tv_episode.versions)?;
+                    }
+                },
+                (Some(series), None) => {
+                    let path = api_path(&["api", "tv", title, &series.to_string()]);
+                    if let Some(details) = fetch::<TvSeriesDetails>(server, format, &path, &query(&year))? {
+                        output::title(format, &format!("{}: Series {}", details.tv_show.title, details.tv_series.series_number))?;
+                        let mut table = Table::new(vec!["Episode", "Versions", "Resolution", "Source", "File"]);
+                        for episode in details.tv_series.episodes {
+                            let best = episode.versions.first();
+                            table.row(vec![
+                                episode.episode_number.to_string(),
+                                episode.versions.len().to_string(),
+                                output::resolution(best.and_then(|v| v.quality.resolution)),
+                                best.and_then(|v| v.quality.source.clone()).unwrap_or_default(),
+                                best.map(|v| v.file_path.clone()).unwrap_or_default(),
+                            ]);
+                        }
+                        table.print(format)?;
+                        print_extras(format, &details.tv_series.extras)?;
+                    }
+                },
+                (None, _) => {
+                    let path = api_path(&["api", "tv", title]);
+                    if let Some(details) = fetch::<TvShowDetails>(server, format, &path, &query(&year))? {
+                        let tv_show = details.tv_show;
+                        output::title(format, &tv_show.year.map_or(tv_show.title.to_owned(), |y| format!("{} ({})", tv_show.title, y)))?;
+                        let mut table = Table::new(vec!["Series", "Episodes", "Extras"]);
+                        for series in &tv_show.series {
+                            table.row(vec![series.series_number.to_string(), series.episodes.len().to_string(), series.extras.len().to_string()]);
+                        }
+                        table.print(format)?;
+                        print_extras(format, &tv_show.extras)?;
+                    }
+                },
+            }
+        },
+        (command, _) => bail!("unhandled command: info {}", command),
+    }
+    Ok(())
+}
+
+pub fn handle_search(server: &Server, profile: &Profile, matches: &ArgMatches) -> Result<(), Error> {
+    let format = Format::from_matches(matches, profile)?;
+    let query = matches.values_of("query").unwrap().collect::<Vec<_>>().join(" ");
+    if let Some(results) = fetch::<SearchResults>(server, format, "/api/search", &[("q", &query)])? {
+        let mut table = Table::new(vec!["Kind", "Title", "Year"]);
+        for movie in results.movies {
+            table.row(vec!["movie".to_owned(), movie.title, output::year(movie.year)]);
+        }
+        for tv_show in results.tv_shows {
+            table.row(vec!["tv".to_owned(), tv_show.title, output::year(tv_show.year)]);
+        }
+        table.print(format)?;
+    }
+    Ok(())
+}
+
+fn print_versions(format: Format, versions: &[MediaVersion]) -> Result<(), Error> {
+    let mut table = Table::new(vec!["Version", "Name", "Edition", "Resolution", "Source", "Parts", "File"]);
+    for (index, version) in versions.iter().enumerate() {
+        table.row(vec![
+            index.to_string(),
+            version.name.to_owned(),
+            version.edition.clone().unwrap_or_default(),
+            output::resolution(version.quality.resolution),
+            version.quality.source.clone().unwrap_or_default(),
+            version.part_count().to_string(),
+            version.file_path.to_owned(),
+        ]);
+    }
+    Ok(table.print(format)?)
+}
+
+/// Lists the extras below the main table, which is all that plain output has.
+fn print_extras(format: Format, extras: &[Extra]) -> Result<(), Error> {
+    if extras.is_empty() || format != Format::Table {
+        return Ok(());
+    }
+    output::heading(format, "Extras")?;
+    let mut table = Table::new(vec!["Kind", "Title"]);
+    for extra in extras {
+        let kind = serde_json::to_value(extra.kind)?;
+        table.row(vec![kind.as_str().unwrap_or_default().to_owned(), extra.title.to_owned()]);
+    }
+    Ok(table.print(format)?)
+}
@@ -1,4 +1,4 @@
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 pub fn build_cli() -> App<'static, 'static> {
     let app =
@@ -6,6 +6,7 @@ pub fn build_cli() -> App<'static, 'static> {
             .version("0.1.0")
             .about("CLI client for Carolus")
             .author("Simon Dickson")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
             .arg(Arg::with_name("v")
                 .short("v")
                 .multiple(true)
@@ -13,7 +14,31 @@ pub fn build_cli() -> App<'static, 'static> {
             .arg(Arg::with_name("host")
                 .short("h")
                 .env("CAROLUS_SERVER_URL")
-                .help("The base URL of the Carolus Server"))
+                .help("The base URL of the Carolus Server (defaults to the profile's)"))
+            .arg(Arg::with_name("token")
+                .long("token")
+                .env("CAROLUS_TOKEN")
+                .takes_value(true)
+                .help("The token to give the server, if it needs one (defaults to the profile's)"))
+            .arg(Arg::with_name("profile")
+                .long("profile")
+                .short("p")
+                .env("CAROLUS_PROFILE")
+                .help("The profile in the config to use (defaults to the config's default)"))
+            .arg(Arg::with_name("config")
+                .long("config")
+                .env("CAROLUS_CONFIG")
+                .help("The config file of profiles (defaults to ~/.config/carolus/config.toml)"))
+            .subcommand(SubCommand::with_name("set-token")
+                .about("Keeps the server and the token a proxy in front of it asks for in the profile")
+                .arg(Arg::with_name("server")
+                    .long("server")
+                    .takes_value(true)
+                    .help("The base URL of the Carolus Server (defaults to the profile's)"))
+                .arg(Arg::with_name("token")
+                    .long("token")
+                    .takes_value(true)
+                    .help("The token, rather than asking for it")))
             .subcommand(SubCommand::with_name("completions")
                 .about("Generates shell completions")
                 .arg(Arg::with_name("shell")
@@ -22,13 +47,189 @@ pub fn build_cli() -> App<'static, 'static> {
                     .index(1)
                     .help("Shell type, supported: [zsh,bash,fish,powershell,elvish]")));
 
-    player_subcommand(app)
+    export_subcommands(download_subcommands(browse_subcommands(player_subcommand(parse_test_subcommand(app)))))
+        .subcommand(dir_arg(SubCommand::with_name("tui")
+            .about("Browses the library and plays from it in the terminal")
+            .arg(player_arg())))
+}
+
+/// Only built with the `parse-test` feature, as it takes the whole indexer.
+#[cfg(feature = "parse-test")]
+fn parse_test_subcommand(app: App<'static, 'static>) -> App<'static, 'static> {
+    app.subcommand(SubCommand::with_name("parse-test")
+        .about("Shows how a file would be indexed, without needing a server")
+        .arg(Arg::with_name("path")
+            .required(true)
+            .takes_value(true)
+            .index(1)
+            .help("Path of the video file"))
+        .arg(Arg::with_name("tv")
+            .long("tv")
+            .help("Parses the file as a tv episode instead of a movie"))
+        .arg(Arg::with_name("root")
+            .long("root")
+            .takes_value(true)
+            .help("The movie or tv directory the file is in (defaults to the folder above the movie or show)"))
+        .arg(Arg::with_name("patterns")
+            .long("patterns")
+            .env("CAROLUS_NAMING_PATTERNS")
+            .takes_value(true)
+            .help("JSON file of naming patterns, as given to the server")))
+}
+
+#[cfg(not(feature = "parse-test"))]
+fn parse_test_subcommand(app: App<'static, 'static>) -> App<'static, 'static> {
+    app
+}
+
+fn browse_subcommands(app: App<'static, 'static>) -> App<'static, 'static> {
+    app.subcommand(SubCommand::with_name("list")
+        .about("Lists the library")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(output_args(SubCommand::with_name("movies")
+            .about("Lists every movie")))
+        .subcommand(output_args(SubCommand::with_name("tv")
+            .about("Lists every tv show"))))
+    .subcommand(SubCommand::with_name("info")
+        .about("Shows what the library has of a movie or tv show")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(output_args(SubCommand::with_name("movie")
+            .about("Shows the versions and extras of a movie")
+            .arg(Arg::with_name("title")
+                .required(true)
+                .index(1)
+                .help("Title of the movie"))
+            .arg(Arg::with_name("year")
+                .short("y")
+                .takes_value(true)
+                .help("Year of the movie (only used when there are conflicts)"))))
+        .subcommand(output_args(SubCommand::with_name("tv")
+            .about("Shows the series of a tv show, the episodes of one series, or the versions of one episode")
+            .arg(Arg::with_name("title")
+                .required(true)
+                .index(1)
+                .help("Title of the tv show"))
+            .arg(Arg::with_name("series")
+                .short("s")
+                .takes_value(true)
+                .help("Series to list the episodes of"))
+            .arg(Arg::with_name("episode")
+                .short("e")
+                .takes_value(true)
+                .requires("series")
+                .help("Episode to list the versions of"))
+            .arg(Arg::with_name("year")
+                .short("y")
+                .takes_value(true)
+                .help("Year of the tv show (only used when there are conflicts)")))))
+    .subcommand(output_args(SubCommand::with_name("search")
+        .about("Finds movies and tv shows by title")
+        .arg(Arg::with_name("query")
+            .required(true)
+            .multiple(true)
+            .index(1)
+            .help("Words that are all in the title"))))
+}
+
+fn download_subcommands(app: App<'static, 'static>) -> App<'static, 'static> {
+    app.subcommand(SubCommand::with_name("download")
+        .about("Downloads videos to watch without the server")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(download_args(SubCommand::with_name("movie")
+            .about("Downloads a movie")
+            .arg(Arg::with_name("title")
+                .required(true)
+                .index(1)
+                .help("Title of the movie"))
+            .arg(Arg::with_name("year")
+                .short("y")
+                .takes_value(true)
+                .help("Year of the movie (only used when there are conflicts)"))
+            .arg(Arg::with_name("version")
+                .long("version")
+                .takes_value(true)
+                .help("Which version to download, counting from 0 (defaults to the best one)"))))
+        .subcommand(download_args(SubCommand::with_name("tv")
+            .about("Downloads the episodes of a tv show")
+            .arg(Arg::with_name("title")
+                .required(true)
+                .index(1)
+                .help("Title of the tv show"))
+            .arg(Arg::with_name("series")
+                .short("s")
+                .takes_value(true)
+                .help("Series to download (defaults to all of them)"))
+            .arg(Arg::with_name("year")
+                .short("y")
+                .takes_value(true)
+                .help("Year of the tv show (only used when there are conflicts)")))))
+    .subcommand(download_args(SubCommand::with_name("sync")
+        .about("Downloads what is on the watchlist, deletes what isn't and sends progress made offline")
+        .arg(Arg::with_name("watchlist")
+            .long("watchlist")
+            .takes_value(true)
+            .help("JSON file of the movies and tv shows to keep (defaults to watchlist.json in the download folder)"))
+        .arg(Arg::with_name("keep")
+            .long("keep")
+            .help("Keeps downloads that are no longer on the watchlist"))))
+}
+
+fn export_subcommands(app: App<'static, 'static>) -> App<'static, 'static> {
+    app.subcommand(SubCommand::with_name("export")
+        .about("Exports the library and how far it was watched, or has the server write .nfo files for Kodi")
+        .arg(Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["json", "csv", "nfo"])
+            .default_value("json")
+            .help("JSON that can be imported again, CSV for spreadsheets, or .nfo files written by the server next to the media"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("File to write the export to (defaults to printing it)")))
+    .subcommand(SubCommand::with_name("import")
+        .about("Restores how far everything was watched from a JSON export")
+        .arg(Arg::with_name("file")
+            .required(true)
+            .index(1)
+            .help("The JSON export, or - to read it from stdin")))
+}
+
+fn download_args(subcommand: App<'static, 'static>) -> App<'static, 'static> {
+    dir_arg(subcommand)
+        .arg(Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .default_value("4")
+            .help("How many pieces of a file to download at once"))
+}
+
+fn dir_arg(subcommand: App<'static, 'static>) -> App<'static, 'static> {
+    subcommand
+        .arg(Arg::with_name("dir")
+            .long("dir")
+            .env("CAROLUS_DOWNLOAD_DIR")
+            .help("Folder downloads are kept in (defaults to Carolus in the videos folder)"))
+}
+
+fn output_args(subcommand: App<'static, 'static>) -> App<'static, 'static> {
+    subcommand
+        .arg(Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["table", "plain", "json"])
+            .help("Prints aligned columns, tab separated columns without a header, or the server's JSON (defaults to the profile's, or table)"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Same as --format json"))
 }
 
 fn player_subcommand(app: App<'static, 'static>) -> App<'static, 'static> {
     app.subcommand(SubCommand::with_name("play")
         .about("Play a video in the player")
-        .subcommand(SubCommand::with_name("movie")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(play_args(SubCommand::with_name("movie")
             .about("plays a movie")
             .arg(Arg::with_name("title")
                 .short("t")
@@ -38,8 +239,8 @@ fn player_subcommand(app: App<'static, 'static>) -> App<'static, 'static> {
             .arg(Arg::with_name("year")
                 .short("y")
                 .takes_value(true)
-                .help("Year of movie to play (only used when there are conflicts)")))
-        .subcommand(SubCommand::with_name("tv")
+                .help("Year of movie to play (only used when there are conflicts)"))))
+        .subcommand(play_args(SubCommand::with_name("tv")
             .about("Plays a tv episode")
             .arg(Arg::with_name("title")
                 .short("t")
@@ -59,5 +260,25 @@ fn player_subcommand(app: App<'static, 'static>) -> App<'static, 'static> {
             .arg(Arg::with_name("year")
                 .short("y")
                 .takes_value(true)
-                .help("Year of movie to play (only used when there are conflicts)"))))
-}
\ No newline at end of file
+                .help("Year of movie to play (only used when there are conflicts)")))))
+}
+
+fn play_args(subcommand: App<'static, 'static>) -> App<'static, 'static> {
+    dir_arg(subcommand)
+        .arg(player_arg())
+        .arg(Arg::with_name("start")
+            .long("start")
+            .takes_value(true)
+            .help("Where to start, in seconds or hh:mm:ss (defaults to where it was left off)"))
+        .arg(Arg::with_name("from_start")
+            .long("from-start")
+            .conflicts_with("start")
+            .help("Starts from the beginning instead of where it was left off"))
+}
+
+fn player_arg() -> Arg<'static, 'static> {
+    Arg::with_name("player")
+        .long("player")
+        .env("CAROLUS_PLAYER")
+        .help("Player to use: mpv, vlc, ffplay or a command with {url}, {title}, {start} and {subtitle} in it (defaults to the profile's, or mpv)")
+}
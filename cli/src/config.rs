@@ -0,0 +1,154 @@
+//! Profiles for the servers the CLI talks to, kept in `~/.config/carolus/config.toml`:
+//!
+//! ```toml
+//! default = "production"
+//!
+//! [profiles.production]
+//! server = "https://carolus.example.com"
+//! token = "..."
+//! player = "mpv"
+//! format = "table"
+//!
+//! [profiles.staging]
+//! server = "http://staging:8000"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use failure::{Error, format_err};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::Server;
+use crate::error::CliError;
+
+/// The profile used when none is chosen and the config doesn't name one.
+const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Config {
+    /// The profile used when none is chosen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// What to use for a server, when it isn't given on the command line.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player: Option<String>,
+    /// `table`, `plain` or `json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl Config {
+    /// Where the config is kept, unless given with `--config`.
+    pub fn path(matches: &ArgMatches) -> PathBuf {
+        match matches.value_of("config") {
+            Some(path) => PathBuf::from(path),
+            None => dirs::config_dir().or_else(|| dirs::home_dir().map(|h| h.join(".config"))).unwrap_or_default().join("carolus").join("config.toml"),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| CliError::Usage { message: format!("could not read the config {:?}: {}", path, e) }.into()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format_err!("could not open the config {:?}: {}", path, e)),
+        }
+    }
+
+    /// Saves the config where only its owner can read it, as it has tokens in it.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let temp = path.with_extension("toml.tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&temp)?.write_all(toml::to_string(self)?.as_bytes())?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// The name of the profile to use: the one chosen, the config's default, or `default`.
+    pub fn profile_name<'a>(&'a self, chosen: Option<&'a str>) -> &'a str {
+        chosen.or_else(|| self.default.as_ref().map(String::as_str)).unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// The profile to use, which has to be in the config when it was asked for by name.
+    pub fn profile(&self, chosen: Option<&str>, path: &Path) -> Result<Profile, Error> {
+        let name = self.profile_name(chosen);
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone()),
+            None if chosen.is_none() && self.default.is_none() => Ok(Profile::default()),
+            None => Err(CliError::Usage { message: format!("there is no profile called '{}' in {:?}", name, path) }.into()),
+        }
+    }
+}
+
+/// Asks for the token to give the server, checks the server can be reached with it and keeps it
+/// in the profile. Carolus doesn't issue or check tokens itself, so this is all there is to it.
+pub fn handle_set_token(config: Config, path: &Path, matches: &ArgMatches, set_token: &ArgMatches) -> Result<(), Error> {
+    let name = config.profile_name(matches.value_of("profile")).to_owned();
+    let mut profile = config.profiles.get(&name).cloned().unwrap_or_default();
+    if let Some(host) = set_token.value_of("server").or_else(|| matches.value_of("host")) {
+        profile.server = Some(host.to_owned());
+    }
+    let token =
+        match set_token.value_of("token").or_else(|| matches.value_of("token")) {
+            Some(token) => token.to_owned(),
+            None => read_token(profile.server.as_ref().map_or("the server", String::as_str))?,
+        };
+
+    // This only fails on a bad token when a proxy in front of the server turns it down, but it also
+    // catches a server that can't be reached.
+    let server = Server::new(profile.server.as_ref().map(String::as_str), Some(&token))?;
+    server.get::<Value>("/api/progress", &[])?;
+
+    let host = profile.server.clone().unwrap_or_default();
+    profile.token = Some(token);
+    let mut config = config;
+    config.profiles.insert(name.to_owned(), profile);
+    if config.default.is_none() && name != DEFAULT_PROFILE {
+        config.default = Some(name.to_owned());
+    }
+    config.save(path)?;
+    println!("Kept the token for {} in the profile '{}', in {}", host, name, path.display());
+    Ok(())
+}
+
+fn read_token(server: &str) -> Result<String, Error> {
+    // Typed in, the token is kept off the screen; piped in, it is read as a line.
+    let token =
+        if atty::is(atty::Stream::Stdin) {
+            eprint!("Token for {}: ", server);
+            io::stderr().flush()?;
+            rpassword::read_password()?
+        } else {
+            let mut token = String::new();
+            io::stdin().lock().read_line(&mut token)?;
+            token
+        };
+    match token.trim() {
+        "" => Err(CliError::Usage { message: "no token was given".to_owned() }.into()),
+        token => Ok(token.to_owned()),
+    }
+}
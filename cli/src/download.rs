@@ -0,0 +1,628 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+use failure::{Error, bail, format_err};
+use log::{debug, info, warn};
+use reqwest::StatusCode;
+use serde_derive::{Deserialize, Serialize};
+
+use data::{DownloadFile, DownloadInfo, DownloadItem, WatchProgress};
+
+use crate::api::{Kind, Server, api_path};
+
+/// Name of the file in the download folder that records what is in it.
+const MANIFEST_NAME: &str = ".carolus-manifest.json";
+
+/// Name of the watchlist `sync` reads from the download folder, unless given another.
+const WATCHLIST_NAME: &str = "watchlist.json";
+
+/// Files are downloaded in pieces of this size, several at a time, so that an
+/// interrupted download only has to fetch the pieces it didn't finish.
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Times a piece is tried before the download is given up on.
+const CHUNK_ATTEMPTS: u64 = 3;
+
+/// What has been downloaded to a folder, so that downloads can be resumed and
+/// what was downloaded can be played without the server.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub items: Vec<ManifestItem>,
+    /// The pieces already downloaded of files that aren't finished, by path.
+    #[serde(default)]
+    partial: BTreeMap<String, Partial>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ManifestItem {
+    #[serde(flatten)]
+    pub item: DownloadItem,
+    /// How far it was watched, as far as this copy knows.
+    #[serde(default)]
+    pub progress: Option<WatchProgress>,
+    /// Whether `progress` is still to be sent to the server.
+    #[serde(default)]
+    pub progress_unsent: bool,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct Partial {
+    size: u64,
+    crc32: Option<String>,
+    chunk_size: u64,
+    done: BTreeSet<u64>,
+}
+
+impl Partial {
+    fn new(file: &DownloadFile) -> Self {
+        Partial { size: file.size, crc32: file.crc32.clone(), chunk_size: CHUNK_SIZE, done: BTreeSet::new() }
+    }
+}
+
+impl Manifest {
+    pub fn load(folder: &Path) -> Result<Self, Error> {
+        let path = folder.join(MANIFEST_NAME);
+        match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).map_err(|e| format_err!("could not read {:?}: {}", path, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the manifest, replacing the old one in one go so it is never left half written.
+    pub fn save(&self, folder: &Path) -> Result<(), Error> {
+        fs::create_dir_all(folder)?;
+        let temp = folder.join(format!("{}.tmp", MANIFEST_NAME));
+        serde_json::to_writer_pretty(File::create(&temp)?, self)?;
+        fs::rename(temp, folder.join(MANIFEST_NAME))?;
+        Ok(())
+    }
+
+    /// The downloaded copy of a movie, or of an episode when given its series and episode numbers.
+    pub fn position(&self, title: &str, year: Option<u16>, episode: Option<(u16, u16)>) -> Option<usize> {
+        self.items.iter().position(|i| {
+            i.item.title.eq_ignore_ascii_case(title)
+                && year.map_or(true, |y| i.item.year == Some(y))
+                && episode.map(|e| e.0) == i.item.series
+                && episode.map(|e| e.1) == i.item.episode
+        })
+    }
+
+    fn add(&mut self, item: DownloadItem) {
+        match self.items.iter_mut().find(|i| same_item(&i.item, &item)) {
+            Some(existing) => existing.item = item,
+            None => self.items.push(ManifestItem { item, progress: None, progress_unsent: false }),
+        }
+    }
+
+    /// Whether an unfinished download of a file can carry on, because the pieces recorded are
+    /// of the same file and what they were written to is still there.
+    fn resumes(&self, file: &DownloadFile, part_path: &Path) -> bool {
+        self.partial.get(&file.path)
+            .map_or(false, |p| Partial { done: BTreeSet::new(), ..p.clone() } == Partial::new(file))
+            && part_path.is_file()
+    }
+
+    /// Whether a file was downloaded before, and is still there.
+    fn has(&self, folder: &Path, file: &DownloadFile) -> bool {
+        let downloaded = self.items.iter()
+            .flat_map(|i| i.item.parts.iter().chain(&i.item.subtitles))
+            .any(|f| f == file);
+        downloaded
+            && !self.partial.contains_key(&file.path)
+            && local_path(folder, &file.path).ok().and_then(|p| fs::metadata(p).ok()).map(|m| m.len()) == Some(file.size)
+    }
+}
+
+fn same_item(a: &DownloadItem, b: &DownloadItem) -> bool {
+    (&a.title, a.year, a.series, a.episode) == (&b.title, b.year, b.series, b.episode)
+}
+
+/// What a downloaded movie or episode is called.
+pub fn display_title(item: &DownloadItem) -> String {
+    match (item.series, item.episode, item.year) {
+        (Some(series), Some(episode), _) => format!("{} S{:02}E{:02}", item.title, series, episode),
+        (_, _, Some(year)) => format!("{} ({})", item.title, year),
+        _ => item.title.to_owned(),
+    }
+}
+
+/// Where to keep a downloaded file, refusing paths that would end up outside the folder.
+pub fn local_path(folder: &Path, path: &str) -> Result<PathBuf, Error> {
+    let relative = Path::new(path);
+    let outside = relative.components().any(|c| match c {
+        Component::Normal(_) => false,
+        _ => true,
+    });
+    if outside || path.is_empty() {
+        bail!("the server asked for a file to be kept at {:?}, which is outside the download folder", path);
+    }
+    Ok(folder.join(relative))
+}
+
+/// The folder downloads are kept in.
+pub fn folder(matches: &ArgMatches) -> PathBuf {
+    match matches.value_of("dir") {
+        Some(dir) => PathBuf::from(dir),
+        None => dirs::video_dir().or_else(dirs::home_dir).unwrap_or_default().join("Carolus"),
+    }
+}
+
+/// The CRC-32 of a whole file, as the server gives it.
+fn checksum(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+enum Event {
+    Bytes(u64),
+    Done(u64),
+    Failed(Error),
+}
+
+struct Downloader<'a> {
+    server: &'a Server,
+    folder: &'a Path,
+    jobs: usize,
+}
+
+impl<'a> Downloader<'a> {
+    /// Downloads the files of a movie or episode that aren't there yet, and records them in the manifest.
+    fn item(&self, manifest: &mut Manifest, item: &DownloadItem) -> Result<usize, Error> {
+        let mut downloaded = 0;
+        for file in item.parts.iter().chain(&item.subtitles) {
+            if manifest.has(self.folder, file) {
+                debug!("Already downloaded {}", file.path);
+                continue;
+            }
+            self.file(manifest, file)?;
+            downloaded += 1;
+        }
+        manifest.add(item.clone());
+        manifest.save(self.folder)?;
+        Ok(downloaded)
+    }
+
+    fn file(&self, manifest: &mut Manifest, file: &DownloadFile) -> Result<(), Error> {
+        let destination = local_path(self.folder, &file.path)?;
+        let part_path = PathBuf::from(format!("{}.part", destination.display()));
+
+        if manifest.resumes(file, &part_path) {
+            info!("Resuming {}", file.path);
+        } else {
+            if let Some(parent) = part_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::create(&part_path)?.set_len(file.size)?;
+            manifest.partial.insert(file.path.to_owned(), Partial::new(file));
+            manifest.save(self.folder)?;
+        }
+
+        let done = manifest.partial[&file.path].done.clone();
+        let missing = (0..(file.size + CHUNK_SIZE - 1) / CHUNK_SIZE).filter(|c| !done.contains(c)).collect();
+        self.chunks(manifest, file, &part_path, missing)?;
+
+        match file.crc32 {
+            Some(ref expected) => {
+                let actual = checksum(&part_path)?;
+                if actual != *expected {
+                    manifest.partial.remove(&file.path);
+                    manifest.save(self.folder)?;
+                    fs::remove_file(&part_path)?;
+                    bail!("{} was damaged on the way (its checksum is {} rather than {}), download it again", file.path, actual, expected);
+                }
+            },
+            None => warn!("The server couldn't give a checksum for {}, so it wasn't checked", file.path),
+        }
+        fs::rename(&part_path, &destination)?;
+        manifest.partial.remove(&file.path);
+        Ok(())
+    }
+
+    /// Downloads pieces of a file, `jobs` at a time, recording each one finished in the manifest.
+    fn chunks(&self, manifest: &mut Manifest, file: &DownloadFile, part_path: &Path, chunks: Vec<u64>) -> Result<(), Error> {
+        let already = file.size.saturating_sub(chunks.len() as u64 * CHUNK_SIZE);
+        // Popped from the back, so the file is fetched from the start.
+        let queue = Arc::new(Mutex::new(chunks.into_iter().rev().collect::<Vec<_>>()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let workers = (0..self.jobs.max(1)).map(|_| {
+            let (server, queue, stop, events) = (self.server.clone(), queue.clone(), stop.clone(), sender.clone());
+            let (url, size, part_path) = (file.url.to_owned(), file.size, part_path.to_owned());
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    let chunk = match queue.lock().unwrap_or_else(PoisonError::into_inner).pop() {
+                        Some(chunk) => chunk,
+                        None => break,
+                    };
+                    let event = match fetch_chunk(&server, &url, size, &part_path, chunk, &events) {
+                        Ok(()) => Event::Done(chunk),
+                        Err(e) => Event::Failed(e),
+                    };
+                    let _ = events.send(event);
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(sender);
+
+        let mut meter = Meter::new(&file.path, file.size, already);
+        let mut failure = None;
+        for event in receiver {
+            match event {
+                Event::Bytes(bytes) => meter.add(bytes),
+                Event::Done(chunk) => {
+                    if let Some(partial) = manifest.partial.get_mut(&file.path) {
+                        partial.done.insert(chunk);
+                    }
+                    if let Err(e) = manifest.save(self.folder) {
+                        stop.store(true, Ordering::SeqCst);
+                        failure.get_or_insert(e);
+                    }
+                },
+                Event::Failed(e) => {
+                    stop.store(true, Ordering::SeqCst);
+                    failure.get_or_insert(e);
+                },
+            }
+        }
+        for worker in workers {
+            let _ = worker.join();
+        }
+        meter.finish();
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// Downloads one piece of a file, trying again a couple of times when it fails.
+fn fetch_chunk(server: &Server, url: &str, size: u64, part_path: &Path, chunk: u64, events: &Sender<Event>) -> Result<(), Error> {
+    let start = chunk * CHUNK_SIZE;
+    let end = (start + CHUNK_SIZE).min(size) - 1;
+    let mut attempt = 1;
+    loop {
+        match fetch_range(server, url, size, part_path, (start, end), events) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < CHUNK_ATTEMPTS => {
+                debug!("Trying bytes {}-{} of {} again: {}", start, end, url, e);
+                thread::sleep(Duration::from_secs(attempt));
+                attempt += 1;
+            },
+            Err(e) => return Err(format_err!("could not download bytes {}-{} of {}: {}", start, end, url, e)),
+        }
+    }
+}
+
+fn fetch_range(server: &Server, url: &str, size: u64, part_path: &Path, (start, end): (u64, u64), events: &Sender<Event>) -> Result<(), Error> {
+    let mut response = server.download(url, Some((start, end)))?;
+    // Servers that can't send part of a file send all of it, which is only any good when all of it was asked for.
+    if response.status() != StatusCode::PARTIAL_CONTENT && (start, end + 1) != (0, size) {
+        bail!("the server can't send part of a file ({})", response.status());
+    }
+
+    let mut file = OpenOptions::new().write(true).open(part_path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut remaining = end + 1 - start;
+    while remaining > 0 {
+        let wanted = remaining.min(buffer.len() as u64) as usize;
+        let read = response.read(&mut buffer[..wanted])?;
+        if read == 0 {
+            bail!("the connection was closed {} bytes early", remaining);
+        }
+        file.write_all(&buffer[..read])?;
+        remaining -= read as u64;
+        let _ = events.send(Event::Bytes(read as u64));
+    }
+    Ok(())
+}
+
+/// Shows how a download is going, on a line of its own when run in a terminal.
+struct Meter {
+    name: String,
+    size: u64,
+    done: u64,
+    started: Instant,
+    shown: Instant,
+    terminal: bool,
+}
+
+impl Meter {
+    fn new(name: &str, size: u64, done: u64) -> Self {
+        let now = Instant::now();
+        Meter { name: name.to_owned(), size, done, started: now, shown: now, terminal: atty::is(atty::Stream::Stderr) }
+    }
+
+    fn add(&mut self, bytes: u64) {
+        self.done = (self.done + bytes).min(self.size);
+        if self.terminal && self.shown.elapsed() >= Duration::from_millis(200) {
+            self.show();
+            self.shown = Instant::now();
+        }
+    }
+
+    fn show(&self) {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+        let percent = if self.size == 0 { 100 } else { self.done * 100 / self.size };
+        eprint!("\r{} {:>3}% {:.1}/{:.1} MiB {:.1} MiB/s ", self.name, percent, mib(self.done), mib(self.size), mib(self.done) / seconds.max(0.001));
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        if self.terminal {
+            self.show();
+            eprintln!();
+        } else {
+            info!("Downloaded {}", self.name);
+        }
+    }
+}
+
+fn downloader<'a>(server: &'a Server, folder: &'a Path, matches: &ArgMatches) -> Result<Downloader<'a>, Error> {
+    let jobs = matches.value_of("jobs").unwrap().parse::<usize>()
+        .map_err(|_| format_err!("--jobs needs to be a number"))?;
+    Ok(Downloader { server, folder, jobs })
+}
+
+pub fn handle_download(server: &Server, matches: &ArgMatches) -> Result<(), Error> {
+    let (kind, matches) =
+        match matches.subcommand() {
+            ("movie", Some(matches)) => (Kind::Movie, matches),
+            ("tv", Some(matches)) => (Kind::TvShow, matches),
+            (command, _) => bail!("unhandled command: download {}", command),
+        };
+    let title = matches.value_of("title").unwrap();
+    let path =
+        match kind {
+            Kind::Movie => api_path(&["api", "movies", "download", title]),
+            Kind::TvShow => api_path(&["api", "tv", "download", title]),
+        };
+    let year = server.resolve_year(kind, title, crate::number_arg(matches, "year")?)?.map(|y| y.to_string());
+    let series = crate::number_arg(matches, "series")?.map(|s| s.to_string());
+    let query = [("year", year), ("version", matches.value_of("version").map(str::to_owned)), ("series", series)];
+    let query = query.iter().filter_map(|(name, value)| Some((*name, value.as_ref()?.as_str()))).collect::<Vec<_>>();
+
+    let folder = folder(matches);
+    let downloader = downloader(server, &folder, matches)?;
+    let info: DownloadInfo = server.get_slow(&path, &query)?;
+    let mut manifest = Manifest::load(&folder)?;
+    for item in &info.items {
+        let downloaded = downloader.item(&mut manifest, item)?;
+        if downloaded == 0 {
+            println!("{} was already downloaded", display_title(item));
+        }
+    }
+    println!("Downloaded to {}", folder.display());
+    Ok(())
+}
+
+/// What `sync` keeps downloaded.
+#[derive(Deserialize)]
+struct Watchlist {
+    #[serde(default)]
+    movies: Vec<WatchlistMovie>,
+    #[serde(default)]
+    tv: Vec<WatchlistTvShow>,
+}
+
+#[derive(Deserialize)]
+struct WatchlistMovie {
+    title: String,
+    year: Option<u16>,
+    version: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct WatchlistTvShow {
+    title: String,
+    year: Option<u16>,
+    /// Every series when empty.
+    #[serde(default)]
+    series: Vec<u16>,
+}
+
+impl Watchlist {
+    fn load(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|e| format_err!("could not open the watchlist {:?}: {}", path, e))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| format_err!("could not read the watchlist {:?}: {}", path, e))
+    }
+
+    /// The requests to the download API that cover everything on the list.
+    fn requests(&self) -> Vec<(String, Vec<(&'static str, String)>)> {
+        let mut requests = vec![];
+        for movie in &self.movies {
+            let query = vec![("year", movie.year.map(|y| y.to_string())), ("version", movie.version.map(|v| v.to_string()))];
+            requests.push((api_path(&["api", "movies", "download", &movie.title]), query));
+        }
+        for tv_show in &self.tv {
+            let path = api_path(&["api", "tv", "download", &tv_show.title]);
+            let year = ("year", tv_show.year.map(|y| y.to_string()));
+            if tv_show.series.is_empty() {
+                requests.push((path.to_owned(), vec![year.clone()]));
+            }
+            for series in &tv_show.series {
+                requests.push((path.to_owned(), vec![year.clone(), ("series", Some(series.to_string()))]));
+            }
+        }
+        requests.into_iter()
+            .map(|(path, query)| (path, query.into_iter().filter_map(|(name, value)| Some((name, value?))).collect()))
+            .collect()
+    }
+}
+
+/// Sends the progress made watching downloaded copies while the server couldn't be reached.
+fn send_progress(server: &Server, manifest: &mut Manifest) -> Result<usize, Error> {
+    let mut sent = 0;
+    for item in manifest.items.iter_mut().filter(|i| i.progress_unsent) {
+        if let Some(ref progress) = item.progress {
+            server.post(&item.item.progress_url, progress)
+                .map_err(|e| format_err!("could not send how far {} was watched: {}", display_title(&item.item), e))?;
+            sent += 1;
+        }
+        item.progress_unsent = false;
+    }
+    Ok(sent)
+}
+
+/// Deletes the files of a downloaded movie or episode, and the folders they leave empty.
+fn remove_item(folder: &Path, item: &DownloadItem) -> Result<(), Error> {
+    for file in item.parts.iter().chain(&item.subtitles) {
+        let path = local_path(folder, &file.path)?;
+        match fs::remove_file(&path) {
+            Err(ref e) if e.kind() != io::ErrorKind::NotFound => return Err(format_err!("could not delete {:?}: {}", path, e)),
+            _ => (),
+        }
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|d| *d != folder) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_sync(server: &Server, matches: &ArgMatches) -> Result<(), Error> {
+    let folder = folder(matches);
+    let downloader = downloader(server, &folder, matches)?;
+    let watchlist = Watchlist::load(&matches.value_of("watchlist").map_or_else(|| folder.join(WATCHLIST_NAME), PathBuf::from))?;
+    let mut manifest = Manifest::load(&folder)?;
+
+    let sent = send_progress(server, &mut manifest);
+    manifest.save(&folder)?;
+    let sent = sent?;
+
+    let mut wanted = vec![];
+    let mut downloaded = 0;
+    for (path, query) in watchlist.requests() {
+        let query = query.iter().map(|(name, value)| (*name, value.as_str())).collect::<Vec<_>>();
+        let info: DownloadInfo = server.get_slow(&path, &query)?;
+        for item in info.items {
+            downloaded += downloader.item(&mut manifest, &item)?;
+            wanted.push(item);
+        }
+    }
+
+    let mut removed = 0;
+    if !matches.is_present("keep") {
+        let (kept, unwanted): (Vec<_>, Vec<_>) = manifest.items.drain(..).partition(|i| wanted.iter().any(|w| same_item(w, &i.item)));
+        manifest.items = kept;
+        for item in unwanted {
+            remove_item(&folder, &item.item)?;
+            info!("Removed {}", display_title(&item.item));
+            removed += 1;
+        }
+        manifest.save(&folder)?;
+    }
+
+    println!("{} files downloaded, {} removed, {} watched progress sent", downloaded, removed, sent);
+    Ok(())
+}
+
+#[cfg(test)]
+fn test_file(path: &str, size: u64) -> DownloadFile {
+    DownloadFile { url: format!("/stream/{}", path), path: path.to_owned(), size, crc32: Some("0badcafe".to_owned()) }
+}
+
+#[cfg(test)]
+fn test_folder(test: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("carolus-download-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+#[test]
+fn local_paths() {
+    let folder = Path::new("/downloads");
+    assert_eq!(PathBuf::from("/downloads/Movies/Alien (1979)/Alien (1979).mp4"), local_path(folder, "Movies/Alien (1979)/Alien (1979).mp4").unwrap());
+
+    for path in &["", "../outside.mp4", "Movies/../../outside.mp4", "/etc/passwd", "./Alien.mp4"] {
+        assert!(local_path(folder, path).is_err(), "{:?}", path);
+    }
+}
+
+#[test]
+fn resumes() {
+    let folder = test_folder("resumes");
+    let file = test_file("Alien (1979).mp4", 3 * CHUNK_SIZE);
+    let part_path = folder.join("Alien (1979).mp4.part");
+
+    let mut manifest = Manifest::default();
+    let mut partial = Partial::new(&file);
+    partial.done.insert(1);
+    manifest.partial.insert(file.path.to_owned(), partial);
+    manifest.save(&folder).unwrap();
+    let manifest = Manifest::load(&folder).unwrap();
+    assert!(!manifest.resumes(&file, &part_path), "nothing to carry on writing to");
+
+    File::create(&part_path).unwrap();
+    assert!(manifest.resumes(&file, &part_path));
+    assert_eq!(vec![1], manifest.partial[&file.path].done.iter().cloned().collect::<Vec<_>>());
+    assert!(!manifest.resumes(&test_file("Alien (1979).mp4", 2 * CHUNK_SIZE), &part_path), "the file changed on the server");
+    assert!(!manifest.resumes(&DownloadFile { crc32: None, ..file.clone() }, &part_path), "the file changed on the server");
+    assert!(!manifest.resumes(&test_file("Aliens (1986).mp4", 3 * CHUNK_SIZE), &part_path));
+
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn downloaded_files() {
+    let folder = test_folder("downloaded");
+    let file = test_file("Alien (1979).mp4", 5);
+    let mut manifest = Manifest::default();
+    manifest.add(DownloadItem {
+        title: "Alien".to_owned(), year: Some(1979), series: None, episode: None,
+        parts: vec![file.clone()], subtitles: vec![], progress_url: "/api/progress".to_owned(),
+    });
+    assert!(!manifest.has(&folder, &file), "not on disk");
+
+    fs::write(folder.join(&file.path), b"12345").unwrap();
+    assert!(manifest.has(&folder, &file));
+    assert!(!manifest.has(&folder, &DownloadFile { size: 6, ..file.clone() }), "a different file on the server");
+
+    manifest.partial.insert(file.path.to_owned(), Partial::new(&file));
+    assert!(!manifest.has(&folder, &file), "being downloaded again");
+
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn watchlists() {
+    let watchlist: Watchlist = serde_json::from_str(r#"{
+        "movies": [{ "title": "Alien", "year": 1979 }, { "title": "Blade Runner", "version": 1 }],
+        "tv": [{ "title": "Jonathan Creek" }, { "title": "Doctor Who", "year": 2005, "series": [1, 2] }]
+    }"#).unwrap();
+
+    let query = |pairs: &[(&'static str, &str)]| pairs.iter().map(|&(name, value)| (name, value.to_owned())).collect::<Vec<_>>();
+    assert_eq!(vec![
+        (api_path(&["api", "movies", "download", "Alien"]), query(&[("year", "1979")])),
+        (api_path(&["api", "movies", "download", "Blade Runner"]), query(&[("version", "1")])),
+        (api_path(&["api", "tv", "download", "Jonathan Creek"]), query(&[])),
+        (api_path(&["api", "tv", "download", "Doctor Who"]), query(&[("year", "2005"), ("series", "1")])),
+        (api_path(&["api", "tv", "download", "Doctor Who"]), query(&[("year", "2005"), ("series", "2")])),
+    ], watchlist.requests());
+
+    let empty: Watchlist = serde_json::from_str("{}").unwrap();
+    assert!(empty.requests().is_empty());
+    assert!(serde_json::from_str::<Watchlist>(r#"{ "movies": [{ "year": 1979 }] }"#).is_err(), "a movie needs a title");
+}
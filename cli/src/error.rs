@@ -0,0 +1,33 @@
+use failure::Fail;
+
+/// What went wrong talking to the server, for the errors that get their own exit code.
+#[derive(Fail, Debug)]
+pub enum CliError {
+    #[fail(display = "{}", message)]
+    Usage { message: String },
+
+    #[fail(display = "could not reach the server at {}: {}", url, cause)]
+    Connection { url: String, cause: String },
+
+    #[fail(display = "{}", message)]
+    NotFound { message: String },
+
+    #[fail(display = "there is more than one '{}', give the year with -y: {}", title, years)]
+    Ambiguous { title: String, years: String },
+
+    #[fail(display = "{}", message)]
+    Server { status: u16, message: String },
+}
+
+impl CliError {
+    /// The code the CLI exits with. Other errors exit with 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage { .. } => 2,
+            CliError::Connection { .. } => 3,
+            CliError::NotFound { .. } => 4,
+            CliError::Ambiguous { .. } => 5,
+            CliError::Server { .. } => 6,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+use clap::ArgMatches;
+use failure::Error;
+use log::warn;
+
+use data::{ImportReport, LibraryExport};
+
+use crate::api::Server;
+use crate::error::CliError;
+
+/// Writes the library out as JSON or CSV, or has the server write .nfo files next to the media.
+pub fn handle_export(server: &Server, matches: &ArgMatches) -> Result<(), Error> {
+    let format = matches.value_of("format").unwrap();
+    if format == "nfo" {
+        server.post("/api/jobs/nfo", &())?;
+        println!("The server is writing the .nfo files, see /admin/jobs for how it is getting on");
+        return Ok(());
+    }
+
+    let mut export = server.download(&format!("/api/export?format={}", format), None)?;
+    match matches.value_of("output") {
+        Some(path) => {
+            let mut file = File::create(path)?;
+            export.copy_to(&mut file)?;
+        },
+        None => {
+            export.copy_to(&mut io::stdout().lock())?;
+        },
+    }
+    Ok(())
+}
+
+/// Restores how far everything was watched from a JSON export, even for files that have moved.
+pub fn handle_import(server: &Server, matches: &ArgMatches) -> Result<(), Error> {
+    let path = matches.value_of("file").unwrap();
+    let reader: Box<dyn Read> =
+        match path {
+            "-" => Box::new(io::stdin()),
+            path => Box::new(BufReader::new(File::open(path)?)),
+        };
+    let library: LibraryExport = serde_json::from_reader(reader)
+        .map_err(|e| CliError::Usage { message: format!("'{}' is not a JSON export: {}", path, e) })?;
+
+    let report: ImportReport = server.post_slow("/api/import", &library)?;
+    for file_path in &report.unmatched {
+        warn!("{} is no longer in the library", file_path);
+    }
+    for guess in &report.guessed {
+        warn!("{} is no longer in the library, so how far it was watched went to {}", guess.from, guess.to);
+    }
+    println!("Restored how far {} files were watched, and guessed where {} more went", report.restored, report.guessed.len());
+    Ok(())
+}
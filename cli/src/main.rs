@@ -1,15 +1,49 @@
 use std::str::FromStr;
 use std::io;
+use std::path::Path;
+use std::process;
 
-use clap::{ArgMatches, Shell};
-use log::{LevelFilter, error};
+use clap::{ArgMatches, ErrorKind, Shell};
+use failure::{Error, bail, format_err};
+use log::{LevelFilter, debug, error, info, warn};
 use simplelog::TermLogger;
 
+use data::{DownloadFile, PlayInfo, WatchProgress};
+#[cfg(feature = "parse-test")]
+use data::MediaVersion;
+#[cfg(feature = "parse-test")]
+use index::{Interpretation, NamingPatterns};
+
+use crate::api::{Kind, Server, api_path};
+use crate::config::{Config, Profile};
+use crate::download::Manifest;
+use crate::error::CliError;
+use crate::player::{Player, Playback};
+
+mod api;
+mod browse;
 mod cli;
+mod config;
+mod download;
+mod error;
+mod export;
+#[cfg(unix)]
+mod mpv;
+mod output;
+mod player;
 mod the_movie_db;
+mod tui;
 
 fn main() {
-    let matches = cli::build_cli().get_matches();
+    let matches =
+        match cli::build_cli().get_matches_safe() {
+            Ok(matches) => matches,
+            Err(ref e) if e.kind == ErrorKind::HelpDisplayed || e.kind == ErrorKind::VersionDisplayed => e.exit(),
+            Err(e) => {
+                eprintln!("{}", e.message);
+                process::exit(CliError::Usage { message: e.message }.exit_code());
+            },
+        };
 
     match matches.subcommand() {
         ("completions", Some(matches)) => {
@@ -22,47 +56,44 @@ fn main() {
 
     init_logging(matches.occurrences_of("v"));
 
-    let host = matches.value_of("host").unwrap();
+    let result =
+        match matches.subcommand() {
+            #[cfg(feature = "parse-test")]
+            ("parse-test", Some(matches)) => handle_parse_test(matches),
+            _ => handle_server_command(&matches),
+        };
+    if let Err(e) = result {
+        error!("{}", e);
+        process::exit(e.downcast_ref::<CliError>().map_or(1, CliError::exit_code));
+    }
+}
+
+/// Runs a command that talks to the server, which is taken from the profile unless given.
+fn handle_server_command(matches: &ArgMatches) -> Result<(), Error> {
+    let config_path = Config::path(matches);
+    let config = Config::load(&config_path)?;
+    if let ("set-token", Some(set_token)) = matches.subcommand() {
+        return config::handle_set_token(config, &config_path, matches, set_token);
+    }
 
+    let profile = config.profile(matches.value_of("profile"), &config_path)?;
+    let host = matches.value_of("host").or_else(|| profile.server.as_ref().map(String::as_str));
+    let token = matches.value_of("token").or_else(|| profile.token.as_ref().map(String::as_str));
+    let server = Server::new(host, token)?;
     match matches.subcommand() {
-        ("play", Some(matches)) => {
-            handle_play(host, matches);
-        }
-        (command, _) => error!("unhandled command: {}", command),
+        ("play", Some(matches)) => handle_play(&server, &profile, matches),
+        ("list", Some(matches)) => browse::handle_list(&server, &profile, matches),
+        ("info", Some(matches)) => browse::handle_info(&server, &profile, matches),
+        ("search", Some(matches)) => browse::handle_search(&server, &profile, matches),
+        ("download", Some(matches)) => download::handle_download(&server, matches),
+        ("sync", Some(matches)) => download::handle_sync(&server, matches),
+        ("export", Some(matches)) => export::handle_export(&server, matches),
+        ("import", Some(matches)) => export::handle_import(&server, matches),
+        ("tui", Some(matches)) => tui::handle_tui(&server, &profile, matches),
+        (command, _) => Err(format_err!("unhandled command: {}", command)),
     }
 }
 
-//fn start_player(uri: &str) {
-//    gst::init().unwrap();
-//
-//    let pipeline = gst::parse_launch(&format!("playbin uri={}", uri)).unwrap();
-//
-//    let ret = pipeline.set_state(gst::State::Playing);
-//    assert_ne!(ret, gst::StateChangeReturn::Failure);
-//
-//    let bus = pipeline.get_bus().unwrap();
-//    while let Some(msg) = bus.timed_pop(gst::CLOCK_TIME_NONE) {
-//        use gst::MessageView;
-//
-//        match msg.view() {
-//            MessageView::Eos(..) => break,
-//            MessageView::Error(err) => {
-//                println!(
-//                    "Error from {:?}: {} ({:?})",
-//                    msg.get_src().map(|s| s.get_path_string()),
-//                    err.get_error(),
-//                    err.get_debug()
-//                );
-//                break;
-//            }
-//            _ => (),
-//        }
-//    }
-//
-//    let ret = pipeline.set_state(gst::State::Null);
-//    assert_ne!(ret, gst::StateChangeReturn::Failure);
-//}
-
 fn init_logging(level: u64) {
     let log_filter =
         match level {
@@ -75,23 +106,192 @@ fn init_logging(level: u64) {
     TermLogger::init(log_filter, Default::default()).unwrap();
 }
 
-fn handle_play(host: &str, matches: &ArgMatches) {
-    let uri =
+/// A movie or episode to play, and where to start it.
+pub struct PlayRequest {
+    pub title: String,
+    pub year: Option<u16>,
+    /// Series and episode numbers, for an episode.
+    pub episode: Option<(u16, u16)>,
+    /// Seconds to start at, rather than where it was left off.
+    pub start: Option<f64>,
+    pub from_start: bool,
+}
+
+fn handle_play(server: &Server, profile: &Profile, matches: &ArgMatches) -> Result<(), Error> {
+    let (matches, episode) =
         match matches.subcommand() {
-            ("movie", Some(matches)) => {
-                let year = matches.value_of("year").map_or("".to_owned(), |y|format!("?year={}", y));
-                format!("{}/api/movies/play/{}{}", host, escape_string(matches.value_of("title").unwrap()), year)
-            },
-            ("tv", Some(matches)) => {
-                let year = matches.value_of("year").map_or("".to_owned(), |y|format!("?year={}", y));
-                format!("{}/api/tv/play/{}/{}/{}{}", host, escape_string(matches.value_of("title").unwrap()), matches.value_of("series").unwrap(), matches.value_of("episode").unwrap(), year)
-            },
-            (command, _) => panic!("unhandled command: {}", command),
+            ("movie", Some(matches)) => (matches, None),
+            ("tv", Some(matches)) => (matches, Some((number_arg(matches, "series")?.unwrap(), number_arg(matches, "episode")?.unwrap()))),
+            (command, _) => bail!("unhandled command: {}", command),
+        };
+    let request = PlayRequest {
+        title: matches.value_of("title").unwrap().to_owned(),
+        year: number_arg(matches, "year")?,
+        episode,
+        start: match matches.value_of("start") {
+            Some(start) => Some(player::parse_time(start)?),
+            None => None,
+        },
+        from_start: matches.is_present("from_start"),
+    };
+    let player = Player::from_matches(matches, profile)?;
+    play(server, &player, &download::folder(matches), &request)
+}
+
+/// A number given on the command line, such as a year, series or episode.
+fn number_arg(matches: &ArgMatches, name: &str) -> Result<Option<u16>, Error> {
+    match matches.value_of(name) {
+        Some(value) => value.parse().map(Some)
+            .map_err(|_| CliError::Usage { message: format!("the {} needs to be a number, not '{}'", name, value) }.into()),
+        None => Ok(None),
+    }
+}
+
+/// Plays a movie or episode, from the download folder when it was downloaded and otherwise from the server.
+fn play(server: &Server, player: &Player, folder: &Path, request: &PlayRequest) -> Result<(), Error> {
+    let path =
+        match request.episode {
+            Some((series, episode)) => api_path(&["api", "tv", "play", &request.title, &series.to_string(), &episode.to_string()]),
+            None => api_path(&["api", "movies", "play", &request.title]),
         };
+    let year = request.year.map(|y| y.to_string());
+    let year = year.as_ref().map(|year| ("year", year.as_str())).into_iter().collect::<Vec<_>>();
+
+    let mut manifest = Manifest::load(folder)?;
+    if let Some(position) = manifest.position(&request.title, request.year, request.episode) {
+        return play_downloaded(server, request, player, folder, &mut manifest, position, &path, &year);
+    }
+
+    let kind = if request.episode.is_some() { Kind::TvShow } else { Kind::Movie };
+    let year = server.resolve_year(kind, &request.title, request.year)?.map(|y| y.to_string());
+    let year = year.as_ref().map(|year| ("year", year.as_str())).into_iter().collect::<Vec<_>>();
+
+    let info: PlayInfo = server.get(&path, &year)?;
+    let (first_part, start) = start_point(request, info.progress.clone());
+    let playback = Playback {
+        title: info.title.to_owned(),
+        streams: info.streams.iter().skip(first_part).map(|s| server.player_url(s)).collect::<Result<_, _>>()?,
+        first_part,
+        subtitles: info.subtitles.iter().map(|s| server.player_url(&s.url)).collect::<Result<_, _>>()?,
+        start,
+    };
+
+    player::play(player, &playback, &mut |progress| {
+        if let Err(e) = server.post(&info.progress_url, progress) {
+            warn!("Could not save how far {} got: {}", info.title, e);
+        }
+    })
+}
+
+/// Plays the downloaded copy of a movie or episode, keeping track of how far it got
+/// in the manifest for when the server can't be reached.
+fn play_downloaded(server: &Server, request: &PlayRequest, player: &Player, folder: &Path, manifest: &mut Manifest, position: usize, path: &str, year: &[(&str, &str)]) -> Result<(), Error> {
+    let item = manifest.items[position].clone();
+    info!("Playing the copy downloaded to {}", folder.display());
+    // Progress made offline is newer than the server's, until it has been sent.
+    let progress =
+        if item.progress_unsent {
+            item.progress.clone()
+        } else {
+            match server.get::<PlayInfo>(path, year) {
+                Ok(info) => info.progress,
+                Err(e) => {
+                    debug!("Could not ask the server how far it got: {}", e);
+                    item.progress.clone()
+                },
+            }
+        };
+    let (first_part, start) = start_point(request, progress);
+    let local_files = |files: &[DownloadFile]| files.iter()
+        .map(|f| download::local_path(folder, &f.path).map(|p| p.display().to_string()))
+        .collect::<Result<Vec<_>, _>>();
+    let playback = Playback {
+        title: download::display_title(&item.item),
+        streams: local_files(&item.item.parts)?.into_iter().skip(first_part).collect(),
+        first_part,
+        subtitles: local_files(&item.item.subtitles)?,
+        start,
+    };
+
+    let parts = item.item.parts.len();
+    player::play(player, &playback, &mut |progress| {
+        let mut progress = progress.clone();
+        progress.watched = progress.finished(parts);
+        let sent = server.post(&item.item.progress_url, &progress);
+        if let Err(ref e) = sent {
+            info!("Could not save how far {} got, it will be sent by sync: {}", playback.title, e);
+        }
+        let saved = &mut manifest.items[position];
+        saved.progress = Some(progress);
+        saved.progress_unsent = sent.is_err();
+        if let Err(e) = manifest.save(folder) {
+            warn!("Could not save how far {} got: {}", playback.title, e);
+        }
+    })
+}
+
+/// The part and time to start at, as asked for or else where it was left off.
+fn start_point(request: &PlayRequest, progress: Option<WatchProgress>) -> (usize, Option<f64>) {
+    let progress = progress.filter(|p| !p.watched && !request.from_start);
+    match (request.start, progress) {
+        (Some(start), _) => (0, Some(start)),
+        (None, Some(progress)) => (progress.part, Some(progress.position)),
+        (None, None) => (0, None),
+    }
+}
+
+#[cfg(feature = "parse-test")]
+fn handle_parse_test(matches: &ArgMatches) -> Result<(), Error> {
+    let naming = match matches.value_of("patterns") {
+        Some(patterns) => NamingPatterns::load(Path::new(patterns))?,
+        None => NamingPatterns::default(),
+    };
+    let path = Path::new(matches.value_of("path").unwrap());
+    let root = matches.value_of("root").map(Path::new);
+
+    let interpretation =
+        if matches.is_present("tv") {
+            index::interpret_episode(root, path, &naming)?
+        } else {
+            index::interpret_movie(root, path, &naming)?
+        };
+
+    match interpretation {
+        Interpretation::Movie { title, year, part, version, pattern } => {
+            println!("Movie:   {}", title);
+            println!("Year:    {}", year.map_or("-".to_owned(), |y| y.to_string()));
+            if let Some(part) = part {
+                println!("Part:    {}", part);
+            }
+            print_version(&version);
+            print_pattern(pattern);
+        },
+        Interpretation::Episode { show, year, season, episodes, conflicting_season, version, pattern } => {
+            println!("Show:    {}", show);
+            println!("Year:    {}", year.map_or("-".to_owned(), |y| y.to_string()));
+            println!("Series:  {}", season);
+            println!("Episode: {}", episodes.iter().map(u16::to_string).collect::<Vec<_>>().join(", "));
+            if let Some(folder_season) = conflicting_season {
+                println!("Warning: the file is in the folder for series {}", folder_season);
+            }
+            print_version(&version);
+            print_pattern(pattern);
+        },
+        Interpretation::Extra(extra) => {
+            println!("Extra:   {}", extra.title);
+            println!("Kind:    {:?}", extra.kind);
+        },
+    }
+    Ok(())
+}
 
-    //start_player(&uri);
+#[cfg(feature = "parse-test")]
+fn print_version(version: &MediaVersion) {
+    println!("Edition: {}", version.edition.as_ref().map_or("-", String::as_str));
+    println!("Version: {}", version.name);
 }
 
-fn escape_string(s: &str) -> String {
-    s.replace(" ", "%20")
+#[cfg(feature = "parse-test")]
+fn print_pattern(pattern: Option<String>) {
+    println!("Pattern: {}", pattern.unwrap_or_else(|| "built-in".to_owned()));
 }
@@ -0,0 +1,111 @@
+//! Asks a running mpv how far it has got over its [JSON IPC](https://mpv.io/manual/stable/#json-ipc).
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::{Error, bail, format_err};
+use log::{debug, trace};
+use serde_json::{Value, json};
+
+use data::WatchProgress;
+
+/// How often mpv is asked where it is.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often progress is reported back while playing.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    request_id: u64,
+}
+
+impl Connection {
+    fn open(socket: &Path) -> Result<Self, Error> {
+        let writer = UnixStream::connect(socket)?;
+        writer.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Connection { reader: BufReader::new(writer.try_clone()?), writer, request_id: 0 })
+    }
+
+    /// The value of a property, `None` while mpv doesn't have one, such as the duration before a file is loaded.
+    fn property(&mut self, name: &str) -> Result<Option<Value>, Error> {
+        self.request_id += 1;
+        let request = json!({ "command": ["get_property", name], "request_id": self.request_id });
+        writeln!(self.writer, "{}", request)?;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("mpv closed the connection");
+            }
+            let reply = serde_json::from_str::<Value>(&line)?;
+            // Events are sent on the same connection, in between replies.
+            if reply["request_id"] != json!(self.request_id) {
+                trace!("mpv: {}", line.trim());
+                continue;
+            }
+            return Ok(Some(reply["data"].clone()).filter(|_| reply["error"] == "success"));
+        }
+    }
+
+    fn progress(&mut self, first_part: usize) -> Result<Option<WatchProgress>, Error> {
+        let position = match self.property("playback-time")?.and_then(|p| p.as_f64()) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let duration = self.property("duration")?.and_then(|d| d.as_f64());
+        let part = self.property("playlist-pos")?.and_then(|p| p.as_u64()).unwrap_or(0) as usize;
+        Ok(Some(WatchProgress { part: first_part + part, position, duration, watched: false }))
+    }
+}
+
+/// Runs mpv, which was told to listen on `socket`, until it is closed.
+pub fn play(mut command: Command, socket: &Path, first_part: usize, report: &mut dyn FnMut(&WatchProgress)) -> Result<(), Error> {
+    let mut child = command.spawn()?;
+    let mut connection = None;
+    let mut progress = None;
+    let mut reported = Instant::now();
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        thread::sleep(POLL_INTERVAL);
+
+        if connection.is_none() {
+            // mpv takes a moment to start listening.
+            connection = Connection::open(socket).ok();
+        }
+        if let Some(mut open) = connection.take() {
+            match open.progress(first_part) {
+                Ok(latest) => {
+                    progress = latest.or(progress);
+                    connection = Some(open);
+                },
+                Err(e) => debug!("Lost the connection to mpv: {}", e),
+            }
+        }
+        if reported.elapsed() >= REPORT_INTERVAL {
+            if let Some(ref progress) = progress {
+                report(progress);
+            }
+            reported = Instant::now();
+        }
+    };
+    let _ = fs::remove_file(socket);
+
+    if let Some(ref progress) = progress {
+        report(progress);
+    }
+    if !status.success() {
+        return Err(format_err!("mpv stopped with {}", status));
+    }
+    Ok(())
+}
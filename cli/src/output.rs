@@ -0,0 +1,101 @@
+use std::io::{self, Write};
+
+use clap::ArgMatches;
+use failure::Error;
+
+use crate::config::Profile;
+use crate::error::CliError;
+
+/// How the browsing commands print what they find.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Aligned columns under a header, for people.
+    Table,
+    /// Tab separated columns without a header, for scripts.
+    Plain,
+    /// The server's JSON as it is.
+    Json,
+}
+
+impl Format {
+    /// The format given on the command line, or else the profile's, or else a table.
+    pub fn from_matches(matches: &ArgMatches, profile: &Profile) -> Result<Self, Error> {
+        if matches.is_present("json") {
+            return Ok(Format::Json);
+        }
+        match matches.value_of("format").or_else(|| profile.format.as_ref().map(String::as_str)) {
+            None | Some("table") => Ok(Format::Table),
+            Some("plain") => Ok(Format::Plain),
+            Some("json") => Ok(Format::Json),
+            Some(format) => Err(CliError::Usage { message: format!("the profile's format '{}' isn't one of table, plain or json", format) }.into()),
+        }
+    }
+}
+
+/// Rows of text printed as a table or tab separated lines.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Table { headers, rows: vec![] }
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    pub fn print(&self, format: Format) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        if format != Format::Table {
+            for row in &self.rows {
+                writeln!(out, "{}", row.join("\t"))?;
+            }
+            return Ok(());
+        }
+
+        let widths = (0..self.headers.len())
+            .map(|i| self.rows.iter().map(|r| r[i].chars().count()).chain(Some(self.headers[i].len())).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+        let line = |cells: Vec<&str>| cells.iter().zip(&widths)
+            .map(|(cell, width)| format!("{:1$}", cell, width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_owned();
+        writeln!(out, "{}", line(self.headers.clone()))?;
+        let rules = widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>();
+        writeln!(out, "{}", line(rules.iter().map(String::as_str).collect()))?;
+        for row in &self.rows {
+            writeln!(out, "{}", line(row.iter().map(String::as_str).collect()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints what the tables that follow are about, in a table.
+pub fn title(format: Format, text: &str) -> io::Result<()> {
+    match format {
+        Format::Table => writeln!(io::stdout(), "{}\n", text),
+        _ => Ok(()),
+    }
+}
+
+/// Prints a heading between tables, in a table.
+pub fn heading(format: Format, text: &str) -> io::Result<()> {
+    match format {
+        Format::Table => writeln!(io::stdout(), "\n{}\n", text),
+        _ => Ok(()),
+    }
+}
+
+pub fn year(year: Option<u16>) -> String {
+    year.map_or_else(String::new, |y| y.to_string())
+}
+
+pub fn resolution(resolution: Option<u16>) -> String {
+    resolution.map_or_else(String::new, |r| format!("{}p", r))
+}
@@ -0,0 +1,245 @@
+use std::process::Command;
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use failure::{Error, bail, format_err};
+use log::{info, warn};
+
+use data::WatchProgress;
+
+use crate::config::Profile;
+
+/// The program videos are played with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Player {
+    Mpv,
+    Vlc,
+    Ffplay,
+    /// A command line where `{url}`, `{title}`, `{start}` and `{subtitle}` are
+    /// replaced with what is being played. An argument that is just `{url}`
+    /// becomes one argument for each part of a multi-part movie.
+    Custom(String),
+}
+
+impl FromStr for Player {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.trim() {
+            "" => bail!("the player can't be empty"),
+            "mpv" => Ok(Player::Mpv),
+            "vlc" => Ok(Player::Vlc),
+            "ffplay" => Ok(Player::Ffplay),
+            template if template.contains("{url}") => Ok(Player::Custom(template.to_owned())),
+            program => Ok(Player::Custom(format!("{} {{url}}", program))),
+        }
+    }
+}
+
+/// What to play, with absolute URLs.
+#[derive(Clone, Debug)]
+pub struct Playback {
+    pub title: String,
+    /// The parts still to play, starting with `first_part`.
+    pub streams: Vec<String>,
+    pub first_part: usize,
+    pub subtitles: Vec<String>,
+    /// Seconds into the first stream to start at.
+    pub start: Option<f64>,
+}
+
+impl Player {
+    /// The player given on the command line, or else the profile's, or else mpv.
+    pub fn from_matches(matches: &ArgMatches, profile: &Profile) -> Result<Self, Error> {
+        matches.value_of("player").or_else(|| profile.player.as_ref().map(String::as_str)).unwrap_or("mpv").parse()
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Player::Mpv => "mpv",
+            Player::Vlc => "vlc",
+            Player::Ffplay => "ffplay",
+            Player::Custom(template) => template.split_whitespace().next().unwrap_or(template),
+        }
+    }
+
+    /// The command that plays `playback`, telling mpv to listen for progress requests on `ipc_socket`.
+    pub fn command(&self, playback: &Playback, ipc_socket: Option<&std::path::Path>) -> Result<Command, Error> {
+        let start = playback.start.filter(|s| *s > 0.0);
+        let (first, rest) = playback.streams.split_first().ok_or_else(|| format_err!("there is nothing to play"))?;
+        let mut command;
+        match self {
+            Player::Mpv => {
+                command = Command::new("mpv");
+                command.arg(format!("--force-media-title={}", playback.title));
+                command.args(playback.subtitles.iter().map(|s| format!("--sub-file={}", s)));
+                if let Some(socket) = ipc_socket {
+                    command.arg(format!("--input-ipc-server={}", socket.display()));
+                }
+                // Given like this the start time only applies to the first part.
+                match start {
+                    Some(start) => command.args(&["--{".to_owned(), format!("--start={}", start), first.to_owned(), "--}".to_owned()]),
+                    None => command.arg(first),
+                };
+                command.args(rest);
+            },
+            Player::Vlc => {
+                command = Command::new("vlc");
+                command.arg(format!("--meta-title={}", playback.title));
+                command.arg(first);
+                // Options after a URL only apply to it.
+                if let Some(start) = start {
+                    command.arg(format!(":start-time={}", start));
+                }
+                if let Some(subtitle) = playback.subtitles.first() {
+                    command.arg(format!(":sub-file={}", subtitle));
+                }
+                command.args(rest);
+            },
+            Player::Ffplay => {
+                if !rest.is_empty() {
+                    warn!("ffplay can only play the first of the {} parts", playback.streams.len());
+                }
+                if !playback.subtitles.is_empty() {
+                    warn!("ffplay can't load subtitles from the server, playing without them");
+                }
+                command = Command::new("ffplay");
+                command.args(&["-autoexit", "-window_title", &playback.title]);
+                if let Some(start) = start {
+                    command.args(&["-ss".to_owned(), start.to_string()]);
+                }
+                command.arg(first);
+            },
+            Player::Custom(template) => {
+                let words = split_words(template)?;
+                let (program, args) = words.split_first().ok_or_else(|| format_err!("the player can't be empty"))?;
+                command = Command::new(program);
+                for arg in args {
+                    if arg == "{url}" {
+                        command.args(&playback.streams);
+                        continue;
+                    }
+                    command.arg(arg
+                        .replace("{url}", first)
+                        .replace("{title}", &playback.title)
+                        .replace("{start}", &start.unwrap_or(0.0).to_string())
+                        .replace("{subtitle}", playback.subtitles.first().map_or("", String::as_str)));
+                }
+            },
+        }
+        Ok(command)
+    }
+}
+
+/// Plays `playback`, passing what the player says about how far it got to `report`
+/// every so often and once more when it is closed. Only mpv says.
+pub fn play(player: &Player, playback: &Playback, report: &mut dyn FnMut(&WatchProgress)) -> Result<(), Error> {
+    info!("Playing {} with {}", playback.title, player.name());
+
+    #[cfg(unix)]
+    {
+        if let Player::Mpv = player {
+            let socket = std::env::temp_dir().join(format!("carolus-mpv-{}.sock", std::process::id()));
+            let command = player.command(playback, Some(&socket))?;
+            return crate::mpv::play(command, &socket, playback.first_part, report)
+                .map_err(|e| format_err!("could not play with mpv: {}", e));
+        }
+    }
+
+    let status = player.command(playback, None)?.status()
+        .map_err(|e| format_err!("could not start {}: {}", player.name(), e))?;
+    if !status.success() {
+        bail!("{} stopped with {}", player.name(), status);
+    }
+    Ok(())
+}
+
+/// Splits a command line into words, keeping together what is in single or double quotes.
+fn split_words(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = vec![];
+    let mut word = None::<String>;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            },
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("the player command has an unclosed quote: {}", line);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Reads a start time given as seconds, `mm:ss` or `hh:mm:ss`.
+pub fn parse_time(time: &str) -> Result<f64, Error> {
+    time.split(':').try_fold(0.0, |total, part| {
+        let part = part.trim().parse::<f64>().map_err(|_| format_err!("'{}' is not a time, give seconds or hh:mm:ss", time))?;
+        Ok(total * 60.0 + part)
+    })
+}
+
+#[test]
+fn words() {
+    let cases = vec![
+        ("mpv {url}", vec!["mpv", "{url}"]),
+        ("  mpv   --fs  {url} ", vec!["mpv", "--fs", "{url}"]),
+        ("\"/Applications/My Player\" '{title}' {url}", vec!["/Applications/My Player", "{title}", "{url}"]),
+        ("player --title=\"{title}\" ''", vec!["player", "--title={title}", ""]),
+    ];
+
+    for (line, expected) in cases {
+        assert_eq!(expected, split_words(line).unwrap(), "{}", line);
+    }
+    assert!(split_words("player \"{url}").is_err());
+}
+
+#[test]
+fn times() {
+    let cases = vec![("90", 90.0), ("1.5", 1.5), ("01:30", 90.0), ("1:02:03", 3723.0), (" 2 : 00 ", 120.0)];
+
+    for (time, expected) in cases {
+        assert_eq!(expected, parse_time(time).unwrap(), "{}", time);
+    }
+    assert!(parse_time("").is_err());
+    assert!(parse_time("1h").is_err());
+    assert!(parse_time("1::2").is_err());
+}
+
+#[test]
+fn commands() {
+    let playback = Playback {
+        title: "Alien (1979)".to_owned(),
+        streams: vec!["http://server/part0".to_owned(), "http://server/part1".to_owned()],
+        first_part: 0,
+        subtitles: vec!["http://server/sub0".to_owned()],
+        start: Some(12.5),
+    };
+    let args = |player: Player, socket: Option<&std::path::Path>| {
+        let command = player.command(&playback, socket).unwrap();
+        let mut args = vec![command.get_program().to_string_lossy().into_owned()];
+        args.extend(command.get_args().map(|a| a.to_string_lossy().into_owned()));
+        args
+    };
+
+    assert_eq!(vec!["mpv", "--force-media-title=Alien (1979)", "--sub-file=http://server/sub0", "--input-ipc-server=/tmp/mpv.sock",
+                    "--{", "--start=12.5", "http://server/part0", "--}", "http://server/part1"],
+               args(Player::Mpv, Some(std::path::Path::new("/tmp/mpv.sock"))));
+    assert_eq!(vec!["vlc", "--meta-title=Alien (1979)", "http://server/part0", ":start-time=12.5", ":sub-file=http://server/sub0", "http://server/part1"],
+               args(Player::Vlc, None));
+    assert_eq!(vec!["ffplay", "-autoexit", "-window_title", "Alien (1979)", "-ss", "12.5", "http://server/part0"],
+               args(Player::Ffplay, None));
+    assert_eq!(vec!["my player", "--title=Alien (1979)", "--start=12.5", "http://server/part0", "http://server/part1"],
+               args("'my player' --title={title} --start={start} {url}".parse().unwrap(), None));
+    assert_eq!(vec!["iina", "http://server/part0"], args("iina".parse().unwrap(), None).into_iter().take(2).collect::<Vec<_>>());
+
+    let nothing = Playback { streams: vec![], ..playback.clone() };
+    assert!(Player::Mpv.command(&nothing, None).is_err());
+}
@@ -0,0 +1,561 @@
+//! `carolus-cli tui`, for browsing the library over the JSON API and playing from it.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Stdout};
+
+use clap::ArgMatches;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode};
+use failure::{Error, bail};
+use ratatui::{Frame, Terminal};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs, Wrap};
+
+use data::{MediaVersion, Movie, TvEpisode, TvSeries, TvShow, WatchProgress};
+
+use crate::PlayRequest;
+use crate::api::{AllMovies, AllProgress, AllTvShows, Server};
+use crate::config::Profile;
+use crate::download;
+use crate::output;
+use crate::player::Player;
+
+/// Rows moved by page up and page down.
+const PAGE: usize = 10;
+
+/// What the tui shows, read from the server.
+struct Library {
+    movies: Vec<Movie>,
+    tv_shows: Vec<TvShow>,
+    /// By the path of the first file of a version.
+    progress: HashMap<String, WatchProgress>,
+}
+
+impl Library {
+    fn load(server: &Server) -> Result<Self, Error> {
+        Ok(Library {
+            movies: server.get::<AllMovies>("/api/movies", &[])?.movies,
+            tv_shows: server.get::<AllTvShows>("/api/tv", &[])?.tv_shows,
+            progress: load_progress(server)?,
+        })
+    }
+
+    fn progress(&self, versions: &[MediaVersion]) -> Option<&WatchProgress> {
+        versions.first().and_then(|v| self.progress.get(&v.file_path))
+    }
+
+    fn watched(&self, versions: &[MediaVersion]) -> Watched {
+        match self.progress(versions) {
+            Some(progress) if progress.watched => Watched::All,
+            Some(_) => Watched::Started,
+            None => Watched::Not,
+        }
+    }
+
+    fn series_watched(&self, tv_series: &TvSeries) -> Watched {
+        Watched::of_all(tv_series.episodes.iter().map(|e| self.watched(&e.versions)))
+    }
+
+    fn show_watched(&self, tv_show: &TvShow) -> Watched {
+        Watched::of_all(tv_show.series.iter().flat_map(|s| &s.episodes).map(|e| self.watched(&e.versions)))
+    }
+}
+
+fn load_progress(server: &Server) -> Result<HashMap<String, WatchProgress>, Error> {
+    Ok(server.get::<AllProgress>("/api/progress", &[])?.progress)
+}
+
+/// How much of something has been watched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Watched {
+    Not,
+    Started,
+    All,
+}
+
+impl Watched {
+    /// Of a tv show or series, from its episodes.
+    fn of_all<I: IntoIterator<Item = Watched>>(episodes: I) -> Self {
+        let (mut any, mut all) = (false, true);
+        for watched in episodes {
+            any |= watched != Watched::Not;
+            all &= watched == Watched::All;
+        }
+        match (any, all) {
+            (true, true) => Watched::All,
+            (true, false) => Watched::Started,
+            (false, _) => Watched::Not,
+        }
+    }
+
+    fn marker(self) -> Span<'static> {
+        match self {
+            Watched::Not => Span::raw("  "),
+            Watched::Started => Span::styled("◐ ", Style::default().fg(Color::Yellow)),
+            Watched::All => Span::styled("✓ ", Style::default().fg(Color::Green)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tab {
+    Movies,
+    Tv,
+}
+
+/// How far into a tv show the list has gone.
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    /// Every movie or tv show.
+    Library,
+    Series { show: usize },
+    Episodes { show: usize, series: usize },
+}
+
+/// A line of the list, with the index of what it is in the library.
+struct Row {
+    index: usize,
+    label: String,
+    watched: Watched,
+}
+
+enum Action {
+    Nothing,
+    Quit,
+    Reload,
+    Play(PlayRequest),
+}
+
+struct App {
+    library: Library,
+    tab: Tab,
+    level: Level,
+    filter: String,
+    /// Whether keys are typed into the filter.
+    filtering: bool,
+    list: ListState,
+    /// What went wrong last, shown until the next key.
+    status: Option<String>,
+}
+
+impl App {
+    fn new(library: Library) -> Self {
+        App { library, tab: Tab::Movies, level: Level::Library, filter: String::new(), filtering: false, list: ListState::default(), status: None }
+    }
+
+    fn tv_show(&self, show: usize) -> &TvShow {
+        &self.library.tv_shows[show]
+    }
+
+    fn tv_series(&self, show: usize, series: usize) -> &TvSeries {
+        &self.tv_show(show).series[series]
+    }
+
+    /// What the list shows, best matches first when filtered.
+    fn rows(&self) -> Vec<Row> {
+        let library = &self.library;
+        let rows: Vec<Row> =
+            match (self.tab, self.level) {
+                (Tab::Movies, _) => library.movies.iter().enumerate()
+                    .map(|(index, movie)| Row { index, label: with_year(&movie.title, movie.year), watched: library.watched(&movie.versions) })
+                    .collect(),
+                (Tab::Tv, Level::Library) => library.tv_shows.iter().enumerate()
+                    .map(|(index, tv_show)| Row { index, label: with_year(&tv_show.title, tv_show.year), watched: library.show_watched(tv_show) })
+                    .collect(),
+                (Tab::Tv, Level::Series { show }) => self.tv_show(show).series.iter().enumerate()
+                    .map(|(index, tv_series)| Row { index, label: format!("Series {}", tv_series.series_number), watched: library.series_watched(tv_series) })
+                    .collect(),
+                (Tab::Tv, Level::Episodes { show, series }) => self.tv_series(show, series).episodes.iter().enumerate()
+                    .map(|(index, episode)| Row { index, label: format!("Episode {}", episode.episode_number), watched: library.watched(&episode.versions) })
+                    .collect(),
+            };
+        if self.filter.is_empty() {
+            return rows;
+        }
+        let mut scored = rows.into_iter()
+            .filter_map(|row| Some((fuzzy_score(&self.filter, &row.label)?, row)))
+            .collect::<Vec<_>>();
+        scored.sort_by_key(|s| Reverse(s.0));
+        scored.into_iter().map(|(_, row)| row).collect()
+    }
+
+    /// The index in the library of the selected row.
+    fn selected(&self) -> Option<usize> {
+        self.list.selected().and_then(|i| self.rows().get(i).map(|r| r.index))
+    }
+
+    fn go_to(&mut self, tab: Tab, level: Level, select: Option<usize>) {
+        self.tab = tab;
+        self.level = level;
+        self.filter.clear();
+        self.filtering = false;
+        let position = select.and_then(|index| self.rows().iter().position(|r| r.index == index));
+        self.list.select(Some(position.unwrap_or(0)));
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        let count = self.rows().len();
+        if count == 0 {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        self.list.select(Some(current.saturating_add(offset).max(0).min(count as isize - 1) as usize));
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Action {
+        self.status = None;
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        if self.filtering {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => { self.filter.pop(); },
+                KeyCode::Enter => self.filtering = false,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.filtering = false;
+                },
+                _ => return Action::Nothing,
+            }
+            self.list.select(Some(0));
+            return Action::Nothing;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Char('r') => return Action::Reload,
+            KeyCode::Char('/') => self.filtering = true,
+            KeyCode::Tab | KeyCode::BackTab => {
+                let tab = if self.tab == Tab::Movies { Tab::Tv } else { Tab::Movies };
+                self.go_to(tab, Level::Library, None);
+            },
+            KeyCode::Char('1') => self.go_to(Tab::Movies, Level::Library, None),
+            KeyCode::Char('2') => self.go_to(Tab::Tv, Level::Library, None),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-(PAGE as isize)),
+            KeyCode::PageDown => self.move_by(PAGE as isize),
+            KeyCode::Home | KeyCode::Char('g') => self.list.select(Some(0)),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::max_value()),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => return self.open(false),
+            KeyCode::Char('s') => return self.open(true),
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.list.select(Some(0));
+            },
+            KeyCode::Esc | KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => self.back(),
+            _ => (),
+        }
+        Action::Nothing
+    }
+
+    /// Goes into the selected tv show or series, or plays the selected movie or episode.
+    fn open(&mut self, from_start: bool) -> Action {
+        let index = match self.selected() {
+            Some(index) => index,
+            None => return Action::Nothing,
+        };
+        match (self.tab, self.level) {
+            (Tab::Movies, _) => {
+                let movie = &self.library.movies[index];
+                Action::Play(PlayRequest { title: movie.title.to_owned(), year: movie.year, episode: None, start: None, from_start })
+            },
+            (Tab::Tv, Level::Library) => {
+                self.go_to(Tab::Tv, Level::Series { show: index }, None);
+                Action::Nothing
+            },
+            (Tab::Tv, Level::Series { show }) => {
+                self.go_to(Tab::Tv, Level::Episodes { show, series: index }, None);
+                Action::Nothing
+            },
+            (Tab::Tv, Level::Episodes { show, series }) => {
+                let tv_show = self.tv_show(show);
+                let tv_series = self.tv_series(show, series);
+                let episode = Some((tv_series.series_number, tv_series.episodes[index].episode_number));
+                Action::Play(PlayRequest { title: tv_show.title.to_owned(), year: tv_show.year, episode, start: None, from_start })
+            },
+        }
+    }
+
+    fn back(&mut self) {
+        match self.level {
+            Level::Library => (),
+            Level::Series { show } => self.go_to(Tab::Tv, Level::Library, Some(show)),
+            Level::Episodes { show, series } => self.go_to(Tab::Tv, Level::Series { show }, Some(series)),
+        }
+    }
+
+    /// Reads the library again, keeping what is selected when it is still there.
+    fn reload(&mut self, server: &Server) {
+        match Library::load(server) {
+            Ok(library) => {
+                let (tab, selected) = (self.tab, self.selected());
+                let level = self.level;
+                self.library = library;
+                let still_there = match level {
+                    Level::Library => true,
+                    Level::Series { show } => show < self.library.tv_shows.len(),
+                    Level::Episodes { show, series } => self.library.tv_shows.get(show).map_or(false, |s| series < s.series.len()),
+                };
+                if still_there {
+                    self.go_to(tab, level, selected);
+                } else {
+                    self.go_to(tab, Level::Library, None);
+                }
+            },
+            Err(e) => self.status = Some(format!("Could not read the library: {}", e)),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = self.rows();
+        match self.list.selected() {
+            _ if rows.is_empty() => self.list.select(None),
+            Some(selected) if selected >= rows.len() => self.list.select(Some(rows.len() - 1)),
+            None => self.list.select(Some(0)),
+            Some(_) => (),
+        }
+
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0), Constraint::Length(1)])
+            .split(frame.size());
+        let tabs = Tabs::new(vec!["Movies", "TV"])
+            .select(if self.tab == Tab::Movies { 0 } else { 1 })
+            .block(Block::default().borders(Borders::ALL).title(" Carolus "))
+            .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+        frame.render_widget(tabs, areas[0]);
+
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+            .split(areas[1]);
+        let items = rows.iter()
+            .map(|row| ListItem::new(Line::from(vec![row.watched.marker(), Span::raw(row.label.to_owned())])))
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(self.list_title()))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, panes[0], &mut self.list);
+
+        let selected = self.list.selected().and_then(|i| rows.get(i)).map(|r| r.index);
+        let details = Paragraph::new(selected.map(|index| self.details(index)).unwrap_or_default())
+            .block(Block::default().borders(Borders::ALL).title(" Details "))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, panes[1]);
+
+        frame.render_widget(Paragraph::new(self.status_line()), areas[2]);
+    }
+
+    fn list_title(&self) -> String {
+        let title =
+            match (self.tab, self.level) {
+                (Tab::Movies, _) => format!("Movies ({})", self.library.movies.len()),
+                (Tab::Tv, Level::Library) => format!("TV ({})", self.library.tv_shows.len()),
+                (Tab::Tv, Level::Series { show }) => self.tv_show(show).title.to_owned(),
+                (Tab::Tv, Level::Episodes { show, series }) => format!("{}: Series {}", self.tv_show(show).title, self.tv_series(show, series).series_number),
+            };
+        if self.filter.is_empty() {
+            format!(" {} ", title)
+        } else {
+            format!(" {} /{} ", title, self.filter)
+        }
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        if self.filtering {
+            return Line::from(format!("/{}", self.filter));
+        }
+        match self.status {
+            Some(ref status) => Line::from(Span::styled(status.to_owned(), Style::default().fg(Color::Red))),
+            None => Line::from(Span::styled(
+                "Enter open/play  s play from start  / filter  Tab movies/tv  ← back  r reload  q quit",
+                Style::default().add_modifier(Modifier::DIM))),
+        }
+    }
+
+    /// What is known about the movie, tv show, series or episode at `index` in the list.
+    fn details(&self, index: usize) -> Vec<Line<'static>> {
+        let library = &self.library;
+        let mut lines = vec![];
+        match (self.tab, self.level) {
+            (Tab::Movies, _) => {
+                let movie = &library.movies[index];
+                lines.push(heading(with_year(&movie.title, movie.year)));
+                lines.push(Line::from(describe_progress(library.progress(&movie.versions), &movie.versions)));
+                version_lines(&movie.versions, &mut lines);
+                lines.push(Line::from(format!("Extras: {}", movie.extras.len())));
+            },
+            (Tab::Tv, Level::Library) => {
+                let tv_show = &library.tv_shows[index];
+                let episodes = tv_show.series.iter().flat_map(|s| s.episodes.iter().map(|e| &**e)).collect::<Vec<_>>();
+                lines.push(heading(with_year(&tv_show.title, tv_show.year)));
+                lines.push(Line::from(format!("{} series, {} episodes", tv_show.series.len(), episodes.len())));
+                lines.push(Line::from(watched_count(library, episodes)));
+                lines.push(Line::from(format!("Extras: {}", tv_show.extras.len())));
+            },
+            (Tab::Tv, Level::Series { show }) => {
+                let tv_series = self.tv_series(show, index);
+                lines.push(heading(format!("{}: Series {}", self.tv_show(show).title, tv_series.series_number)));
+                lines.push(Line::from(format!("{} episodes", tv_series.episodes.len())));
+                lines.push(Line::from(watched_count(library, tv_series.episodes.iter().map(|e| &**e).collect())));
+                lines.push(Line::from(format!("Extras: {}", tv_series.extras.len())));
+            },
+            (Tab::Tv, Level::Episodes { show, series }) => {
+                let tv_series = self.tv_series(show, series);
+                let episode = &tv_series.episodes[index];
+                lines.push(heading(format!("{} S{:02}E{:02}", self.tv_show(show).title, tv_series.series_number, episode.episode_number)));
+                lines.push(Line::from(describe_progress(library.progress(&episode.versions), &episode.versions)));
+                version_lines(&episode.versions, &mut lines);
+            },
+        }
+        lines
+    }
+}
+
+fn heading(text: String) -> Line<'static> {
+    Line::from(Span::styled(text, Style::default().add_modifier(Modifier::BOLD)))
+}
+
+fn with_year(title: &str, year: Option<u16>) -> String {
+    year.map_or_else(|| title.to_owned(), |y| format!("{} ({})", title, y))
+}
+
+fn watched_count(library: &Library, episodes: Vec<&TvEpisode>) -> String {
+    let watched = episodes.iter().filter(|e| library.watched(&e.versions) == Watched::All).count();
+    format!("Watched {} of {}", watched, episodes.len())
+}
+
+fn describe_progress(progress: Option<&WatchProgress>, versions: &[MediaVersion]) -> String {
+    match progress {
+        None => "Not watched".to_owned(),
+        Some(progress) if progress.watched => "Watched".to_owned(),
+        Some(progress) => {
+            let parts = versions.first().map_or(1, MediaVersion::part_count);
+            let part = if parts > 1 { format!(" of part {}", progress.part + 1) } else { String::new() };
+            format!("Stopped at {}{}", clock(progress.position), part)
+        },
+    }
+}
+
+fn version_lines(versions: &[MediaVersion], lines: &mut Vec<Line<'static>>) {
+    lines.push(Line::from(""));
+    for (index, version) in versions.iter().enumerate() {
+        let mut about = vec![version.name.to_owned()];
+        about.extend(version.edition.clone());
+        about.extend(Some(output::resolution(version.quality.resolution)).filter(|r| !r.is_empty()));
+        about.extend(version.quality.source.clone());
+        if version.part_count() > 1 {
+            about.push(format!("{} parts", version.part_count()));
+        }
+        lines.push(Line::from(format!("Version {}: {}", index, about.join(", "))));
+        lines.push(Line::from(Span::styled(format!("  {}", version.file_path), Style::default().add_modifier(Modifier::DIM))));
+    }
+    lines.push(Line::from(""));
+}
+
+/// Seconds as `h:mm:ss`.
+fn clock(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// How well `text` matches `pattern`, whose letters all have to be in it in order. Letters
+/// that follow on from the last one matched, or that start a word, count for more.
+fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let mut wanted = pattern.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut previous = None::<char>;
+    let mut last_match = None;
+    for (i, c) in text.chars().flat_map(char::to_lowercase).enumerate() {
+        if wanted.peek() == Some(&c) {
+            wanted.next();
+            score += 1;
+            if i > 0 && last_match == Some(i - 1) {
+                score += 4;
+            }
+            if previous.map_or(true, |p| !p.is_alphanumeric()) {
+                score += 2;
+            }
+            last_match = Some(i);
+        }
+        previous = Some(c);
+    }
+    match wanted.peek() {
+        Some(_) => None,
+        None => Some(score),
+    }
+}
+
+/// The terminal, taken over for the tui and put back how it was when dropped.
+struct Screen(Terminal<CrosstermBackend<Stdout>>);
+
+impl Screen {
+    fn enter() -> Result<Self, Error> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(Screen(Terminal::new(CrosstermBackend::new(io::stdout()))?))
+    }
+
+    fn leave(&mut self) -> io::Result<()> {
+        disable_raw_mode()?;
+        execute!(io::stdout(), LeaveAlternateScreen)?;
+        self.0.show_cursor()
+    }
+
+    /// Gives the terminal back while `run` runs, for a player to use.
+    fn suspend<T>(&mut self, run: impl FnOnce() -> T) -> Result<T, Error> {
+        self.leave()?;
+        let result = run();
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        self.0.clear()?;
+        Ok(result)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.leave();
+    }
+}
+
+pub fn handle_tui(server: &Server, profile: &Profile, matches: &ArgMatches) -> Result<(), Error> {
+    if !atty::is(atty::Stream::Stdout) {
+        bail!("the tui needs to be run in a terminal");
+    }
+    let player = Player::from_matches(matches, profile)?;
+    let folder = download::folder(matches);
+    let mut app = App::new(Library::load(server)?);
+
+    let mut screen = Screen::enter()?;
+    loop {
+        screen.0.draw(|frame| app.draw(frame))?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match app.key(key) {
+            Action::Nothing => (),
+            Action::Quit => return Ok(()),
+            Action::Reload => app.reload(server),
+            Action::Play(request) => {
+                let played = screen.suspend(|| crate::play(server, &player, &folder, &request))?;
+                if let Err(e) = played {
+                    app.status = Some(format!("Could not play {}: {}", request.title, e));
+                }
+                match load_progress(server) {
+                    Ok(progress) => app.library.progress = progress,
+                    Err(e) => app.status = Some(format!("Could not read how far things were watched: {}", e)),
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Runs the CLI against a stub of the server's JSON API.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A server that answers every request for a path with the same status and JSON,
+/// and remembers what it was asked for, and with which token.
+struct Stub {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    tokens: Arc<Mutex<Vec<String>>>,
+}
+
+impl Stub {
+    fn start(routes: Vec<(&'static str, u16, &'static str)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let (seen, seen_tokens) = (requests.clone(), tokens.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut body_length = 0;
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap() == 0 || header.trim().is_empty() {
+                        break;
+                    }
+                    let lower = header.to_lowercase();
+                    if lower.starts_with("authorization: bearer ") {
+                        seen_tokens.lock().unwrap().push(header[22..].trim().to_owned());
+                    }
+                    if lower.starts_with("content-length:") {
+                        body_length = header[15..].trim().parse().unwrap();
+                    }
+                }
+                reader.read_exact(&mut vec![0; body_length]).unwrap();
+                let target = request_line.split_whitespace().nth(1).unwrap_or_default().to_owned();
+                let path = target.split('?').next().unwrap().to_owned();
+                seen.lock().unwrap().push(target);
+
+                let (status, body) = routes.iter()
+                    .find(|(route, _, _)| *route == path)
+                    .map_or((404, r#"{"message": "'nothing' was not found."}"#), |(_, status, body)| (*status, *body));
+                write!(stream, "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).unwrap();
+            }
+        });
+        Stub { url, requests, tokens }
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn tokens(&self) -> Vec<String> {
+        self.tokens.lock().unwrap().clone()
+    }
+}
+
+fn cli_path() -> PathBuf {
+    // Tests are built in target/debug/deps, next to the CLI in target/debug.
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join(format!("carolus-cli{}", env::consts::EXE_SUFFIX))
+}
+
+/// The CLI, with a config that doesn't exist unless the test writes it.
+fn cli(config: &PathBuf) -> Command {
+    let mut command = Command::new(cli_path());
+    for name in &["CAROLUS_SERVER_URL", "CAROLUS_TOKEN", "CAROLUS_PROFILE", "CAROLUS_PLAYER"] {
+        command.env_remove(name);
+    }
+    command.env("CAROLUS_CONFIG", config);
+    command
+}
+
+fn run(host: Option<&str>, args: &[&str]) -> Output {
+    let mut command = cli(&temp_dir("no-config").join("config.toml"));
+    if let Some(host) = host {
+        command.args(&["-h", host]);
+    }
+    command.args(args).output().unwrap()
+}
+
+/// An empty folder for a test to keep files in.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("carolus-cli-{}-{}", test, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+const NO_RESULTS: &str = r#"{"movies": [], "tv_shows": []}"#;
+
+const ALIEN: &str = r#"{"movie": {"title": "Alien", "year": 1979, "versions": [], "extras": []}}"#;
+
+#[test]
+fn missing_server_url() {
+    let output = run(None, &["list", "movies"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("CAROLUS_SERVER_URL"), "{}", stderr(&output));
+}
+
+#[test]
+fn invalid_server_url() {
+    for host in &["carolus-host:8000", "ftp://carolus-host", "http://carolus-host/?page=1"] {
+        let output = run(Some(host), &["list", "movies"]);
+        assert_eq!(output.status.code(), Some(2), "{}", host);
+        assert!(stderr(&output).contains("is not a server URL"), "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn missing_command() {
+    let output = run(Some("http://127.0.0.1:1"), &["play"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn connection_refused() {
+    let url = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+    let output = run(Some(&url), &["list", "movies"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("could not reach the server"), "{}", stderr(&output));
+}
+
+#[test]
+fn not_found() {
+    let stub = Stub::start(vec![("/api/search", 200, NO_RESULTS)]);
+    let output = run(Some(&stub.url), &["info", "movie", "nothing"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("'nothing' was not found."), "{}", stderr(&output));
+}
+
+#[test]
+fn server_error() {
+    let stub = Stub::start(vec![("/api/movies", 500, r#"{"message": "There was an error with the Actix async arbiter."}"#)]);
+    let output = run(Some(&stub.url), &["list", "movies"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(stderr(&output).contains("Actix async arbiter"), "{}", stderr(&output));
+}
+
+#[test]
+fn ambiguous_title() {
+    let stub = Stub::start(vec![("/api/search", 200, r#"{"movies": [
+        {"title": "Alien", "year": 1979, "versions": [], "extras": []},
+        {"title": "Alien Resurrection", "year": 1997, "versions": [], "extras": []},
+        {"title": "alien", "year": 2019, "versions": [], "extras": []}
+    ], "tv_shows": []}"#)]);
+    let output = run(Some(&stub.url), &["info", "movie", "Alien"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("1979, 2019"), "{}", stderr(&output));
+
+    let output = run(Some(&stub.url), &["info", "movie", "Alien", "-y", "1979"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stub.requests().iter().filter(|r| r.starts_with("/api/search")).count(), 1);
+}
+
+#[test]
+fn only_title_gets_its_year() {
+    let stub = Stub::start(vec![
+        ("/api/search", 200, r#"{"movies": [{"title": "Alien", "year": 1979, "versions": [], "extras": []}], "tv_shows": []}"#),
+        ("/api/movie/alien", 200, ALIEN),
+    ]);
+    let output = run(Some(&stub.url), &["info", "movie", "alien", "--format", "plain"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stub.requests().last().unwrap(), "/api/movie/alien?year=1979");
+}
+
+#[test]
+fn episode_versions() {
+    let stub = Stub::start(vec![
+        ("/api/search", 200, r#"{"movies": [], "tv_shows": [{"title": "Firefly", "year": 2002, "series": [], "extras": []}]}"#),
+        ("/api/tv/Firefly/1/2", 200, r#"{
+            "tv_show": {"title": "Firefly", "year": 2002, "series": [], "extras": []},
+            "tv_series": {"series_number": 1, "episodes": [], "extras": []},
+            "tv_episode": {"episode_number": 2, "versions": [{"name": "Firefly S01E02", "file_path": "Firefly/S01E02.mkv", "parts": [], "edition": null, "quality": {"resolution": 720, "source": "BluRay"}}]}
+        }"#),
+    ]);
+    let output = run(Some(&stub.url), &["info", "tv", "Firefly", "-s", "1", "-e", "2", "--format", "plain"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stub.requests().last().unwrap(), "/api/tv/Firefly/1/2?year=2002");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Firefly/S01E02.mkv"));
+
+    let output = run(Some(&stub.url), &["info", "tv", "Firefly", "-e", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn titles_are_escaped() {
+    let stub = Stub::start(vec![("/api/search", 200, NO_RESULTS)]);
+    run(Some(&stub.url), &["info", "movie", "AC/DC: Live & Loud #1?"]);
+    run(Some(&stub.url), &["info", "tv", "Amélie 100%", "-s", "2"]);
+    let requests = stub.requests();
+    assert!(requests.contains(&"/api/search?q=AC%2FDC%3A+Live+%26+Loud+%231%3F".to_owned()), "{:?}", requests);
+    assert!(requests.contains(&"/api/movie/AC%2FDC:%20Live%20&%20Loud%20%231%3F".to_owned()), "{:?}", requests);
+    assert!(requests.contains(&"/api/tv/Am%C3%A9lie%20100%25/2".to_owned()), "{:?}", requests);
+}
+
+#[test]
+fn numbers_are_checked() {
+    let output = run(Some("http://127.0.0.1:1"), &["info", "tv", "Firefly", "-s", "one"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("the series needs to be a number"), "{}", stderr(&output));
+}
+
+#[test]
+fn list_movies() {
+    let stub = Stub::start(vec![("/api/movies", 200, TWO_MOVIES)]);
+    let output = run(Some(&format!("{}/", stub.url)), &["list", "movies", "--format", "plain"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Alien\t1979\t0\t0\nHeat\t\t0\t0\n");
+}
+
+const TWO_MOVIES: &str = r#"{"movies": [
+    {"title": "Alien", "year": 1979, "versions": [], "extras": []},
+    {"title": "Heat", "year": null, "versions": [], "extras": []}
+]}"#;
+
+#[test]
+fn profiles() {
+    let production = Stub::start(vec![("/api/movies", 200, TWO_MOVIES)]);
+    let staging = Stub::start(vec![("/api/movies", 200, TWO_MOVIES)]);
+    let config = temp_dir("profiles").join("config.toml");
+    fs::write(&config, format!(r#"default = "production"
+
+[profiles.production]
+server = "{}"
+token = "production-token"
+format = "plain"
+
+[profiles.staging]
+server = "{}"
+"#, production.url, staging.url)).unwrap();
+
+    let output = cli(&config).args(&["list", "movies"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Alien\t1979\t0\t0\nHeat\t\t0\t0\n");
+    assert_eq!(production.tokens(), vec!["production-token"]);
+
+    let output = cli(&config).args(&["--profile", "staging", "list", "movies"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Title"));
+    assert_eq!(staging.requests(), vec!["/api/movies"]);
+    assert!(staging.tokens().is_empty());
+
+    let output = cli(&config).args(&["--profile", "testing", "list", "movies"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("there is no profile called 'testing'"), "{}", stderr(&output));
+}
+
+#[test]
+fn set_token() {
+    let stub = Stub::start(vec![("/api/progress", 200, r#"{"progress": {}}"#), ("/api/movies", 200, TWO_MOVIES)]);
+    let config = temp_dir("set-token").join("carolus").join("config.toml");
+
+    let mut set_token = cli(&config).args(&["--profile", "staging", "set-token", "--server", &stub.url])
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    set_token.stdin.take().unwrap().write_all(b"secret\n").unwrap();
+    let output = set_token.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stub.tokens(), vec!["secret"]);
+
+    // The first profile given a token becomes the default.
+    let output = cli(&config).args(&["list", "movies"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stub.tokens(), vec!["secret", "secret"]);
+}
+
+#[test]
+fn export_csv() {
+    let csv = "type,title\r\nmovie,Alien\r\n";
+    let stub = Stub::start(vec![("/api/export", 200, csv)]);
+    let file = temp_dir("export").join("library.csv");
+    let output = run(Some(&stub.url), &["export", "--format", "csv", "-o", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(&file).unwrap(), csv);
+    assert_eq!(stub.requests(), vec!["/api/export?format=csv"]);
+}
+
+#[test]
+fn import() {
+    let stub = Stub::start(vec![("/api/import", 200, r#"{"restored": 1, "guessed": [{"from": "/movies/Alien - Director's Cut.mkv", "to": "/movies/Alien.mkv"}], "unmatched": ["/movies/Heat.mkv"]}"#)]);
+    let file = temp_dir("import").join("library.json");
+
+    fs::write(&file, "type,title\r\nmovie,Alien\r\n").unwrap();
+    let output = run(Some(&stub.url), &["import", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("is not a JSON export"), "{}", stderr(&output));
+    assert!(stub.requests().is_empty());
+
+    fs::write(&file, r#"{"movies": [], "tv_shows": [], "progress": {
+        "/movies/Alien.mkv": {"part": 0, "position": 60.0, "duration": null, "watched": false}
+    }}"#).unwrap();
+    let output = run(Some(&stub.url), &["import", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("/movies/Heat.mkv is no longer in the library"), "{}", stdout);
+    assert!(stdout.contains("/movies/Alien - Director's Cut.mkv is no longer in the library, so how far it was watched went to /movies/Alien.mkv"), "{}", stdout);
+    assert!(stdout.ends_with("Restored how far 1 files were watched, and guessed where 1 more went\n"), "{}", stdout);
+    assert_eq!(stub.requests(), vec!["/api/import"]);
+}
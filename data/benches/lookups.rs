@@ -0,0 +1,114 @@
+//! Compares finding movies and episodes in a synthetic library of 100,000 movies and as many
+//! episodes through the indexes of a data set with going through the whole library, as the
+//! data executor used to.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use data::{DataSet, Library, MediaVersion, Movie, Quality, TvEpisode, TvSeries, TvShow};
+
+const MOVIES: usize = 100_000;
+const SHOWS: usize = 1_000;
+const SERIES: u16 = 4;
+const EPISODES: u16 = 25;
+
+fn version(file_path: String) -> MediaVersion {
+    MediaVersion {
+        name: "1080p BluRay".to_owned(),
+        file_path,
+        parts: vec![],
+        edition: None,
+        quality: Quality { resolution: Some(1080), source: Some("BluRay".to_owned()) },
+        fingerprint: None,
+        added: None,
+    }
+}
+
+fn library() -> Library {
+    Library {
+        movies: (0..MOVIES)
+            .map(|i| Movie {
+                title: format!("Movie {}", i),
+                year: Some(1950 + (i % 70) as u16),
+                versions: vec![version(format!("/movies/Movie {}/Movie {}.mp4", i, i))],
+                extras: vec![],
+            })
+            .collect(),
+        tv_shows: (0..SHOWS)
+            .map(|i| TvShow {
+                title: format!("Show {}", i),
+                year: None,
+                series: (1..=SERIES)
+                    .map(|s| Arc::new(TvSeries {
+                        series_number: s,
+                        episodes: (1..=EPISODES)
+                            .map(|e| Arc::new(TvEpisode {
+                                episode_number: e,
+                                versions: vec![version(format!("/tv/Show {}/Season {}/Show {} S{:02}E{:02}.mp4", i, s, i, s, e))],
+                            }))
+                            .collect(),
+                        extras: vec![],
+                    }))
+                    .collect(),
+                extras: vec![],
+            })
+            .collect(),
+        ..Library::default()
+    }
+}
+
+/// How a movie was found before there were indexes.
+fn scan_movie(data_set: &DataSet, title: &str, year: Option<u16>) -> Option<Arc<Movie>> {
+    match data_set.movies().iter().find(|m| m.title.eq_ignore_ascii_case(title) && m.year == year) {
+        None => data_set.movies().iter().find(|m| m.title.eq_ignore_ascii_case(title)),
+        movie => movie,
+    }
+    .cloned()
+}
+
+/// How an episode was found before there were indexes, cloning its series and itself.
+fn scan_tv_episode(data_set: &DataSet, title: &str, series: u16, episode: u16) -> Option<(Arc<TvShow>, TvSeries, TvEpisode)> {
+    let tv_show = data_set.tv_shows().iter().find(|s| s.title.eq_ignore_ascii_case(title))?;
+    let tv_series = tv_show.series.iter().find(|s| s.series_number == series)?;
+    let tv_episode = tv_series.episodes.iter().find(|e| e.episode_number == episode)?;
+    Some((tv_show.clone(), (**tv_series).clone(), (**tv_episode).clone()))
+}
+
+fn lookups(c: &mut Criterion) {
+    let mut data_set = DataSet::default();
+    data_set.update(library());
+    let data_set = Arc::new(data_set);
+
+    // Near the end of the library, and without the year it has, so the scan goes through it twice.
+    let title = format!("movie {}", MOVIES - 10);
+    let show = format!("show {}", SHOWS - 10);
+
+    let indexed = data_set.clone();
+    let indexed_title = title.clone();
+    c.bench_function("movie, indexed", move |b| b.iter(|| indexed.movie(&indexed_title, None).unwrap()));
+    let scanned = data_set.clone();
+    c.bench_function("movie, scanned", move |b| b.iter(|| scan_movie(&scanned, &title, None).unwrap()));
+
+    let indexed = data_set.clone();
+    let indexed_show = show.clone();
+    c.bench_function("tv episode, indexed", move |b| b.iter(|| indexed.tv_episode(&indexed_show, None, SERIES, EPISODES).unwrap()));
+    let scanned = data_set.clone();
+    c.bench_function("tv episode, scanned", move |b| b.iter(|| scan_tv_episode(&scanned, &show, SERIES, EPISODES).unwrap()));
+
+    let indexed = data_set.clone();
+    let file_path = format!("/tv/Show {}/Season {}/Show {} S{:02}E{:02}.mp4", SHOWS - 10, SERIES, SHOWS - 10, SERIES, EPISODES);
+    c.bench_function("file owner, indexed", move |b| b.iter(|| indexed.file_owner(&file_path).unwrap()));
+}
+
+fn update(c: &mut Criterion) {
+    let library = library();
+    c.bench_function("update", move |b| b.iter(|| DataSet::default().update(library.clone())));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = lookups, update
+}
+criterion_main!(benches);
@@ -15,6 +15,48 @@ pub enum Error {
     #[fail(display = "'{}' was not found.", title)]
     TvShowNotFound { title: String },
 
+    #[fail(display = "'{}' has no version {}.", title, version)]
+    VersionNotFound { title: String, version: usize },
+
+    #[fail(display = "'{}' has no extra {}.", title, extra)]
+    ExtraNotFound { title: String, extra: usize },
+
+    #[fail(display = "'{}' has no subtitle {}.", title, subtitle)]
+    SubtitleNotFound { title: String, subtitle: usize },
+
+    #[fail(display = "'{}' is not a file of the library.", file_path)]
+    FileNotFound { file_path: String },
+
+    #[fail(display = "Thumbnail '{}' has not been generated.", name)]
+    ThumbnailNotFound { name: String },
+
+    #[fail(display = "There is no job called '{}'.", name)]
+    JobKindNotFound { name: String },
+
+    #[fail(display = "There is no export format called '{}'.", name)]
+    ExportFormatNotFound { name: String },
+
+    #[fail(display = "There is no playlist called '{}'.", name)]
+    PlaylistNotFound { name: String },
+
+    #[fail(display = "Playlists can be .m3u8 or .xspf, not '{}'.", name)]
+    PlaylistFormatNotFound { name: String },
+
+    #[fail(display = "The playlists could not be saved. Cause: {}", cause)]
+    PlaylistNotSaved { cause: String },
+
+    #[fail(display = "How far everything was watched could not be saved. Cause: {}", cause)]
+    ProgressNotSaved { cause: String },
+
+    #[fail(display = "'{}' could not be read. Cause: {}", path, cause)]
+    FileNotReadable { path: String, cause: String },
+
+    #[fail(display = "'{}' is not a page of the library.", cursor)]
+    CursorNotValid { cursor: String },
+
+    #[fail(display = "The library has not been scanned yet.")]
+    ScanReportNotFound,
+
     #[fail(display = "There was an error rendering the HTML page.")]
     Template,
 }
@@ -1,28 +1,493 @@
-use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
 
 use actix_web::actix::*;
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::error::Error;
 
 pub mod error;
 
+#[derive(Default)]
 pub struct DataSet {
-    pub movies: Arc<Vec<Arc<Movie>>>,
-    pub tv_shows: Arc<Vec<Arc<TvShow>>>,
+    /// Only changed by [update](#method.update), which keeps `index` in step with them.
+    movies: Arc<Vec<Arc<Movie>>>,
+    tv_shows: Arc<Vec<Arc<TvShow>>>,
+    pub last_scan: Option<Arc<ScanReport>>,
+    /// How far into each movie or episode version playback got, by the version's `file_path`,
+    /// kept in `progress_path` when there is one.
+    pub progress: HashMap<String, WatchProgress>,
+    pub progress_path: Option<PathBuf>,
+    /// Playlists by name, kept in `playlists_path` when there is one.
+    pub playlists: BTreeMap<String, Playlist>,
+    pub playlists_path: Option<PathBuf>,
+    /// Lookups into `movies` and `tv_shows`, rebuilt whenever [update](#method.update) changes them.
+    index: LibraryIndex,
+    /// Files folder rescans found removed, or added, without finding where they went or came from,
+    /// by their fingerprints, as that can be in a folder rescanned later.
+    unpaired_removed: HashMap<Fingerprint, String>,
+    unpaired_added: HashMap<Fingerprint, String>,
+}
+
+/// Finds movies and tv shows without going through the whole library, by their positions in it.
+#[derive(Default)]
+struct LibraryIndex {
+    movies: TitleIndex,
+    tv_shows: TitleIndex,
+    /// Every file of every version, the first of which is what progress is kept by.
+    files: HashMap<PathBuf, FilePosition>,
+}
+
+/// Positions by normalised title, by normalised title and year, and by [id](fn.title_id.html).
+/// The first one with a title is kept, as lookups have always found that one.
+#[derive(Default)]
+struct TitleIndex {
+    by_title: HashMap<String, usize>,
+    by_title_year: HashMap<(String, Option<u16>), usize>,
+    by_id: HashMap<String, usize>,
+}
+
+/// Where the movie or episode a file is a version of is in the library.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FilePosition {
+    Movie(usize),
+    /// The tv show, and the positions of the series in it and the episode in that.
+    TvEpisode(usize, usize, usize),
+}
+
+impl TitleIndex {
+    fn new<'a>(titles: impl Iterator<Item=(&'a str, Option<u16>)>) -> Self {
+        let mut index = TitleIndex::default();
+        for (position, (title, year)) in titles.enumerate() {
+            index.by_id.entry(title_id(title, year)).or_insert(position);
+            let title = normalise_title(title);
+            index.by_title_year.entry((title.clone(), year)).or_insert(position);
+            index.by_title.entry(title).or_insert(position);
+        }
+        index
+    }
+
+    /// The one with the title and year, or else the first with the title.
+    fn find(&self, title: &str, year: Option<u16>) -> Option<usize> {
+        let key = (normalise_title(title), year);
+        self.by_title_year.get(&key).or_else(||self.by_title.get(&key.0)).cloned()
+    }
+
+    /// The one with the title and year, and nothing else.
+    fn find_exact(&self, title: &str, year: Option<u16>) -> Option<usize> {
+        self.by_title_year.get(&(normalise_title(title), year)).cloned()
+    }
+
+    fn find_id(&self, id: &str) -> Option<usize> {
+        self.by_id.get(id).cloned()
+    }
+}
+
+impl LibraryIndex {
+    fn new(movies: &[Arc<Movie>], tv_shows: &[Arc<TvShow>]) -> Self {
+        let mut files = HashMap::new();
+        for (m, movie) in movies.iter().enumerate() {
+            for version in &movie.versions {
+                files.extend(version_files(version).into_iter().map(|f|(PathBuf::from(f), FilePosition::Movie(m))));
+            }
+        }
+        for (s, tv_show) in tv_shows.iter().enumerate() {
+            for (r, tv_series) in tv_show.series.iter().enumerate() {
+                for (e, tv_episode) in tv_series.episodes.iter().enumerate() {
+                    for version in &tv_episode.versions {
+                        files.extend(version_files(version).into_iter().map(|f|(PathBuf::from(f), FilePosition::TvEpisode(s, r, e))));
+                    }
+                }
+            }
+        }
+        LibraryIndex {
+            movies: TitleIndex::new(movies.iter().map(|m|(&*m.title, m.year))),
+            tv_shows: TitleIndex::new(tv_shows.iter().map(|s|(&*s.title, s.year))),
+            files,
+        }
+    }
+}
+
+/// A title as it is looked up: in lower case, with the punctuation and spaces between words
+/// made a single space, so that "Spider-Man: Homecoming" is found as "spider man homecoming".
+fn normalise_title(title: &str) -> String {
+    title.to_lowercase()
+        .split(|c: char|!c.is_alphanumeric())
+        .filter(|word|!word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Names a movie or tv show in a way that stays the same from one scan to the next and can go
+/// in a URL as it is: its normalised title with dashes between the words, and then its year,
+/// such as `alien-1979`.
+pub fn title_id(title: &str, year: Option<u16>) -> String {
+    let title = normalise_title(title).replace(' ', "-");
+    match year {
+        Some(year) => format!("{}-{}", title, year),
+        None => title,
+    }
+}
+
+/// The movie or episode a file of the library is a version of.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileOwner {
+    Movie(Arc<Movie>),
+    TvEpisode(Arc<TvShow>, Arc<TvSeries>, Arc<TvEpisode>),
+}
+
+impl FileOwner {
+    pub fn versions(&self) -> &[MediaVersion] {
+        match self {
+            FileOwner::Movie(movie) => &movie.versions,
+            FileOwner::TvEpisode(_, _, tv_episode) => &tv_episode.versions,
+        }
+    }
+
+    /// The version the file is the first file of, or one of the later parts of.
+    pub fn version(&self, file_path: &str) -> Option<&MediaVersion> {
+        self.versions().iter().find(|v|version_files(v).iter().any(|f|f == file_path))
+    }
+}
+
+impl DataSet {
+    /// Replaces the part of the data set covered by `library` and records what changed.
+    pub fn update(&mut self, library: Library) -> Arc<ScanReport> {
+        let folder = library.folder.clone();
+        let in_scope = |file_path: &str| folder.as_ref().map_or(true, |f| Path::new(file_path).starts_with(f));
+
+        let old_files = files(self.movies.iter().map(|m|&**m), self.tv_shows.iter().map(|s|&**s))
+            .into_iter().filter(|f|in_scope(f)).collect::<BTreeSet<_>>();
+        let new_files = files(library.movies.iter(), library.tv_shows.iter());
+
+        let mut added = new_files.difference(&old_files).cloned().collect::<BTreeSet<_>>();
+        let mut removed = old_files.difference(&new_files).cloned().collect::<BTreeSet<_>>();
+        let mut moved = vec![];
+        // A removed file with the same content as an added one was renamed or moved.
+        let old_fingerprints = fingerprints(self.movies.iter().map(|m|&**m), self.tv_shows.iter().map(|s|&**s));
+        let mut new_fingerprints = HashMap::new();
+        for (file_path, fingerprint) in fingerprints(library.movies.iter(), library.tv_shows.iter()) {
+            if added.contains(&file_path) {
+                new_fingerprints.entry(fingerprint).or_insert(file_path);
+            }
+        }
+        for from in removed.clone() {
+            let fingerprint = match old_fingerprints.get(&from) {
+                Some(fingerprint) => fingerprint,
+                None => continue,
+            };
+            // Either within the folder, or to a file another folder's rescan added that is still there.
+            let to = new_fingerprints.remove(fingerprint).or_else(||{
+                self.unpaired_added.remove(fingerprint).filter(|to|!in_scope(to) && self.index.files.contains_key(Path::new(to)))
+            });
+            if let Some(to) = to {
+                removed.remove(&from);
+                added.remove(&to);
+                moved.push(self.moved(from, to));
+            }
+        }
+        // From a file another folder's rescan removed, unless it is back where it was.
+        for (fingerprint, to) in new_fingerprints.iter() {
+            if let Some(from) = self.unpaired_removed.remove(fingerprint).filter(|from|from != to) {
+                added.remove(to);
+                moved.push(self.moved(from, to.to_owned()));
+            }
+        }
+        moved.sort_by(|a, b|a.to.cmp(&b.to));
+
+        // A whole rescan sees every file, so what is left was removed or added for good.
+        if folder.is_some() {
+            self.unpaired_removed.extend(removed.iter().filter_map(|f|Some((old_fingerprints.get(f)?.clone(), f.to_owned()))));
+            self.unpaired_added.extend(new_fingerprints.into_iter().filter(|(_, f)|added.contains(f)));
+        } else {
+            self.unpaired_removed.clear();
+            self.unpaired_added.clear();
+        }
+
+        // A title was rescanned when any of its files is in the folder, extras included, as a
+        // show can have nothing else yet.
+        let mut movies = self.movies.iter()
+            .filter(|m|!files(iter::once(&***m), iter::empty()).iter().any(|f|in_scope(f)))
+            .cloned()
+            .chain(library.movies.into_iter().map(Arc::new))
+            .collect::<Vec<_>>();
+        movies.sort_by(|a, b|(&a.title, a.year).cmp(&(&b.title, b.year)));
+
+        let mut tv_shows = self.tv_shows.iter()
+            .filter(|s|!files(iter::empty(), iter::once(&***s)).iter().any(|f|in_scope(f)))
+            .cloned()
+            .chain(library.tv_shows.into_iter().map(Arc::new))
+            .collect::<Vec<_>>();
+        tv_shows.sort_by(|a, b|(&a.title, a.year).cmp(&(&b.title, b.year)));
+
+        let report = Arc::new(ScanReport {
+            folder: library.folder,
+            added: added.into_iter().collect(),
+            removed: removed.into_iter().collect(),
+            moved,
+            unchanged: new_files.intersection(&old_files).count(),
+            failures: library.failures,
+            conflicts: library.conflicts,
+        });
+
+        self.index = LibraryIndex::new(&movies, &tv_shows);
+        self.movies = Arc::new(movies);
+        self.tv_shows = Arc::new(tv_shows);
+        self.last_scan = Some(report.clone());
+        report
+    }
+
+    /// Follows a file that was renamed or moved with how far it was watched.
+    fn moved(&mut self, from: String, to: String) -> MovedFile {
+        if let Some(progress) = self.progress.remove(&from) {
+            self.progress.insert(to.clone(), progress);
+        }
+        MovedFile { from, to }
+    }
+
+    /// Every movie, by title and then year.
+    pub fn movies(&self) -> &Arc<Vec<Arc<Movie>>> {
+        &self.movies
+    }
+
+    /// Every tv show, by title and then year.
+    pub fn tv_shows(&self) -> &Arc<Vec<Arc<TvShow>>> {
+        &self.tv_shows
+    }
+
+    /// Everything in the data set, with how far each file was watched.
+    pub fn export(&self) -> LibraryExport {
+        LibraryExport {
+            movies: self.movies.clone(),
+            tv_shows: self.tv_shows.clone(),
+            progress: self.progress.iter().map(|(k, v)|(k.to_owned(), v.clone())).collect(),
+        }
+    }
+
+    /// Restores how far files were watched from an export, following each file to where it is
+    /// now: the same path, else a file with the same content, else the version of the same name
+    /// of the same movie or episode. Failing those, it goes to the best version, which may be
+    /// another cut, so those are reported as guessed.
+    pub fn import_progress(&mut self, export: LibraryExport) -> ImportReport {
+        let current = versions(self.movies.iter().map(|m|&**m), self.tv_shows.iter().map(|s|&**s));
+        let exported = versions(export.movies.iter().map(|m|&**m), export.tv_shows.iter().map(|s|&**s));
+
+        let by_fingerprint = current.iter()
+            .filter_map(|(_, v)|Some((v.fingerprint.as_ref()?, &*v.file_path)))
+            .collect::<HashMap<_, _>>();
+        let exported = exported.iter().map(|(owner, v)|(&*v.file_path, (owner, *v))).collect::<HashMap<_, _>>();
+
+        let mut report = ImportReport::default();
+        for (file_path, progress) in export.progress.iter() {
+            let found =
+                if self.index.files.contains_key(Path::new(file_path)) {
+                    Some((&**file_path, false))
+                } else {
+                    exported.get(&**file_path).and_then(|(owner, version)|{
+                        let same_content = version.fingerprint.as_ref().and_then(|f|by_fingerprint.get(f)).map(|to|(*to, false));
+                        same_content.or_else(||{
+                            let versions = self.owner_versions(owner)?;
+                            match versions.iter().find(|v|v.name == version.name) {
+                                Some(same_name) => Some((&*same_name.file_path, false)),
+                                None => versions.first().map(|v|(&*v.file_path, true)),
+                            }
+                        })
+                    })
+                }
+                .map(|(to, guessed)|(to.to_owned(), guessed));
+            match found {
+                Some((to, guessed)) => {
+                    self.progress.insert(to.clone(), progress.clone());
+                    if guessed {
+                        report.guessed.push(MovedFile { from: file_path.to_owned(), to });
+                    } else {
+                        report.restored += 1;
+                    }
+                },
+                None => report.unmatched.push(file_path.to_owned()),
+            }
+        }
+        report
+    }
+
+    /// The versions of the movie or episode an export's version was of, when the library still
+    /// has a title of that name and year.
+    fn owner_versions(&self, owner: &VersionOwner) -> Option<&[MediaVersion]> {
+        let (title, year, episode) = owner;
+        match episode {
+            None => self.index.movies.find_exact(title, *year).map(|i|&*self.movies[i].versions),
+            Some((series, episode)) => {
+                let tv_show = &self.tv_shows[self.index.tv_shows.find_exact(title, *year)?];
+                let tv_series = tv_show.series.iter().find(|s|s.series_number == *series)?;
+                tv_series.episodes.iter().find(|e|e.episode_number == *episode).map(|e|&*e.versions)
+            },
+        }
+    }
+
+    /// The movie or episode a file is a version of, by any of its parts.
+    pub fn file_owner(&self, file_path: &str) -> Option<FileOwner> {
+        match *self.index.files.get(Path::new(file_path))? {
+            FilePosition::Movie(m) => Some(FileOwner::Movie(self.movies[m].clone())),
+            FilePosition::TvEpisode(s, r, e) => {
+                let tv_show = self.tv_shows[s].clone();
+                let tv_series = tv_show.series[r].clone();
+                let tv_episode = tv_series.episodes[e].clone();
+                Some(FileOwner::TvEpisode(tv_show, tv_series, tv_episode))
+            },
+        }
+    }
+
+    /// The movie with the [id](fn.title_id.html).
+    pub fn movie_by_id(&self, id: &str) -> Option<Arc<Movie>> {
+        self.index.movies.find_id(id).map(|i|self.movies[i].clone())
+    }
+
+    /// The tv show with the [id](fn.title_id.html).
+    pub fn tv_show_by_id(&self, id: &str) -> Option<Arc<TvShow>> {
+        self.index.tv_shows.find_id(id).map(|i|self.tv_shows[i].clone())
+    }
+
+    /// The movie with the title and year, or else the first with the title.
+    pub fn movie(&self, title: &str, year: Option<u16>) -> Option<Arc<Movie>> {
+        self.index.movies.find(title, year).map(|i|self.movies[i].clone())
+    }
+
+    /// The tv show with the title and year, or else the first with the title.
+    pub fn tv_show(&self, title: &str, year: Option<u16>) -> Option<Arc<TvShow>> {
+        self.index.tv_shows.find(title, year).map(|i|self.tv_shows[i].clone())
+    }
+
+    /// A series of a tv show, found as [tv_show](#method.tv_show) finds the show.
+    pub fn tv_series(&self, title: &str, year: Option<u16>, series: u16) -> Option<(Arc<TvShow>, Arc<TvSeries>)> {
+        let tv_show = self.tv_show(title, year)?;
+        let tv_series = tv_show.series.iter().find(|s|s.series_number == series)?.clone();
+        Some((tv_show, tv_series))
+    }
+
+    /// An episode of a tv show, found as [tv_show](#method.tv_show) finds the show.
+    pub fn tv_episode(&self, title: &str, year: Option<u16>, series: u16, episode: u16) -> Option<(Arc<TvShow>, Arc<TvSeries>, Arc<TvEpisode>)> {
+        let (tv_show, tv_series) = self.tv_series(title, year, series)?;
+        let tv_episode = tv_series.episodes.iter().find(|e|e.episode_number == episode)?.clone();
+        Some((tv_show, tv_series, tv_episode))
+    }
+
+    /// Where the progress a player sent for a file is kept, the first file of its version, and
+    /// whether that means the version was watched.
+    fn played(&self, file_path: &str, mut progress: WatchProgress) -> Result<(String, WatchProgress), Error> {
+        let not_found = || Error::FileNotFound { file_path: file_path.to_owned() };
+        let owner = self.file_owner(file_path).ok_or_else(not_found)?;
+        let version = owner.version(file_path).ok_or_else(not_found)?;
+        progress.watched = progress.finished(version.part_count());
+        Ok((version.file_path.to_owned(), progress))
+    }
+
+    /// Keeps the playlists in `playlists_path`, replacing the file whole so it is never half written.
+    fn save_playlists(&self) -> Result<(), Error> {
+        save_json(&self.playlists_path, &self.playlists).map_err(|cause| Error::PlaylistNotSaved { cause })
+    }
+
+    /// Keeps how far everything was watched in `progress_path`, the same way.
+    fn save_progress(&self) -> Result<(), Error> {
+        let progress = self.progress.iter().collect::<BTreeMap<_, _>>();
+        save_json(&self.progress_path, &progress).map_err(|cause| Error::ProgressNotSaved { cause })
+    }
+}
+
+fn save_json(path: &Option<PathBuf>, value: &impl serde::Serialize) -> Result<(), String> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let json = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    let temp = path.with_extension("json.tmp");
+    path.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temp, json))
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|e| e.to_string())
+}
+
+fn files<'a>(movies: impl Iterator<Item=&'a Movie>, tv_shows: impl Iterator<Item=&'a TvShow>) -> BTreeSet<String> {
+    let mut files = BTreeSet::new();
+    for movie in movies {
+        files.extend(movie.versions.iter().flat_map(version_files));
+        files.extend(movie.extras.iter().map(|e|e.file_path.to_owned()));
+    }
+    for tv_show in tv_shows {
+        files.extend(tv_show.extras.iter().map(|e|e.file_path.to_owned()));
+        for tv_series in &tv_show.series {
+            files.extend(tv_series.episodes.iter().flat_map(|e|e.versions.iter()).flat_map(version_files));
+            files.extend(tv_series.extras.iter().map(|e|e.file_path.to_owned()));
+        }
+    }
+    files
+}
+
+fn fingerprints<'a>(movies: impl Iterator<Item=&'a Movie>, tv_shows: impl Iterator<Item=&'a TvShow>) -> HashMap<String, Fingerprint> {
+    let versions = movies.flat_map(|m|m.versions.iter())
+        .chain(tv_shows.flat_map(|s|s.series.iter()).flat_map(|s|s.episodes.iter()).flat_map(|e|e.versions.iter()));
+    versions
+        .filter_map(|v|Some((v.file_path.to_owned(), v.fingerprint.clone()?)))
+        .collect()
+}
+
+/// The movie or episode a version is of: its title, year and, for an episode, its series and
+/// episode numbers.
+type VersionOwner = (String, Option<u16>, Option<(u16, u16)>);
+
+fn versions<'a>(movies: impl Iterator<Item=&'a Movie>, tv_shows: impl Iterator<Item=&'a TvShow>) -> Vec<(VersionOwner, &'a MediaVersion)> {
+    let mut versions = vec![];
+    for movie in movies {
+        let owner = (movie.title.to_owned(), movie.year, None);
+        versions.extend(movie.versions.iter().map(|v|(owner.clone(), v)));
+    }
+    for tv_show in tv_shows {
+        for tv_series in &tv_show.series {
+            for tv_episode in &tv_series.episodes {
+                let owner = (tv_show.title.to_owned(), tv_show.year, Some((tv_series.series_number, tv_episode.episode_number)));
+                versions.extend(tv_episode.versions.iter().map(|v|(owner.clone(), v)));
+            }
+        }
+    }
+    versions
+}
+
+fn version_files(version: &MediaVersion) -> Vec<String> {
+    (0..version.part_count()).filter_map(|i|version.part(i)).map(str::to_owned).collect()
 }
 
-pub struct DataExecutor(pub DataSet);
+/// Shares one [DataSet](struct.DataSet.html) between every thread of the `SyncArbiter`
+/// so a rescan only has to update it once.
+pub struct DataExecutor(pub Arc<RwLock<DataSet>>);
+
+impl DataExecutor {
+    fn data(&self) -> RwLockReadGuard<DataSet> {
+        // Updates swap whole collections in, so the data is still consistent after a panic.
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
 
 impl Actor for DataExecutor {
     type Context = SyncContext<Self>;
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+/// A movie with the files it is available as, the theatrical cut in the best quality first.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Movie {
     pub title: String,
     pub year: Option<u16>,
-    pub file_path: String
+    pub versions: Vec<MediaVersion>,
+    pub extras: Vec<Extra>,
+}
+
+impl Movie {
+    /// The requested version, or the best one when none is asked for.
+    pub fn version(&self, index: Option<usize>) -> Option<&MediaVersion> {
+        self.versions.get(index.unwrap_or(0))
+    }
 }
 
 pub struct AllMoviesMessage;
@@ -37,7 +502,7 @@ impl Handler<AllMoviesMessage> for DataExecutor {
     type Result = AllMoviesResult;
 
     fn handle(&mut self, _: AllMoviesMessage, _: &mut Self::Context) -> Self::Result {
-        Ok(self.0.movies.clone())
+        Ok(self.data().movies.clone())
     }
 }
 
@@ -56,20 +521,17 @@ impl Handler<MovieMessage> for DataExecutor {
     type Result = MovieResult;
 
     fn handle(&mut self, msg: MovieMessage, _: &mut Self::Context) -> Self::Result {
-        match self.0.movies.iter().find(|m|m.title.eq_ignore_ascii_case(&*msg.title) && m.year == msg.year) {
-            None => self.0.movies.iter().find(|m|m.title.eq_ignore_ascii_case(&*msg.title)),
-            movie => movie,
-        }
-        .cloned()
-        .ok_or_else(||Error::MovieNotFound{ title: msg.title })
+        self.data().movie(&msg.title, msg.year)
+            .ok_or_else(||Error::MovieNotFound{ title: msg.title })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TvShow {
     pub title: String,
     pub year: Option<u16>,
-    pub series: Vec<TvSeries>
+    pub series: Vec<Arc<TvSeries>>,
+    pub extras: Vec<Extra>,
 }
 
 pub struct AllTvShowsMessage;
@@ -84,7 +546,7 @@ impl Handler<AllTvShowsMessage> for DataExecutor {
     type Result = AllTvShowsResult;
 
     fn handle(&mut self, _: AllTvShowsMessage, _: &mut Self::Context) -> Self::Result {
-        Ok(self.0.tv_shows.clone())
+        Ok(self.data().tv_shows.clone())
     }
 }
 
@@ -103,19 +565,348 @@ impl Handler<TvShowMessage> for DataExecutor {
     type Result = TvShowResult;
 
     fn handle(&mut self, msg: TvShowMessage, _: &mut Self::Context) -> Self::Result {
-        match self.0.tv_shows.iter().find(|m|m.title.eq_ignore_ascii_case(&*msg.title) && m.year == msg.year) {
-            None => self.0.tv_shows.iter().find(|m|m.title.eq_ignore_ascii_case(&*msg.title)),
-            tv_show => tv_show,
-        }
-        .cloned()
-        .ok_or_else(||Error::TvShowNotFound{ title: msg.title })
+        self.data().tv_show(&msg.title, msg.year)
+            .ok_or_else(||Error::TvShowNotFound{ title: msg.title })
+    }
+}
+
+/// Finds the movies and tv shows with every word of `query` in their title, ignoring case.
+pub struct SearchMessage {
+    pub query: String,
+}
+
+type SearchResult = Result<(Vec<Arc<Movie>>, Vec<Arc<TvShow>>), Error>;
+
+impl Message for SearchMessage {
+    type Result = SearchResult;
+}
+
+impl Handler<SearchMessage> for DataExecutor {
+    type Result = SearchResult;
+
+    fn handle(&mut self, msg: SearchMessage, _: &mut Self::Context) -> Self::Result {
+        let words = msg.query.to_lowercase().split_whitespace().map(str::to_owned).collect::<Vec<_>>();
+        let found = |title: &str| {
+            let title = title.to_lowercase();
+            words.iter().all(|w|title.contains(&**w))
+        };
+        let data = self.data();
+        Ok((
+            data.movies.iter().filter(|m|found(&m.title)).cloned().collect(),
+            data.tv_shows.iter().filter(|s|found(&s.title)).cloned().collect(),
+        ))
+    }
+}
+
+/// Which movies or tv shows to list, in what order, and from where. Every filter that is
+/// given has to match, and a page carries on from the `after` cursor of the one before it.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct LibraryQuery {
+    pub year_from: Option<u16>,
+    pub year_to: Option<u16>,
+    /// The lowest resolution to have a version in, such as 720 or 1080.
+    pub resolution: Option<u16>,
+    /// Only what was watched to the end, or only what wasn't. A tv show is watched once every
+    /// episode of it is.
+    pub watched: Option<bool>,
+    /// Only what was added since then, in seconds since the epoch. A tv show was added when
+    /// its latest episode was.
+    pub added_since: Option<u64>,
+    /// Only what runs for at least this many minutes, going by the length of the best version,
+    /// and for a tv show of every episode together.
+    pub runtime_from: Option<u32>,
+    /// Only what runs for at most this many minutes.
+    pub runtime_to: Option<u32>,
+    /// Only what The Movie Database has in this genre, such as "Horror", in any case.
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub sort: SortKey,
+    #[serde(default)]
+    pub descending: bool,
+    /// The `next` cursor of the previous page.
+    pub after: Option<String>,
+    /// How many to list at most, everything when missing.
+    pub limit: Option<usize>,
+}
+
+/// What a list of movies or tv shows is sorted by, ties going by title, year and then path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// Leaving out a leading "The", "A" or "An".
+    Title,
+    Year,
+    Added,
+    Runtime,
+    /// Out of 10 on The Movie Database. Titles without a rating come before every rated one, so
+    /// they are left until last when listing the best first.
+    Rating,
+}
+
+impl Default for SortKey {
+    fn default() -> Self {
+        SortKey::Title
     }
 }
 
+/// One page of a list of movies or tv shows, and the cursor to pass as `after` for the next
+/// one when there is more.
 #[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LibraryPage<T> {
+    pub items: Vec<T>,
+    /// How many matched the filters, over every page.
+    pub total: usize,
+    pub next: Option<String>,
+}
+
+/// Gives the length in seconds of the video at a path, when it is known. The data set doesn't
+/// read videos, so this comes from whoever does, such as the thumbnails made of them.
+pub type RuntimeLookup = Arc<dyn Fn(&str) -> Option<f64> + Send + Sync>;
+
+/// What The Movie Database has on a movie or tv show that the lists are filtered and sorted by.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TitleMetadata {
+    pub genres: Vec<String>,
+    /// Out of 10.
+    pub rating: Option<f32>,
+}
+
+/// Gives the metadata fetched for a title and year, when there is any. Like running times, it is
+/// kept outside the data set, by whoever fetched it.
+pub type MetadataLookup = Arc<dyn Fn(&str, Option<u16>) -> Option<TitleMetadata> + Send + Sync>;
+
+/// What a movie or tv show is filtered and sorted by.
+struct Listing<'a> {
+    title: &'a str,
+    year: Option<u16>,
+    resolution: Option<u16>,
+    watched: bool,
+    added: Option<u64>,
+    /// In seconds, only looked up when the query needs it.
+    runtime: Option<f64>,
+    /// The first file of the first version, which tells apart titles that are otherwise the same.
+    path: &'a str,
+    /// Only looked up when the query needs them, like the running time.
+    genres: Vec<String>,
+    rating: Option<f32>,
+}
+
+impl<'a> Listing<'a> {
+    fn movie(movie: &'a Movie, progress: &HashMap<String, WatchProgress>, runtime: Option<&RuntimeLookup>, metadata: Option<&MetadataLookup>) -> Self {
+        let metadata = metadata.and_then(|metadata|metadata(&movie.title, movie.year)).unwrap_or_default();
+        Listing {
+            title: &movie.title,
+            year: movie.year,
+            resolution: movie.versions.iter().filter_map(|v|v.quality.resolution).max(),
+            watched: movie.versions.iter().any(|v|watched(v, progress)),
+            added: movie.versions.iter().filter_map(|v|v.added).max(),
+            runtime: runtime.and_then(|runtime|version_runtime(movie.versions.first()?, progress, runtime)),
+            path: movie.versions.first().map_or("", |v|&v.file_path),
+            genres: metadata.genres,
+            rating: metadata.rating,
+        }
+    }
+
+    fn tv_show(tv_show: &'a TvShow, progress: &HashMap<String, WatchProgress>, runtime: Option<&RuntimeLookup>, metadata: Option<&MetadataLookup>) -> Self {
+        let metadata = metadata.and_then(|metadata|metadata(&tv_show.title, tv_show.year)).unwrap_or_default();
+        let episodes = || tv_show.series.iter().flat_map(|s|s.episodes.iter());
+        let versions = || episodes().flat_map(|e|e.versions.iter());
+        Listing {
+            title: &tv_show.title,
+            year: tv_show.year,
+            resolution: versions().filter_map(|v|v.quality.resolution).max(),
+            watched: episodes().all(|e|e.versions.iter().any(|v|watched(v, progress))),
+            added: versions().filter_map(|v|v.added).max(),
+            runtime: runtime.and_then(|runtime|{
+                let lengths = episodes().filter_map(|e|version_runtime(e.versions.first()?, progress, runtime)).collect::<Vec<_>>();
+                if lengths.is_empty() { None } else { Some(lengths.iter().sum()) }
+            }),
+            path: versions().next().map_or("", |v|&v.file_path),
+            genres: metadata.genres,
+            rating: metadata.rating,
+        }
+    }
+
+    /// Where it goes in the list: the sort key, then the title, year and path.
+    fn position(&self, sort: SortKey) -> Position {
+        let key =
+            match sort {
+                SortKey::Title => None,
+                SortKey::Year => self.year.map(u64::from),
+                SortKey::Added => self.added,
+                SortKey::Runtime => self.runtime.map(|r|r.round() as u64),
+                SortKey::Rating => self.rating.map(|r|(f64::from(r) * 100.0).round() as u64),
+            };
+        Position { key, title: sort_title(self.title), year: self.year, path: self.path.to_owned() }
+    }
+}
+
+fn watched(version: &MediaVersion, progress: &HashMap<String, WatchProgress>) -> bool {
+    progress.get(&version.file_path).map_or(false, |p|p.finished(version.part_count()))
+}
+
+/// How long the first file of a version is, or else how long the player said it was.
+fn version_runtime(version: &MediaVersion, progress: &HashMap<String, WatchProgress>, runtime: &RuntimeLookup) -> Option<f64> {
+    runtime(&version.file_path)
+        .or_else(||progress.get(&version.file_path).filter(|p|p.part == 0).and_then(|p|p.duration))
+}
+
+/// A title as it is sorted, leaving out the article it starts with.
+fn sort_title(title: &str) -> String {
+    let title = title.to_lowercase();
+    for article in &["the ", "a ", "an "] {
+        if title.starts_with(article) && title.len() > article.len() {
+            return title[article.len()..].trim_start().to_owned();
+        }
+    }
+    title
+}
+
+/// Where a movie or tv show is in a sorted list, which is what a cursor holds so that the next
+/// page carries on from the right place even when the library changed in between.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    key: Option<u64>,
+    title: String,
+    year: Option<u16>,
+    path: String,
+}
+
+impl Position {
+    /// The numbers, the length of the title and then the title and path run together, as either
+    /// can have any character in it.
+    fn cursor(&self) -> String {
+        let number = |n: Option<u64>| n.map_or_else(String::new, |n| n.to_string());
+        format!("{}:{}:{}:{}{}", number(self.key), number(self.year.map(u64::from)), self.title.len(), self.title, self.path)
+    }
+
+    fn from_cursor(cursor: &str) -> Result<Self, Error> {
+        let not_valid = || Error::CursorNotValid { cursor: cursor.to_owned() };
+        let number = |n: &str| if n.is_empty() { Ok(None) } else { n.parse().map(Some).map_err(|_|not_valid()) };
+        let mut fields = cursor.splitn(4, ':');
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(key), Some(year), Some(title_len), Some(rest)) => {
+                let title_len = title_len.parse::<usize>().map_err(|_|not_valid())?;
+                let (title, path) = match (rest.get(..title_len), rest.get(title_len..)) {
+                    (Some(title), Some(path)) => (title, path),
+                    _ => return Err(not_valid()),
+                };
+                Ok(Position {
+                    key: number(key)?,
+                    year: number(year)?.map(|y: u64|u16::try_from(y).map_err(|_|not_valid())).transpose()?,
+                    title: title.to_owned(),
+                    path: path.to_owned(),
+                })
+            },
+            _ => Err(not_valid()),
+        }
+    }
+}
+
+impl LibraryQuery {
+    fn matches(&self, listing: &Listing) -> bool {
+        self.year_from.map_or(true, |from|listing.year.map_or(false, |y|y >= from))
+            && self.year_to.map_or(true, |to|listing.year.map_or(false, |y|y <= to))
+            && self.resolution.map_or(true, |r|listing.resolution.map_or(false, |l|l >= r))
+            && self.watched.map_or(true, |w|listing.watched == w)
+            && self.added_since.map_or(true, |since|listing.added.map_or(false, |a|a >= since))
+            && self.runtime_from.map_or(true, |from|listing.runtime.map_or(false, |r|r >= f64::from(from) * 60.0))
+            && self.runtime_to.map_or(true, |to|listing.runtime.map_or(false, |r|r <= f64::from(to) * 60.0))
+            && self.genre.as_ref().map_or(true, |genre|listing.genres.iter().any(|g|g.to_lowercase() == genre.to_lowercase()))
+    }
+
+    /// Whether the lengths of videos need looking up, which means reading the thumbnails of each.
+    fn uses_runtime(&self) -> bool {
+        self.sort == SortKey::Runtime || self.runtime_from.is_some() || self.runtime_to.is_some()
+    }
+
+    /// Whether the metadata of titles needs looking up, which is a file in the cache for each.
+    fn uses_metadata(&self) -> bool {
+        self.sort == SortKey::Rating || self.genre.is_some()
+    }
+
+    /// The page of `items` this query asks for.
+    fn page<'a, T: 'a + Clone>(&self, items: impl Iterator<Item=(&'a T, Listing<'a>)>) -> Result<LibraryPage<T>, Error> {
+        let after =
+            match self.after {
+                Some(ref cursor) => Some(Position::from_cursor(cursor)?),
+                None => None,
+            };
+        let mut found = items
+            .filter(|(_, listing)|self.matches(listing))
+            .map(|(item, listing)|(listing.position(self.sort), item))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b|a.0.cmp(&b.0));
+        if self.descending {
+            found.reverse();
+        }
+
+        let total = found.len();
+        let start = after.map_or(0, |after|{
+            found.iter().take_while(|(p, _)|if self.descending { *p >= after } else { *p <= after }).count()
+        });
+        let end = self.limit.map_or(total, |limit|total.min(start + limit));
+        Ok(LibraryPage {
+            items: found[start..end].iter().map(|(_, item)|(*item).clone()).collect(),
+            total,
+            next: if end < total && end > start { Some(found[end - 1].0.cursor()) } else { None },
+        })
+    }
+}
+
+/// Lists the movies a [LibraryQuery](struct.LibraryQuery.html) asks for.
+pub struct MovieQueryMessage {
+    pub query: LibraryQuery,
+    pub runtime: RuntimeLookup,
+    pub metadata: MetadataLookup,
+}
+
+type MovieQueryResult = Result<LibraryPage<Arc<Movie>>, Error>;
+
+impl Message for MovieQueryMessage {
+    type Result = MovieQueryResult;
+}
+
+impl Handler<MovieQueryMessage> for DataExecutor {
+    type Result = MovieQueryResult;
+
+    fn handle(&mut self, msg: MovieQueryMessage, _: &mut Self::Context) -> Self::Result {
+        let data = self.data();
+        let runtime = Some(&msg.runtime).filter(|_|msg.query.uses_runtime());
+        let metadata = Some(&msg.metadata).filter(|_|msg.query.uses_metadata());
+        msg.query.page(data.movies.iter().map(|m|(m, Listing::movie(m, &data.progress, runtime, metadata))))
+    }
+}
+
+/// Lists the tv shows a [LibraryQuery](struct.LibraryQuery.html) asks for.
+pub struct TvShowQueryMessage {
+    pub query: LibraryQuery,
+    pub runtime: RuntimeLookup,
+    pub metadata: MetadataLookup,
+}
+
+type TvShowQueryResult = Result<LibraryPage<Arc<TvShow>>, Error>;
+
+impl Message for TvShowQueryMessage {
+    type Result = TvShowQueryResult;
+}
+
+impl Handler<TvShowQueryMessage> for DataExecutor {
+    type Result = TvShowQueryResult;
+
+    fn handle(&mut self, msg: TvShowQueryMessage, _: &mut Self::Context) -> Self::Result {
+        let data = self.data();
+        let runtime = Some(&msg.runtime).filter(|_|msg.query.uses_runtime());
+        let metadata = Some(&msg.metadata).filter(|_|msg.query.uses_metadata());
+        msg.query.page(data.tv_shows.iter().map(|s|(s, Listing::tv_show(s, &data.progress, runtime, metadata))))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TvSeries {
     pub series_number: u16,
-    pub episodes: Vec<TvEpisode>
+    pub episodes: Vec<Arc<TvEpisode>>,
+    pub extras: Vec<Extra>,
 }
 
 pub struct TvSeriesMessage {
@@ -124,7 +915,7 @@ pub struct TvSeriesMessage {
     pub series: u16,
 }
 
-type TvSeriesResult = Result<(Arc<TvShow>, TvSeries), Error>;
+type TvSeriesResult = Result<(Arc<TvShow>, Arc<TvSeries>), Error>;
 
 impl Message for TvSeriesMessage {
     type Result = TvSeriesResult;
@@ -134,24 +925,172 @@ impl Handler<TvSeriesMessage> for DataExecutor {
     type Result = TvSeriesResult;
 
     fn handle(&mut self, msg: TvSeriesMessage, _: &mut Self::Context) -> Self::Result {
-        match self.0.tv_shows.iter().find(|s|s.title.eq_ignore_ascii_case(&*msg.title) && s.year == msg.year) {
-            None => self.0.tv_shows.iter().find(|s|s.title.eq_ignore_ascii_case(&*msg.title)),
-            tv_show => tv_show,
-        }
-        .and_then(|tv_show|{
-            let series = tv_show.series.iter().find(|s|s.series_number == msg.series)?;
-            Some((tv_show.clone(), series.clone()))
-        })
-        .ok_or_else(||Error::TvShowNotFound{ title: msg.title })
+        self.data().tv_series(&msg.title, msg.year, msg.series)
+            .ok_or_else(||Error::TvShowNotFound{ title: msg.title })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+/// Files that provide the same episode, best first.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TvEpisode {
     pub episode_number: u16,
+    pub versions: Vec<MediaVersion>,
+}
+
+impl TvEpisode {
+    /// The requested version, or the best one when none is asked for.
+    pub fn version(&self, index: Option<usize>) -> Option<&MediaVersion> {
+        self.versions.get(index.unwrap_or(0))
+    }
+}
+
+/// One file of a movie or episode that is available in several versions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MediaVersion {
+    pub name: String,
+    pub file_path: String,
+    /// Every file of a version split over several parts (`cd1`, `cd2`, ...) in the
+    /// order they are played, `file_path` being the first. Empty for a single file.
+    pub parts: Vec<String>,
+    pub edition: Option<String>,
+    pub quality: Quality,
+    /// Identifies the content of `file_path`, when it could be read.
+    pub fingerprint: Option<Fingerprint>,
+    /// When `file_path` was added to the library, in seconds since the epoch, going by when it
+    /// was last modified.
+    pub added: Option<u64>,
+}
+
+impl MediaVersion {
+    pub fn part_count(&self) -> usize {
+        self.parts.len().max(1)
+    }
+
+    /// The file holding the given part of the version.
+    pub fn part(&self, index: usize) -> Option<&str> {
+        if self.parts.is_empty() {
+            Some(&*self.file_path).filter(|_|index == 0)
+        } else {
+            self.parts.get(index).map(|p|&**p)
+        }
+    }
+}
+
+/// Identifies a file by its content rather than its name, so that it can be
+/// followed when it is renamed or moved.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    /// Hash of the first and last 64KiB of the file.
+    pub hash: String,
+    /// The hash OpenSubtitles looks subtitles up by.
+    pub opensubtitles_hash: String,
+}
+
+/// How far someone got through a movie or episode, as reported by their player.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WatchProgress {
+    /// The part being played, for a version split over several files.
+    pub part: usize,
+    /// Seconds into the part.
+    pub position: f64,
+    /// Length of the part in seconds, when the player knows it.
+    pub duration: Option<f64>,
+    /// Whether it was watched to the end, or close enough to it.
+    pub watched: bool,
+}
+
+impl WatchProgress {
+    /// Whether it counts as watched, which it does once the end credits of the last part
+    /// are reached, or near enough.
+    pub fn finished(&self, part_count: usize) -> bool {
+        self.watched || (self.part + 1 >= part_count && self.duration.map_or(false, |d| self.position >= d * 0.9))
+    }
+}
+
+/// Everything a player needs to play a movie or episode. URLs are relative to the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayInfo {
+    pub title: String,
+    /// One stream for each part of the version, in the order they are played.
+    pub streams: Vec<String>,
+    pub subtitles: Vec<SubtitleInfo>,
+    /// Where the player posts its [WatchProgress](struct.WatchProgress.html).
+    pub progress_url: String,
+    /// Where playback stopped last time, if it was started before.
+    pub progress: Option<WatchProgress>,
+}
+
+/// A subtitle file kept next to a video.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleInfo {
+    /// What follows the video's name in the subtitle's, such as `en` or `en.forced`.
+    pub language: Option<String>,
+    pub url: String,
+}
+
+/// Everything needed to download a movie, or some episodes of a tv show, to watch offline.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownloadInfo {
+    pub items: Vec<DownloadItem>,
+}
+
+/// A movie or episode to download.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DownloadItem {
+    /// Title of the movie or tv show.
+    pub title: String,
+    pub year: Option<u16>,
+    pub series: Option<u16>,
+    pub episode: Option<u16>,
+    /// The files of the version, in the order they are played.
+    pub parts: Vec<DownloadFile>,
+    pub subtitles: Vec<DownloadFile>,
+    /// Where the player posts its [WatchProgress](struct.WatchProgress.html).
+    pub progress_url: String,
+}
+
+/// A file to download.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DownloadFile {
+    /// Relative to the server.
+    pub url: String,
+    /// Where to keep the file, relative to the download folder.
+    pub path: String,
+    pub size: u64,
+    /// CRC-32 of the whole file, when it could be read.
+    pub crc32: Option<String>,
+}
+
+/// A trailer, featurette or other video kept alongside a movie, show or series.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Extra {
+    pub kind: ExtraKind,
+    pub title: String,
     pub file_path: String,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ExtraKind {
+    Trailer,
+    Featurette,
+    #[serde(rename = "Behind The Scenes")]
+    BehindTheScenes,
+    #[serde(rename = "Deleted Scene")]
+    DeletedScene,
+    Interview,
+    Scene,
+    Short,
+    Other,
+}
+
+/// What the file name says about the quality of a video.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Quality {
+    pub resolution: Option<u16>,
+    pub source: Option<String>,
+}
+
 pub struct TvEpisodeMessage {
     pub title: String,
     pub year: Option<u16>,
@@ -159,7 +1098,7 @@ pub struct TvEpisodeMessage {
     pub episode: u16,
 }
 
-type TvEpisodeResult = Result<(Arc<TvShow>, TvSeries, TvEpisode), Error>;
+type TvEpisodeResult = Result<(Arc<TvShow>, Arc<TvSeries>, Arc<TvEpisode>), Error>;
 
 impl Message for TvEpisodeMessage {
     type Result = TvEpisodeResult;
@@ -169,18 +1108,704 @@ impl Handler<TvEpisodeMessage> for DataExecutor {
     type Result = TvEpisodeResult;
 
     fn handle(&mut self, msg: TvEpisodeMessage, _: &mut Self::Context) -> Self::Result {
-        match self.0.tv_shows.iter().find(|s|s.title.eq_ignore_ascii_case(&*msg.title) && s.year == msg.year) {
-            None => self.0.tv_shows.iter().find(|s|s.title.eq_ignore_ascii_case(&*msg.title)),
-            tv_show => tv_show,
+        self.data().tv_episode(&msg.title, msg.year, msg.series, msg.episode)
+            .ok_or_else(||Error::TvShowNotFound{ title: msg.title })
+    }
+}
+
+/// Result of indexing either the whole library or a single `folder` of it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Library {
+    pub folder: Option<String>,
+    pub movies: Vec<Movie>,
+    pub tv_shows: Vec<TvShow>,
+    pub failures: Vec<ParseFailure>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A file the indexer found but could not make sense of.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ParseFailure {
+    pub file_path: String,
+    pub reason: String,
+}
+
+/// A file whose name disagrees with its folder.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Conflict {
+    pub file_path: String,
+    pub reason: String,
+}
+
+/// What changed in the library the last time it was scanned.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ScanReport {
+    pub folder: Option<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Files that were renamed or moved, found by their content.
+    pub moved: Vec<MovedFile>,
+    pub unchanged: usize,
+    pub failures: Vec<ParseFailure>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A file found under a new name with the same content as one that went missing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MovedFile {
+    pub from: String,
+    pub to: String,
+}
+
+pub struct UpdateLibraryMessage(pub Library);
+
+type UpdateLibraryResult = Result<Arc<ScanReport>, Error>;
+
+impl Message for UpdateLibraryMessage {
+    type Result = UpdateLibraryResult;
+}
+
+impl Handler<UpdateLibraryMessage> for DataExecutor {
+    type Result = UpdateLibraryResult;
+
+    fn handle(&mut self, msg: UpdateLibraryMessage, _: &mut Self::Context) -> Self::Result {
+        let mut data = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        let report = data.update(msg.0);
+        if !report.moved.is_empty() {
+            data.save_progress()?;
         }
-        .and_then(|tv_show|{
-            let series = tv_show.series.iter().find(|s|s.series_number == msg.series)?;
-            Some((tv_show, series))
-        })
-        .and_then(|(tv_show, tv_series)|{
-            let tv_episode = tv_series.episodes.iter().find(|s|s.episode_number == msg.episode)?;
-            Some((tv_show.clone(), tv_series.clone(), tv_episode.clone()))
-        })
-        .ok_or_else(||Error::TvShowNotFound{ title: msg.title })
+        Ok(report)
+    }
+}
+
+pub struct ScanReportMessage;
+
+type ScanReportResult = Result<Arc<ScanReport>, Error>;
+
+impl Message for ScanReportMessage {
+    type Result = ScanReportResult;
+}
+
+impl Handler<ScanReportMessage> for DataExecutor {
+    type Result = ScanReportResult;
+
+    fn handle(&mut self, _: ScanReportMessage, _: &mut Self::Context) -> Self::Result {
+        self.data().last_scan.clone().ok_or(Error::ScanReportNotFound)
+    }
+}
+
+pub struct ProgressMessage {
+    pub file_path: String,
+}
+
+type ProgressResult = Result<Option<WatchProgress>, Error>;
+
+impl Message for ProgressMessage {
+    type Result = ProgressResult;
+}
+
+impl Handler<ProgressMessage> for DataExecutor {
+    type Result = ProgressResult;
+
+    fn handle(&mut self, msg: ProgressMessage, _: &mut Self::Context) -> Self::Result {
+        Ok(self.data().progress.get(&msg.file_path).cloned())
+    }
+}
+
+/// How far every file was watched, by path.
+pub struct AllProgressMessage;
+
+type AllProgressResult = Result<HashMap<String, WatchProgress>, Error>;
+
+impl Message for AllProgressMessage {
+    type Result = AllProgressResult;
+}
+
+impl Handler<AllProgressMessage> for DataExecutor {
+    type Result = AllProgressResult;
+
+    fn handle(&mut self, _: AllProgressMessage, _: &mut Self::Context) -> Self::Result {
+        Ok(self.data().progress.clone())
+    }
+}
+
+pub struct UpdateProgressMessage {
+    pub file_path: String,
+    pub progress: WatchProgress,
+}
+
+type UpdateProgressResult = Result<(), Error>;
+
+impl Message for UpdateProgressMessage {
+    type Result = UpdateProgressResult;
+}
+
+impl Handler<UpdateProgressMessage> for DataExecutor {
+    type Result = UpdateProgressResult;
+
+    fn handle(&mut self, msg: UpdateProgressMessage, _: &mut Self::Context) -> Self::Result {
+        let mut data = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        let (file_path, progress) = data.played(&msg.file_path, msg.progress)?;
+        data.progress.insert(file_path, progress);
+        data.save_progress()
+    }
+}
+
+/// The whole library and how far each file of it was watched, to feed to other tools
+/// or to restore watch state from.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LibraryExport {
+    pub movies: Arc<Vec<Arc<Movie>>>,
+    pub tv_shows: Arc<Vec<Arc<TvShow>>>,
+    /// By the path of the first file of a version.
+    pub progress: BTreeMap<String, WatchProgress>,
+}
+
+pub struct ExportMessage;
+
+type ExportResult = Result<LibraryExport, Error>;
+
+impl Message for ExportMessage {
+    type Result = ExportResult;
+}
+
+impl Handler<ExportMessage> for DataExecutor {
+    type Result = ExportResult;
+
+    fn handle(&mut self, _: ExportMessage, _: &mut Self::Context) -> Self::Result {
+        Ok(self.data().export())
+    }
+}
+
+/// How much of an export's watch state could be restored.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Number of files whose progress was restored.
+    pub restored: usize,
+    /// Files whose version is no longer in the library, whose progress went to the best version
+    /// of the same movie or episode instead.
+    #[serde(default)]
+    pub guessed: Vec<MovedFile>,
+    /// Files in the export that are no longer in the library.
+    pub unmatched: Vec<String>,
+}
+
+/// Restores the watch state of an export, leaving the library as it is.
+pub struct ImportProgressMessage(pub LibraryExport);
+
+type ImportProgressResult = Result<ImportReport, Error>;
+
+impl Message for ImportProgressMessage {
+    type Result = ImportProgressResult;
+}
+
+impl Handler<ImportProgressMessage> for DataExecutor {
+    type Result = ImportProgressResult;
+
+    fn handle(&mut self, msg: ImportProgressMessage, _: &mut Self::Context) -> Self::Result {
+        let mut data = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        let report = data.import_progress(msg.0);
+        data.save_progress()?;
+        Ok(report)
+    }
+}
+
+/// Movies and episodes to play one after the other.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub items: Vec<PlaylistItem>,
+}
+
+/// A movie, or a tv show, one series of it or one episode of that, played from the best version.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PlaylistItem {
+    Movie {
+        movie: String,
+        year: Option<u16>,
+    },
+    TvShow {
+        tv_show: String,
+        year: Option<u16>,
+        series: Option<u16>,
+        episode: Option<u16>,
+    },
+}
+
+pub struct AllPlaylistsMessage;
+
+type AllPlaylistsResult = Result<BTreeMap<String, Playlist>, Error>;
+
+impl Message for AllPlaylistsMessage {
+    type Result = AllPlaylistsResult;
+}
+
+impl Handler<AllPlaylistsMessage> for DataExecutor {
+    type Result = AllPlaylistsResult;
+
+    fn handle(&mut self, _: AllPlaylistsMessage, _: &mut Self::Context) -> Self::Result {
+        Ok(self.data().playlists.clone())
+    }
+}
+
+pub struct PlaylistMessage {
+    pub name: String,
+}
+
+type PlaylistResult = Result<Playlist, Error>;
+
+impl Message for PlaylistMessage {
+    type Result = PlaylistResult;
+}
+
+impl Handler<PlaylistMessage> for DataExecutor {
+    type Result = PlaylistResult;
+
+    fn handle(&mut self, msg: PlaylistMessage, _: &mut Self::Context) -> Self::Result {
+        self.data().playlists.get(&msg.name).cloned().ok_or_else(||Error::PlaylistNotFound{ name: msg.name })
+    }
+}
+
+/// Saves a playlist, or deletes it when there is none.
+pub struct SavePlaylistMessage {
+    pub name: String,
+    pub playlist: Option<Playlist>,
+}
+
+type SavePlaylistResult = Result<(), Error>;
+
+impl Message for SavePlaylistMessage {
+    type Result = SavePlaylistResult;
+}
+
+impl Handler<SavePlaylistMessage> for DataExecutor {
+    type Result = SavePlaylistResult;
+
+    fn handle(&mut self, msg: SavePlaylistMessage, _: &mut Self::Context) -> Self::Result {
+        let mut data = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        match msg.playlist {
+            Some(playlist) => {
+                data.playlists.insert(msg.name, playlist);
+            },
+            None => {
+                data.playlists.remove(&msg.name).ok_or_else(||Error::PlaylistNotFound{ name: msg.name })?;
+            },
+        }
+        data.save_playlists()
+    }
+}
+
+#[cfg(test)]
+fn test_version(file_path: &str) -> MediaVersion {
+    MediaVersion {
+        name: "Original".to_owned(),
+        file_path: file_path.to_owned(),
+        parts: vec![],
+        edition: None,
+        quality: Quality::default(),
+        fingerprint: None,
+        added: None,
+    }
+}
+
+#[cfg(test)]
+fn test_library(folder: Option<&str>, movies: &[(&str, &str)], tv_shows: &[(&str, &str)]) -> Library {
+    Library {
+        folder: folder.map(str::to_owned),
+        movies: movies.iter()
+            .map(|&(title, file_path)| Movie { title: title.to_owned(), year: None, versions: vec![test_version(file_path)], extras: vec![] })
+            .collect(),
+        // Shows with nothing but a trailer, which is what went missing from rescans.
+        tv_shows: tv_shows.iter()
+            .map(|&(title, file_path)| TvShow {
+                title: title.to_owned(),
+                year: None,
+                series: vec![],
+                extras: vec![Extra { kind: ExtraKind::Trailer, title: "Trailer".to_owned(), file_path: file_path.to_owned() }],
+            })
+            .collect(),
+        ..Library::default()
+    }
+}
+
+#[test]
+fn update() {
+    let mut data_set = DataSet::default();
+    let report = data_set.update(test_library(
+        None,
+        &[("Alien", "/movies/Alien/Alien.mp4"), ("Heat", "/movies/Heat/Heat.mp4")],
+        &[("Dark", "/tv/Dark/Trailer.mp4"), ("Lost", "/tv/Lost/Trailer.mp4")],
+    ));
+    assert_eq!(4, report.added.len());
+    assert_eq!(vec!["Alien", "Heat"], data_set.movies().iter().map(|m| &*m.title).collect::<Vec<_>>());
+    assert_eq!(vec!["Dark", "Lost"], data_set.tv_shows().iter().map(|s| &*s.title).collect::<Vec<_>>());
+
+    // Rescanning one show's folder replaces it rather than adding it again, and leaves the rest.
+    let report = data_set.update(test_library(Some("/tv/Dark"), &[], &[("Dark", "/tv/Dark/Trailer.mp4")]));
+    assert_eq!((0, 0, 1), (report.added.len(), report.removed.len(), report.unchanged));
+    assert_eq!(vec!["Dark", "Lost"], data_set.tv_shows().iter().map(|s| &*s.title).collect::<Vec<_>>());
+    assert_eq!(2, data_set.movies().len());
+
+    let report = data_set.update(test_library(Some("/tv/Dark"), &[], &[]));
+    assert_eq!(vec!["/tv/Dark/Trailer.mp4"], report.removed);
+    assert_eq!(vec!["Lost"], data_set.tv_shows().iter().map(|s| &*s.title).collect::<Vec<_>>());
+
+    // A full rescan replaces everything.
+    let report = data_set.update(test_library(None, &[("Alien", "/movies/Alien/Alien.mp4")], &[]));
+    assert_eq!(vec!["/movies/Heat/Heat.mp4", "/tv/Lost/Trailer.mp4"], report.removed);
+    assert_eq!(1, report.unchanged);
+    assert_eq!(vec!["Alien"], data_set.movies().iter().map(|m| &*m.title).collect::<Vec<_>>());
+    assert!(data_set.tv_shows().is_empty());
+    assert!(data_set.movie("heat", None).is_none());
+}
+
+#[test]
+fn moves_between_folders() {
+    let library = |folder: &str, movies: &[(&str, &str)]| {
+        let mut library = test_library(Some(folder), movies, &[]);
+        for movie in &mut library.movies {
+            let hash = movie.title.to_owned();
+            movie.versions[0].fingerprint = Some(Fingerprint { size: 1, hash, opensubtitles_hash: String::new() });
+        }
+        library
+    };
+    let watched = WatchProgress { watched: true, ..WatchProgress::default() };
+
+    // Rescanning the folder it left, then the one it went to.
+    let mut data_set = DataSet::default();
+    data_set.update(library("/a", &[("Alien", "/a/Alien.mp4")]));
+    data_set.progress.insert("/a/Alien.mp4".to_owned(), watched.clone());
+    let report = data_set.update(library("/a", &[]));
+    assert_eq!(vec!["/a/Alien.mp4"], report.removed);
+    let report = data_set.update(library("/b", &[("Alien", "/b/Alien.mp4")]));
+    assert_eq!(vec![MovedFile { from: "/a/Alien.mp4".to_owned(), to: "/b/Alien.mp4".to_owned() }], report.moved);
+    assert!(report.added.is_empty());
+    assert_eq!(Some(&watched), data_set.progress.get("/b/Alien.mp4"));
+
+    // Rescanning the folder it went to first.
+    let mut data_set = DataSet::default();
+    data_set.update(library("/a", &[("Heat", "/a/Heat.mp4")]));
+    data_set.progress.insert("/a/Heat.mp4".to_owned(), watched.clone());
+    let report = data_set.update(library("/b", &[("Heat", "/b/Heat.mp4")]));
+    assert_eq!(vec!["/b/Heat.mp4"], report.added);
+    let report = data_set.update(library("/a", &[]));
+    assert_eq!(vec![MovedFile { from: "/a/Heat.mp4".to_owned(), to: "/b/Heat.mp4".to_owned() }], report.moved);
+    assert!(report.removed.is_empty());
+    assert_eq!(Some(&watched), data_set.progress.get("/b/Heat.mp4"));
+}
+
+#[test]
+fn import_progress() {
+    let version = |file_path: &str, name: &str, hash: Option<&str>| MediaVersion {
+        name: name.to_owned(),
+        fingerprint: hash.map(|h| Fingerprint { size: 1, hash: h.to_owned(), opensubtitles_hash: String::new() }),
+        ..test_version(file_path)
+    };
+    let movie = |title: &str, versions: Vec<MediaVersion>| Arc::new(Movie { title: title.to_owned(), year: None, versions, extras: vec![] });
+    let progress = |position| WatchProgress { position, ..WatchProgress::default() };
+
+    let mut data_set = DataSet::default();
+    data_set.update(Library {
+        movies: vec![
+            Movie { title: "Up".to_owned(), year: None, versions: vec![version("/movies/Up.mp4", "Original", None)], extras: vec![] },
+            Movie { title: "Alien".to_owned(), year: None, versions: vec![version("/movies/Alien 1080p.mp4", "1080p", None), version("/movies/Alien (1979).mp4", "Original", Some("alien"))], extras: vec![] },
+            Movie { title: "Heat".to_owned(), year: None, versions: vec![version("/movies/Heat 1080p.mp4", "1080p", None), version("/movies/Heat 720p.mp4", "720p", None)], extras: vec![] },
+            Movie { title: "Blade Runner".to_owned(), year: None, versions: vec![version("/movies/Blade Runner.mp4", "Original", None)], extras: vec![] },
+        ],
+        ..Library::default()
+    });
+
+    let export = LibraryExport {
+        movies: Arc::new(vec![
+            movie("Up", vec![version("/movies/Up.mp4", "Original", None)]),
+            movie("Alien", vec![version("/old/Alien.mp4", "Renamed", Some("alien"))]),
+            movie("heat", vec![version("/old/Heat 720p.mp4", "720p", None)]),
+            movie("Blade Runner", vec![version("/old/Blade Runner - Final Cut.mp4", "Final Cut", None)]),
+            movie("Gone", vec![version("/old/Gone.mp4", "Original", None)]),
+        ]),
+        tv_shows: Arc::new(vec![]),
+        progress: vec![
+            ("/movies/Up.mp4", progress(1.0)),
+            ("/old/Alien.mp4", progress(2.0)),
+            ("/old/Heat 720p.mp4", progress(3.0)),
+            ("/old/Blade Runner - Final Cut.mp4", progress(4.0)),
+            ("/old/Gone.mp4", progress(5.0)),
+        ].into_iter().map(|(path, progress)| (path.to_owned(), progress)).collect(),
+    };
+    let report = data_set.import_progress(export);
+
+    // The same path, the same content and the version of the same name are restored.
+    assert_eq!(3, report.restored);
+    assert_eq!(Some(&progress(1.0)), data_set.progress.get("/movies/Up.mp4"));
+    assert_eq!(Some(&progress(2.0)), data_set.progress.get("/movies/Alien (1979).mp4"));
+    assert_eq!(Some(&progress(3.0)), data_set.progress.get("/movies/Heat 720p.mp4"));
+    // The best version, which may be another cut, is only a guess.
+    assert_eq!(vec![MovedFile { from: "/old/Blade Runner - Final Cut.mp4".to_owned(), to: "/movies/Blade Runner.mp4".to_owned() }], report.guessed);
+    assert_eq!(Some(&progress(4.0)), data_set.progress.get("/movies/Blade Runner.mp4"));
+    assert_eq!(vec!["/old/Gone.mp4"], report.unmatched);
+    assert_eq!(4, data_set.progress.len());
+}
+
+#[cfg(test)]
+fn test_listing<'a>(title: &'a str, year: Option<u16>, path: &'a str) -> Listing<'a> {
+    Listing { title, year, resolution: None, watched: false, added: None, runtime: None, path, genres: vec![], rating: None }
+}
+
+#[test]
+fn sort_titles() {
+    assert_eq!("matrix", sort_title("The Matrix"));
+    assert_eq!("beautiful mind", sort_title("A Beautiful Mind"));
+    assert_eq!("american werewolf in london", sort_title("An American Werewolf in London"));
+    assert_eq!("theodore rex", sort_title("Theodore Rex"));
+    assert_eq!("the", sort_title("The"));
+}
+
+#[test]
+fn query_matches() {
+    let listing = Listing {
+        resolution: Some(1080),
+        watched: true,
+        added: Some(1000),
+        runtime: Some(7020.0),
+        genres: vec!["Horror".to_owned(), "Science Fiction".to_owned()],
+        rating: Some(8.1),
+        ..test_listing("Alien", Some(1979), "/movies/Alien.mp4")
+    };
+    let unknown = test_listing("Alien", None, "/movies/Alien.mp4");
+    let query = |f: &dyn Fn(&mut LibraryQuery)| {
+        let mut query = LibraryQuery::default();
+        f(&mut query);
+        query
+    };
+
+    let cases: Vec<(LibraryQuery, bool)> = vec![
+        (LibraryQuery::default(), true),
+        (query(&|q| q.year_from = Some(1979)), true),
+        (query(&|q| q.year_from = Some(1980)), false),
+        (query(&|q| q.year_to = Some(1979)), true),
+        (query(&|q| q.year_to = Some(1978)), false),
+        (query(&|q| q.resolution = Some(720)), true),
+        (query(&|q| q.resolution = Some(2160)), false),
+        (query(&|q| q.watched = Some(true)), true),
+        (query(&|q| q.watched = Some(false)), false),
+        (query(&|q| q.added_since = Some(1000)), true),
+        (query(&|q| q.added_since = Some(1001)), false),
+        (query(&|q| q.runtime_from = Some(117)), true),
+        (query(&|q| q.runtime_from = Some(118)), false),
+        (query(&|q| q.runtime_to = Some(117)), true),
+        (query(&|q| q.runtime_to = Some(116)), false),
+        (query(&|q| q.genre = Some("Horror".to_owned())), true),
+        (query(&|q| q.genre = Some("science fiction".to_owned())), true),
+        (query(&|q| q.genre = Some("Comedy".to_owned())), false),
+        (query(&|q| q.genre = Some("Science".to_owned())), false),
+        (query(&|q| { q.year_from = Some(1970); q.resolution = Some(2160); }), false),
+    ];
+    for (query, expected) in cases {
+        assert_eq!(expected, query.matches(&listing), "{:?}", query);
+    }
+
+    // What isn't known doesn't match anything asking for it.
+    assert!(LibraryQuery::default().matches(&unknown));
+    assert!(query(&|q| q.watched = Some(false)).matches(&unknown));
+    for query in &[query(&|q| q.year_to = Some(2000)), query(&|q| q.resolution = Some(480)), query(&|q| q.added_since = Some(0)), query(&|q| q.runtime_to = Some(1000)), query(&|q| q.genre = Some("Horror".to_owned()))] {
+        assert!(!query.matches(&unknown), "{:?}", query);
+    }
+}
+
+#[test]
+fn cursors() {
+    let positions = vec![
+        Position { key: None, title: "alien".to_owned(), year: None, path: String::new() },
+        Position { key: Some(1_500_000_000), title: "alien".to_owned(), year: Some(1979), path: "/movies/Alien.mp4".to_owned() },
+        Position { key: Some(7020), title: "star wars: episode iv".to_owned(), year: Some(1977), path: "/movies/Star Wars: IV/Star Wars.mp4".to_owned() },
+        Position { key: None, title: "léon".to_owned(), year: Some(1994), path: "/movies/Léon.mp4".to_owned() },
+    ];
+    for position in positions {
+        assert_eq!(Ok(position.clone()), Position::from_cursor(&position.cursor()).map_err(|e|e.to_string()));
+    }
+
+    for cursor in &["", "1:2", "1:2:alien", "x:1979:5:alien", "1:1979:x:alien", "1:1979:9:alien", "::2:léon", "1:70000:5:alien", "1:1979:5:alien:extra:"] {
+        match Position::from_cursor(cursor) {
+            Err(Error::CursorNotValid { .. }) => (),
+            Ok(position) if *cursor == "1:1979:5:alien:extra:" => assert_eq!(":extra:", position.path),
+            result => assert!(false, "{:?}: {:?}", cursor, result),
+        }
+    }
+}
+
+#[test]
+fn pages() {
+    let items = vec![
+        ("The Thing 1982", "The Thing", Some(1982), "/movies/b/The Thing.mp4"),
+        ("Alien", "Alien", Some(1979), "/movies/Alien.mp4"),
+        ("The Thing 1982 again", "The Thing", Some(1982), "/movies/a/The Thing.mp4"),
+        ("A Bug's Life", "A Bug's Life", Some(1998), "/movies/A Bug's Life.mp4"),
+        ("Cube", "Cube", None, "/movies/Cube.mp4"),
+    ];
+    let page = |query: &LibraryQuery| {
+        let listings = items.iter().map(|(name, title, year, path)| (name, test_listing(title, *year, path)));
+        query.page(listings).map(|page| (page.items, page.total, page.next))
+    };
+    let every_page = |query: LibraryQuery| {
+        let mut query = LibraryQuery { limit: Some(2), ..query };
+        let mut names = vec![];
+        loop {
+            let (page, total, next) = page(&query).unwrap();
+            assert_eq!(5, total);
+            names.extend(page);
+            match next {
+                Some(next) => query.after = Some(next),
+                None => return names,
+            }
+        }
+    };
+
+    // The two that are the same but for their path still both come, one page after the other.
+    let ascending = vec!["Alien", "A Bug's Life", "Cube", "The Thing 1982 again", "The Thing 1982"];
+    assert_eq!(ascending, page(&LibraryQuery::default()).unwrap().0);
+    assert_eq!(ascending, every_page(LibraryQuery::default()));
+    let descending = ascending.iter().rev().cloned().collect::<Vec<_>>();
+    assert_eq!(descending, every_page(LibraryQuery { descending: true, ..LibraryQuery::default() }));
+    assert_eq!(vec!["Cube", "Alien", "The Thing 1982 again", "The Thing 1982", "A Bug's Life"],
+        every_page(LibraryQuery { sort: SortKey::Year, ..LibraryQuery::default() }));
+    assert_eq!((vec!["Alien", "A Bug's Life"], 5, Some(Position { key: None, title: "bug's life".to_owned(), year: Some(1998), path: "/movies/A Bug's Life.mp4".to_owned() }.cursor())),
+        page(&LibraryQuery { limit: Some(2), ..LibraryQuery::default() }).unwrap());
+
+    // A cursor for something that has gone since carries on from where it would have been.
+    let gone = Position { key: None, title: "cabin in the woods".to_owned(), year: Some(2011), path: "/movies/The Cabin in the Woods.mp4".to_owned() };
+    assert_eq!(vec!["Cube", "The Thing 1982 again", "The Thing 1982"], page(&LibraryQuery { after: Some(gone.cursor()), ..LibraryQuery::default() }).unwrap().0);
+
+    match page(&LibraryQuery { after: Some("alien".to_owned()), ..LibraryQuery::default() }) {
+        Err(Error::CursorNotValid { ref cursor }) if cursor == "alien" => (),
+        result => assert!(false, "{:?}", result),
+    }
+}
+
+#[test]
+fn runtimes() {
+    let movie = |title: &str, file_path: &str| Arc::new(Movie { title: title.to_owned(), year: None, versions: vec![test_version(file_path)], extras: vec![] });
+    let movies = vec![movie("Alien", "/movies/Alien.mp4"), movie("Heat", "/movies/Heat.mp4"), movie("Cube", "/movies/Cube.mp4")];
+    let mut progress = HashMap::new();
+    progress.insert("/movies/Cube.mp4".to_owned(), WatchProgress { duration: Some(5400.0), ..WatchProgress::default() });
+    let runtime: RuntimeLookup = Arc::new(|file_path: &str| match file_path {
+        "/movies/Alien.mp4" => Some(7020.0),
+        "/movies/Heat.mp4" => Some(10200.0),
+        _ => None,
+    });
+
+    let titles = |query: LibraryQuery| {
+        let runtime = Some(&runtime).filter(|_| query.uses_runtime());
+        let page = query.page(movies.iter().map(|m| (m, Listing::movie(m, &progress, runtime, None)))).unwrap();
+        page.items.iter().map(|m| m.title.to_owned()).collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["Cube", "Alien", "Heat"], titles(LibraryQuery { sort: SortKey::Runtime, ..LibraryQuery::default() }));
+    assert_eq!(vec!["Alien", "Cube"], titles(LibraryQuery { runtime_to: Some(120), ..LibraryQuery::default() }));
+    assert_eq!(vec!["Heat"], titles(LibraryQuery { runtime_from: Some(121), ..LibraryQuery::default() }));
+    assert!(!LibraryQuery::default().uses_runtime());
+}
+
+#[test]
+fn ratings() {
+    let movie = |title: &str| Arc::new(Movie { title: title.to_owned(), year: None, versions: vec![test_version(&format!("/movies/{}.mp4", title))], extras: vec![] });
+    let movies = vec![movie("Alien"), movie("Heat"), movie("Cube"), movie("Dune")];
+    let metadata: MetadataLookup = Arc::new(|title: &str, _| match title {
+        "Alien" => Some(TitleMetadata { genres: vec!["Horror".to_owned()], rating: Some(8.1) }),
+        "Heat" => Some(TitleMetadata { genres: vec!["Crime".to_owned()], rating: Some(7.9) }),
+        "Cube" => Some(TitleMetadata { genres: vec!["Horror".to_owned()], rating: None }),
+        _ => None,
+    });
+
+    let titles = |query: LibraryQuery| {
+        let metadata = Some(&metadata).filter(|_| query.uses_metadata());
+        let page = query.page(movies.iter().map(|m| (m, Listing::movie(m, &HashMap::new(), None, metadata)))).unwrap();
+        page.items.iter().map(|m| m.title.to_owned()).collect::<Vec<_>>()
+    };
+    // Unrated titles, by title among themselves, go before the rated ones and so last when descending.
+    assert_eq!(vec!["Cube", "Dune", "Heat", "Alien"], titles(LibraryQuery { sort: SortKey::Rating, ..LibraryQuery::default() }));
+    assert_eq!(vec!["Alien", "Heat", "Dune", "Cube"], titles(LibraryQuery { sort: SortKey::Rating, descending: true, ..LibraryQuery::default() }));
+    assert_eq!(vec!["Alien", "Cube"], titles(LibraryQuery { genre: Some("horror".to_owned()), ..LibraryQuery::default() }));
+    assert!(!LibraryQuery::default().uses_metadata());
+}
+
+#[test]
+fn title_index() {
+    let index = TitleIndex::new(vec![
+        ("Alien", Some(1979)), ("Heat", Some(1986)), ("Heat", Some(1995)), ("Dark", None), ("Alien", Some(2019)),
+        ("Spider-Man: Homecoming", Some(2017)), ("Amélie", Some(2001)),
+    ].into_iter());
+
+    assert_eq!(Some(2), index.find("Heat", Some(1995)));
+    assert_eq!(Some(1), index.find("Heat", Some(1986)));
+    // Without the year, or with one it isn't from, the first with the title.
+    assert_eq!(Some(1), index.find("Heat", None));
+    assert_eq!(Some(1), index.find("Heat", Some(2000)));
+    assert_eq!(Some(0), index.find("ALIEN", None));
+    assert_eq!(Some(4), index.find("alien", Some(2019)));
+    assert_eq!(Some(3), index.find("dark", None));
+    assert_eq!(Some(3), index.find("Dark", Some(2017)));
+    assert_eq!(None, index.find("Alien 3", None));
+    assert_eq!(None, index.find("Alie", Some(1979)));
+    // Case beyond ASCII, punctuation and spacing don't matter.
+    assert_eq!(Some(5), index.find("spider man  homecoming", None));
+    assert_eq!(Some(5), index.find("Spider-Man - Homecoming", Some(2017)));
+    assert_eq!(Some(6), index.find("AMÉLIE", None));
+    assert_eq!(None, index.find("Amelie", None));
+    assert_eq!(None, index.find_exact("Heat", Some(2000)));
+    assert_eq!(Some(2), index.find_exact("heat", Some(1995)));
+
+    assert_eq!("spider-man-homecoming-2017", title_id("Spider-Man: Homecoming", Some(2017)));
+    assert_eq!("dark", title_id("Dark", None));
+    assert_eq!(Some(5), index.find_id("spider-man-homecoming-2017"));
+    assert_eq!(Some(3), index.find_id("dark"));
+    assert_eq!(None, index.find_id("heat"));
+}
+
+#[test]
+fn file_owners() {
+    let mut library = test_library(None, &[("Alien", "/movies/Alien.mp4")], &[]);
+    library.movies[0].versions[0].parts = vec!["/movies/Alien.mp4".to_owned(), "/movies/Alien cd2.mp4".to_owned()];
+    let episode = |number, file_path| Arc::new(TvEpisode { episode_number: number, versions: vec![test_version(file_path)] });
+    library.tv_shows.push(TvShow {
+        title: "Dark".to_owned(),
+        year: Some(2017),
+        series: vec![Arc::new(TvSeries { series_number: 1, episodes: vec![episode(1, "/tv/Dark/S01E01.mp4"), episode(2, "/tv/Dark/S01E02.mp4")], extras: vec![] })],
+        extras: vec![],
+    });
+    let mut data_set = DataSet::default();
+    data_set.update(library);
+
+    match data_set.file_owner("/movies/Alien cd2.mp4") {
+        Some(owner @ FileOwner::Movie(_)) => assert_eq!("/movies/Alien.mp4", owner.version("/movies/Alien cd2.mp4").unwrap().file_path),
+        owner => assert!(false, "{:?}", owner),
     }
+    match data_set.file_owner("/tv/Dark/S01E02.mp4") {
+        Some(FileOwner::TvEpisode(tv_show, tv_series, tv_episode)) => assert_eq!(("Dark", 1, 2), (&*tv_show.title, tv_series.series_number, tv_episode.episode_number)),
+        owner => assert!(false, "{:?}", owner),
+    }
+    assert_eq!(None, data_set.file_owner("/movies/Heat.mp4"));
+    assert_eq!("Dark", data_set.tv_show_by_id("dark-2017").unwrap().title);
+    assert_eq!("Alien", data_set.movie_by_id("alien").unwrap().title);
+
+    // Progress goes to the first file of the version, and is only kept for files of the library.
+    let progress = WatchProgress { part: 1, position: 95.0, duration: Some(100.0), watched: false };
+    let (file_path, progress) = data_set.played("/movies/Alien cd2.mp4", progress).unwrap();
+    assert_eq!(("/movies/Alien.mp4", true), (&*file_path, progress.watched));
+    match data_set.played("/movies/Heat.mp4", WatchProgress::default()) {
+        Err(Error::FileNotFound { ref file_path }) if file_path == "/movies/Heat.mp4" => (),
+        result => assert!(false, "{:?}", result),
+    }
+}
+
+#[test]
+fn finished() {
+    let progress = |part, position, duration| WatchProgress { part, position, duration, watched: false };
+
+    assert!(progress(0, 90.0, Some(100.0)).finished(1));
+    assert!(!progress(0, 89.0, Some(100.0)).finished(1));
+    // Only the end of the last part counts.
+    assert!(!progress(0, 95.0, Some(100.0)).finished(2));
+    assert!(progress(1, 95.0, Some(100.0)).finished(2));
+    // Without a duration there is no telling, unless the player said so.
+    assert!(!progress(0, 1e6, None).finished(1));
+    assert!(WatchProgress { watched: true, ..progress(0, 0.0, None) }.finished(1));
 }
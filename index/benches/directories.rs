@@ -0,0 +1,67 @@
+//! Compares scanning a synthetic library one folder at a time, as the indexer
+//! used to, with scanning several folders at the same time.
+//!
+//! The library is written to the temp directory the first time, set
+//! `CAROLUS_BENCH_LIBRARY` to scan a real one instead.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use index::{NamingPatterns, ScanOptions};
+
+const MOVIES: usize = 2_000;
+const SHOWS: usize = 200;
+const SEASONS: usize = 4;
+const EPISODES: usize = 10;
+
+/// Writes empty files laid out like a library, returning the movie and tv directories.
+fn library() -> (PathBuf, PathBuf) {
+    if let Ok(root) = env::var("CAROLUS_BENCH_LIBRARY") {
+        let root = PathBuf::from(root);
+        return (root.join("movies"), root.join("tv"));
+    }
+
+    let root = env::temp_dir().join(format!("carolus-bench-{}-{}", MOVIES, SHOWS * SEASONS * EPISODES));
+    let (movies, tv) = (root.join("movies"), root.join("tv"));
+    if root.exists() {
+        return (movies, tv);
+    }
+
+    for movie in 0..MOVIES {
+        let folder = movies.join(format!("Movie {} ({})", movie, 1950 + movie % 70));
+        fs::create_dir_all(folder.join("Trailers")).unwrap();
+        fs::write(folder.join(format!("Movie.{}.{}.1080p.BluRay.x264-GROUP.mp4", movie, 1950 + movie % 70)), b"").unwrap();
+        fs::write(folder.join("Trailers/Teaser.mp4"), b"").unwrap();
+        fs::write(folder.join("cover.jpg"), b"").unwrap();
+    }
+    for show in 0..SHOWS {
+        for season in 1..=SEASONS {
+            let folder = tv.join(format!("Show {}", show)).join(format!("Season {}", season));
+            fs::create_dir_all(&folder).unwrap();
+            for episode in 1..=EPISODES {
+                fs::write(folder.join(format!("Show {} S{:02}E{:02} 720p HDTV.mp4", show, season, episode)), b"").unwrap();
+            }
+        }
+    }
+    (movies, tv)
+}
+
+fn directories(c: &mut Criterion) {
+    let (movies, tv) = library();
+    let (movies, tv) = (movies.to_string_lossy().into_owned(), tv.to_string_lossy().into_owned());
+
+    c.bench_function_over_inputs("directories", move |b, &&jobs| {
+        let scan = ScanOptions { jobs, ..ScanOptions::default() };
+        b.iter(|| index::directories(Some(&movies), Some(&tv), &NamingPatterns::default(), &scan).unwrap())
+    }, &[1, 2, 4, 8]);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = directories
+}
+criterion_main!(benches);
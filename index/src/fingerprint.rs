@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use failure::{Error, format_err};
+use log::{trace, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use data::Fingerprint;
+
+/// How much of each end of a file is read, as OpenSubtitles does.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// How much of a file is read at a time for its checksum.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Reads the start and end of a file to identify its content. The OpenSubtitles
+/// hash is the size plus the sum of every 64-bit word read, the other hash is
+/// an FNV-1a hash of the same bytes.
+pub fn fingerprint(path: &Path) -> io::Result<Fingerprint> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut head = vec![];
+    (&mut file).take(CHUNK_SIZE).read_to_end(&mut head)?;
+    let mut tail = vec![];
+    file.seek(SeekFrom::Start(size.saturating_sub(CHUNK_SIZE)))?;
+    file.take(CHUNK_SIZE).read_to_end(&mut tail)?;
+
+    Ok(Fingerprint {
+        size,
+        hash: format!("{:016x}", fnv1a(&[&head, &tail])),
+        opensubtitles_hash: format!("{:016x}", opensubtitles_hash(size, &head, &tail)),
+    })
+}
+
+fn fnv1a(chunks: &[&[u8]]) -> u64 {
+    chunks.iter().flat_map(|c| c.iter()).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn opensubtitles_hash(size: u64, head: &[u8], tail: &[u8]) -> u64 {
+    let words = |chunk: &[u8]| chunk.chunks(8).fold(0u64, |sum, word| {
+        let word = word.iter().rev().fold(0u64, |word, byte| (word << 8) | u64::from(*byte));
+        sum.wrapping_add(word)
+    });
+    size.wrapping_add(words(head)).wrapping_add(words(tail))
+}
+
+/// The CRC-32 of a whole file, as hex, for downloads to be checked against.
+pub fn checksum(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    /// Seconds since the epoch the file was last modified.
+    modified: u64,
+    fingerprint: Fingerprint,
+    /// Worked out the first time a file is downloaded, as it means reading all of it.
+    #[serde(default)]
+    checksum: Option<String>,
+}
+
+/// Remembers the fingerprints already worked out, so only new or changed files
+/// are read again on the next scan. Kept in a JSON file when given one.
+#[derive(Debug, Default)]
+pub struct FingerprintCache {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl FingerprintCache {
+    /// Loads the fingerprints saved at `path`, starting afresh when there are none.
+    pub fn load(path: PathBuf) -> Self {
+        let entries = File::open(&path).ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default();
+        FingerprintCache { path: Some(path), entries: Mutex::new(entries) }
+    }
+
+    /// The fingerprint of a file, or `None` when it can't be read.
+    pub fn get(&self, path: &Path) -> Option<Fingerprint> {
+        let (key, size, modified) = identify(path)?;
+        if let Some(entry) = self.entries.lock().ok()?.get(key) {
+            if entry.size == size && entry.modified == modified {
+                return Some(entry.fingerprint.clone());
+            }
+        }
+
+        match fingerprint(path) {
+            Ok(fingerprint) => {
+                trace!("Fingerprinted {:?}: {}", path, fingerprint.hash);
+                let entry = CacheEntry { size, modified, fingerprint: fingerprint.clone(), checksum: None };
+                self.entries.lock().ok()?.insert(key.to_owned(), entry);
+                Some(fingerprint)
+            },
+            Err(err) => {
+                warn!("Could not fingerprint {:?}, err: {}", path, err);
+                None
+            },
+        }
+    }
+
+    /// The [checksum](fn.checksum.html) of a file, or `None` when it can't be read.
+    pub fn checksum(&self, path: &Path) -> Option<String> {
+        let (key, size, modified) = identify(path)?;
+        if let Some(entry) = self.entries.lock().ok()?.get(key) {
+            if entry.size == size && entry.modified == modified && entry.checksum.is_some() {
+                return entry.checksum.clone();
+            }
+        }
+
+        let fingerprint = self.get(path)?;
+        match checksum(path) {
+            Ok(checksum) => {
+                trace!("Checksum of {:?}: {}", path, checksum);
+                let entry = CacheEntry { size, modified, fingerprint, checksum: Some(checksum.clone()) };
+                self.entries.lock().ok()?.insert(key.to_owned(), entry);
+                Some(checksum)
+            },
+            Err(err) => {
+                warn!("Could not work out the checksum of {:?}, err: {}", path, err);
+                None
+            },
+        }
+    }
+
+    /// Saves the fingerprints of the files that still exist.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut entries = self.entries.lock().map_err(|_| format_err!("the fingerprint cache is poisoned"))?;
+        entries.retain(|file_path, _| Path::new(file_path).exists());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        serde_json::to_writer(File::create(path)?, &*entries)?;
+        Ok(())
+    }
+}
+
+/// The cache key, size and modification time (in seconds since the epoch) of a file.
+fn identify(path: &Path) -> Option<(&str, u64, u64)> {
+    let key = path.to_str()?;
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    Some((key, metadata.len(), modified))
+}
+
+#[test]
+fn fingerprints() {
+    let path = std::env::temp_dir().join(format!("carolus-fingerprint-{}", std::process::id()));
+    let content = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write(&path, &content).unwrap();
+
+    let first = fingerprint(&path).unwrap();
+    assert_eq!(200_000, first.size);
+    assert_eq!(16, first.hash.len());
+    assert_eq!(format!("{:016x}", opensubtitles_hash(200_000, &content[..65536], &content[200_000 - 65536..])), first.opensubtitles_hash);
+
+    // The middle of the file isn't read, the ends are.
+    let mut middle = content.clone();
+    middle[100_000] ^= 1;
+    fs::write(&path, &middle).unwrap();
+    assert_eq!(first, fingerprint(&path).unwrap());
+
+    let mut end = content.clone();
+    end[199_999] ^= 1;
+    fs::write(&path, &end).unwrap();
+    assert_ne!(first, fingerprint(&path).unwrap());
+
+    fs::write(&path, b"short").unwrap();
+    assert_eq!(5, fingerprint(&path).unwrap().size);
+    assert_eq!("8f2890a2", checksum(&path).unwrap());
+
+    let cache = FingerprintCache::default();
+    assert_eq!(Some("8f2890a2".to_owned()), cache.checksum(&path));
+    assert_eq!(Some(5), cache.get(&path).map(|f| f.size));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn opensubtitles_hashes() {
+    // Sums little-endian words, padding the last one with zeros.
+    assert_eq!(3 + 1 + 0x0201, opensubtitles_hash(3, &[1, 0, 0, 0, 0, 0, 0, 0], &[1, 2]));
+    assert_eq!(0, opensubtitles_hash(1, &[0xff; 8], &[]));
+}
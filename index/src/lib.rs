@@ -1,104 +1,422 @@
 
 
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::btree_map::Entry;
 use std::ffi::OsStr;
-use std::fs::read_dir;
-use std::iter::FromIterator;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use failure::{Error, format_err};
-use globwalk;
 use log::{trace, warn};
-use lazy_static::lazy_static;
+use rayon::prelude::*;
 
-use data::{Movie, TvShow, TvSeries, TvEpisode};
+use data::{Conflict, Extra, Library, MediaVersion, Movie, ParseFailure, TvShow, TvSeries, TvEpisode};
 
+use crate::parse_extra::ExtraFile;
+use crate::scan::Scanner;
+
+pub use crate::fingerprint::FingerprintCache;
+pub use crate::naming::NamingPatterns;
+pub use crate::scan::ScanOptions;
+pub use crate::subtitles::{SubtitleFile, subtitle_files};
+
+mod fingerprint;
+mod naming;
+mod parse_extra;
 mod parse_movie;
+mod parse_quality;
+mod parse_release;
 mod parse_tv;
+mod scan;
+mod subtitles;
 
-lazy_static! {
-    static ref FILE_TYPES: HashSet<&'static str> = HashSet::from_iter(vec!["ogg", "mp4", "m4v", "webm"]);
+fn failure(path: &Path, err: &Error) -> ParseFailure {
+    ParseFailure { file_path: path.to_string_lossy().into_owned(), reason: err.to_string() }
 }
 
-fn index_movie_directory(directory: Option<&str>) -> Result<Vec<Movie>, Error> {
+/// Accumulates the problems found while indexing.
+#[derive(Default)]
+struct Problems {
+    failures: Vec<ParseFailure>,
+    conflicts: Vec<Conflict>,
+}
+
+impl Problems {
+    fn append(&mut self, other: Problems) {
+        self.failures.extend(other.failures);
+        self.conflicts.extend(other.conflicts);
+    }
+}
+
+fn index_movie_directory(directory: Option<&str>, naming: &NamingPatterns, scanner: &mut Scanner, problems: &mut Problems) -> Result<Vec<Movie>, Error> {
     match directory {
         Some (directory) => {
             let root_dir = Path::new(directory);
+            let mut files = vec![];
+            // Extras along with a file whose name gives the movie they belong to.
+            let mut extras = vec![];
+            let (folders, root_files): (Vec<_>, Vec<_>) = scanner.entries(root_dir)?.into_iter().partition(|path| path.is_dir());
+            for (path, folder_files) in folders.iter().zip(scanner.video_files(&folders)) {
+                match folder_files {
+                    Ok(folder_files) => {
+                        let (folder_files, folder_extras) = split_extras(path, folder_files);
+                        files.extend(folder_files);
+                        extras.extend(folder_extras.into_iter().map(|e| (PathBuf::from(&e.file_path), e)));
+                    },
+                    Err(err) => {
+                        warn!("Could not read movie folder: {:?}, err: {}", path, err);
+                        problems.failures.push(failure(path, &err));
+                    },
+                }
+            }
+            for path in root_files {
+                match parse_extra::parse(root_dir, &path) {
+                    Some(ExtraFile { extra, feature_name: Some(feature_name) }) => extras.push((root_dir.join(feature_name), extra)),
+                    _ => files.push(path),
+                }
+            }
+
+            let stacked = stacked_files(&files);
+            let movies = scanner.install(|| files.par_iter().map(|path| {
+                let mut movie = parse_movie::parse_stacked(root_dir, path, naming, stacked.contains(path))?;
+                movie.versions[0].fingerprint = scanner.fingerprint(path);
+                movie.versions[0].added = scan::added(path);
+                Ok(movie)
+            }).collect::<Vec<Result<_, Error>>>());
             let mut result = BTreeMap::new();
-            for entry in read_dir(root_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_file() && FILE_TYPES.contains(path.extension().and_then(OsStr::to_str).ok_or(format_err!("expected file"))?) {
-                    match parse_movie::parse(&root_dir, &path) {
-                        Ok(movie) => {
-                            trace!("Found movie: {}, year: {:?}, file: {:?}", movie.title, movie.year, movie.file_path);
-                            result.insert((movie.title.clone(), movie.year), movie);
-                        },
-                        Err(err) => warn!("Could not parse movie file: {:?}, err: {}", path, err)
-                    }
+            // Files that are one part of a movie, keyed by the name the parts share.
+            let mut stacks = BTreeMap::new();
+            for (path, movie) in files.into_iter().zip(movies) {
+                match movie {
+                    Ok(movie) => {
+                        trace!("Found movie: {}, year: {:?}, file: {:?}", movie.title, movie.year, path);
+                        let file_name = path.file_name().map(OsStr::to_string_lossy).unwrap_or_default();
+                        match parse_movie::parse_part(&file_name, true).filter(|_| stacked.contains(&path)) {
+                            Some((part, name)) => {
+                                let folder = path.parent().map(Path::to_path_buf);
+                                stacks.entry((movie.title.clone(), movie.year, folder, name)).or_insert_with(Vec::new).push((part, movie));
+                            },
+                            None => add_movie(&mut result, movie),
+                        }
+                    },
+                    Err(err) => {
+                        warn!("Could not parse movie file: {:?}, err: {}", path, err);
+                        problems.failures.push(failure(&path, &err));
+                    },
+                }
+            }
+
+            for (_, mut parts) in stacks {
+                parts.sort_by_key(|(part, _)| *part);
+                let files = parts.iter().flat_map(|(_, m)| m.versions.iter()).map(|v| v.file_path.to_owned()).collect::<Vec<_>>();
+                let mut movie = parts.swap_remove(0).1;
+                if files.len() > 1 {
+                    movie.versions[0].parts = files;
+                }
+                add_movie(&mut result, movie);
+            }
+
+            for (feature_path, extra) in extras {
+                let movie = parse_movie::parse(root_dir, &feature_path, naming).ok()
+                    .and_then(|m| result.get_mut(&(m.title, m.year)));
+                match movie {
+                    Some(movie) => movie.extras.push(extra),
+                    None => {
+                        warn!("Could not find the movie for extra: {:?}", extra.file_path);
+                        problems.failures.push(failure(Path::new(&extra.file_path), &format_err!("there is no movie for this {:?} extra", extra.kind)));
+                    },
                 }
             }
-            Ok(result.into_iter().map(|(_, v)|v).collect())
+
+            Ok(result.into_values().map(|mut movie| {
+                movie.versions.sort_by(parse_quality::compare);
+                movie.extras.sort_by(|a, b| (a.kind, &a.title).cmp(&(b.kind, &b.title)));
+                movie
+            }).collect())
         },
         None => Ok(vec![]),
     }
 }
 
-fn index_tv_directory(directory: Option<&str>) -> Result<Vec<TvShow>, Error> {
+/// The files that are parts of a movie: those with a sibling whose name differs from theirs
+/// only in the part tag.
+fn stacked_files(files: &[PathBuf]) -> HashSet<PathBuf> {
+    let shared_name = |path: &PathBuf| {
+        let (_, name) = parse_movie::parse_part(&path.file_name()?.to_string_lossy(), true)?;
+        Some((path.parent()?.to_path_buf(), name))
+    };
+    let mut stacks = HashMap::<_, Vec<_>>::new();
+    for path in files {
+        if let Some(name) = shared_name(path) {
+            stacks.entry(name).or_default().push(path.to_owned());
+        }
+    }
+    stacks.into_iter().filter(|(_, parts)| parts.len() > 1).flat_map(|(_, parts)| parts).collect()
+}
+
+/// Adds a movie to those found so far, as another version when it was already found.
+fn add_movie(result: &mut BTreeMap<(String, Option<u16>), Movie>, movie: Movie) {
+    match result.entry((movie.title.clone(), movie.year)) {
+        Entry::Vacant(entry) => { entry.insert(movie); },
+        Entry::Occupied(mut entry) => entry.get_mut().versions.extend(movie.versions),
+    }
+}
+
+/// Splits the video files inside a folder holding a single movie into the
+/// movie itself and its extras.
+fn split_extras(path: &Path, folder_files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<Extra>) {
+    let mut files = vec![];
+    let mut extras = vec![];
+    for file in folder_files {
+        match parse_extra::parse(path, &file) {
+            Some(extra_file) => extras.push(extra_file.extra),
+            None => files.push(file),
+        }
+    }
+    (files, extras)
+}
+
+fn index_tv_directory(directory: Option<&str>, naming: &NamingPatterns, scanner: &mut Scanner, problems: &mut Problems) -> Result<Vec<TvShow>, Error> {
     match directory {
         Some (directory) => {
             let root_dir = Path::new(directory);
+            let folders = scanner.entries(root_dir)?.into_iter().filter(|path| path.is_dir()).collect::<Vec<_>>();
             let mut result = BTreeMap::new();
-            for entry in read_dir(root_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    match parse_tv::parse_title(&root_dir, &path) {
-                        Ok((title, year)) => {
-                            match index_tv_show(title, &path) {
-                                Ok(series) => {
-                                    result.insert((title.to_owned(), year), TvShow { title: title.to_owned(), year, series: series });
-                                },
-                                Err(err) => warn!("Could not parse tv series: {:?}, err: {}", path, err),
-                            }
-                        },
-                        Err (err) => warn!("Could not parse tv show: {:?}, err: {}", path, err),
-                    }
-                }
-            }
-            Ok(result.into_iter().map(|(_, v)|v).collect())
+            index_tv_show_directories(root_dir, &folders, &mut result, naming, scanner, problems);
+            Ok(result.into_values().collect())
         },
         None => Ok(vec![]),
     }
 }
 
-fn index_tv_show(title: &str, path: &Path) -> Result<Vec<TvSeries>, Error> {
-    let mut series = HashMap::new();
-    for file in globwalk::glob(&format!("{}/**/*.{{ogg,mp4,m4v,webm}}", path.to_str().ok_or(format_err!("expected file"))?))?.filter_map(Result::ok) {
-        match parse_tv::parse_season_and_episode(file.path()) {
-            Ok((season, episode)) => {
-                trace!("Found tv episode: {}, S{:02}E{:02}, file: {:?}", title, season, episode, &file.path());
-                let episode =
-                    TvEpisode {
-                        episode_number: episode,
-                        file_path: file.path().to_str().ok_or(format_err!("should be a path"))?.to_owned()
-                    };
-                if !series.contains_key(&season) {
-                    series.insert(season, vec![episode]);
-                } else {
-                    if let Some(value) = series.get_mut(&season) {
-                        (*value).push(episode);
-                    }
+/// Indexes several show folders, parsing each of them on its own thread.
+fn index_tv_show_directories(root_dir: &Path, paths: &[PathBuf], result: &mut BTreeMap<(String, Option<u16>), TvShow>, naming: &NamingPatterns, scanner: &mut Scanner, problems: &mut Problems) {
+    let files = scanner.video_files(paths);
+    let shows = scanner.install(|| paths.par_iter().zip(files).map(|(path, files)| {
+        let mut show_problems = Problems::default();
+        let show = index_tv_show_directory(root_dir, path, files, naming, scanner, &mut show_problems);
+        (show, show_problems)
+    }).collect::<Vec<_>>());
+
+    for (show, show_problems) in shows {
+        problems.append(show_problems);
+        if let Some(show) = show {
+            result.insert((show.title.clone(), show.year), show);
+        }
+    }
+}
+
+fn index_tv_show_directory(root_dir: &Path, path: &Path, files: Result<Vec<PathBuf>, Error>, naming: &NamingPatterns, scanner: &Scanner, problems: &mut Problems) -> Option<TvShow> {
+    match parse_tv::parse_title(root_dir, path) {
+        Ok((title, year)) => {
+            match files {
+                Ok(files) => {
+                    let (series, extras) = index_tv_show(&title, path, files, naming, scanner, problems);
+                    Some(TvShow { title, year, series, extras })
+                },
+                Err(err) => {
+                    warn!("Could not parse tv series: {:?}, err: {}", path, err);
+                    problems.failures.push(failure(path, &err));
+                    None
+                },
+            }
+        },
+        Err (err) => {
+            warn!("Could not parse tv show: {:?}, err: {}", path, err);
+            problems.failures.push(failure(path, &err));
+            None
+        },
+    }
+}
+
+fn index_tv_show(title: &str, path: &Path, files: Vec<PathBuf>, naming: &NamingPatterns, scanner: &Scanner, problems: &mut Problems) -> (Vec<Arc<TvSeries>>, Vec<Extra>) {
+    // For every episode, the files providing it and whether their folder agrees with their name.
+    let mut episodes: BTreeMap<(u16, u16), Vec<(MediaVersion, bool)>> = BTreeMap::new();
+    // Extras found in a season folder, the rest belong to the show.
+    let mut series_extras: BTreeMap<u16, Vec<Extra>> = BTreeMap::new();
+    let mut show_extras = vec![];
+    for file in files {
+        let file = file.as_path();
+        if let Some(ExtraFile { extra, .. }) = parse_extra::parse(path, file) {
+            trace!("Found tv extra: {}, {:?}, file: {:?}", title, extra.kind, file);
+            match parse_tv::parse_season_folder(path, file) {
+                Some(season) => series_extras.entry(season).or_default().push(extra),
+                None => show_extras.push(extra),
+            }
+            continue;
+        }
+        match parse_tv::locate_episodes(path, file, naming).and_then(|location| Ok((location, parse_quality::parse_version(file, None)?))) {
+            Ok((location, mut version)) => {
+                version.fingerprint = scanner.fingerprint(file);
+                version.added = scan::added(file);
+                if let Some(folder_season) = location.conflicting_season {
+                    warn!("Series mismatch for {:?}, file name: {}, folder: {}", file, location.season, folder_season);
+                    problems.conflicts.push(Conflict {
+                        file_path: version.file_path.to_owned(),
+                        reason: format!("the file name says series {} but it is in the folder for series {}", location.season, folder_season),
+                    });
                 }
+                let folder_agrees = location.conflicting_season.is_none();
+                for episode in location.episodes {
+                    trace!("Found tv episode: {}, S{:02}E{:02}, version: {}, file: {:?}", title, location.season, episode, version.name, file);
+                    episodes.entry((location.season, episode)).or_default().push((version.clone(), folder_agrees));
+                }
+            },
+            Err(err) => {
+                warn!("Parse failed for {:?}, err: {}", file, err);
+                problems.failures.push(failure(file, &err));
             },
-            Err(err) => warn!("Parse failed for {:?}, err: {}", &file.path(), err),
         }
     }
-    Ok(series.into_iter().map(|(k, v)| TvSeries { series_number: k, episodes: v }).collect())
+
+    let mut series = BTreeMap::new();
+    for ((season, episode), mut versions) in episodes {
+        // Files in the right folder come first.
+        versions.sort_by(|(a, a_agrees), (b, b_agrees)| b_agrees.cmp(a_agrees).then_with(|| parse_quality::compare(a, b)));
+        let versions = versions.into_iter().map(|(v, _)| v).collect();
+        series.entry(season).or_insert_with(Vec::new).push(Arc::new(TvEpisode { episode_number: episode, versions }));
+    }
+    let series: Vec<Arc<TvSeries>> = series.into_iter()
+        .map(|(k, v)| Arc::new(TvSeries { series_number: k, episodes: v, extras: series_extras.remove(&k).unwrap_or_default() }))
+        .collect();
+    // A season folder holding nothing but extras does not make a series.
+    show_extras.extend(series_extras.into_values().flatten());
+    (series, show_extras)
+}
+
+/// How a single file would be indexed, used to try out naming patterns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Interpretation {
+    Movie {
+        title: String,
+        year: Option<u16>,
+        part: Option<u16>,
+        version: MediaVersion,
+        /// The naming pattern that matched, if it was not a built-in one.
+        pattern: Option<String>,
+    },
+    Episode {
+        show: String,
+        year: Option<u16>,
+        season: u16,
+        episodes: Vec<u16>,
+        conflicting_season: Option<u16>,
+        version: MediaVersion,
+        pattern: Option<String>,
+    },
+    Extra(Extra),
+}
+
+/// Works out how a file in the movie directory `root_dir` would be indexed,
+/// taking the directory the file is in when none is given.
+pub fn interpret_movie(root_dir: Option<&Path>, path: &Path, naming: &NamingPatterns) -> Result<Interpretation, Error> {
+    let root_dir = root_dir.or_else(|| path.parent()).ok_or(format_err!("{:?} is not a file", path))?;
+    let first = path.strip_prefix(root_dir)?.components().next().ok_or(format_err!("{:?} is not a file", path))?;
+    let folder = Some(root_dir.join(first)).filter(|f| f != path).unwrap_or_else(|| root_dir.to_path_buf());
+    if let Some(ExtraFile { extra, .. }) = parse_extra::parse(&folder, path) {
+        return Ok(Interpretation::Extra(extra));
+    }
+
+    // Whether the file is a part depends on the files next to it.
+    let siblings = path.parent().and_then(|p| fs::read_dir(p).ok())
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let stacked = stacked_files(&siblings).contains(path);
+    let mut movie = parse_movie::parse_stacked(root_dir, path, naming, stacked)?;
+    Ok(Interpretation::Movie {
+        title: movie.title,
+        year: movie.year,
+        part: path.file_name().and_then(|f| parse_movie::parse_part(&f.to_string_lossy(), true)).filter(|_| stacked).map(|(part, _)| part),
+        version: movie.versions.remove(0),
+        pattern: parse_movie::match_pattern(root_dir, path, naming).map(|m| m.pattern),
+    })
+}
+
+/// Works out how a file in the tv directory `root_dir` would be indexed,
+/// taking the directory above the show (and season) folder when none is given.
+pub fn interpret_episode(root_dir: Option<&Path>, path: &Path, naming: &NamingPatterns) -> Result<Interpretation, Error> {
+    let root_dir = match root_dir {
+        Some(root_dir) => root_dir.to_path_buf(),
+        None => {
+            let folder = path.parent().ok_or(format_err!("{:?} is not in a show folder", path))?;
+            let show_dir = folder.parent().filter(|show| parse_tv::parse_season_folder(show, path).is_some()).unwrap_or(folder);
+            show_dir.parent().ok_or(format_err!("{:?} is not in a show folder", path))?.to_path_buf()
+        },
+    };
+    let show_dir = root_dir.join(path.strip_prefix(&root_dir)?.components().next().ok_or(format_err!("{:?} is not a file", path))?);
+    if let Some(ExtraFile { extra, .. }) = parse_extra::parse(&show_dir, path) {
+        return Ok(Interpretation::Extra(extra));
+    }
+
+    let (show, year) = parse_tv::parse_title(&root_dir, &show_dir)?;
+    let location = parse_tv::locate_episodes(&show_dir, path, naming)?;
+    Ok(Interpretation::Episode {
+        show,
+        year,
+        season: location.season,
+        episodes: location.episodes,
+        conflicting_season: location.conflicting_season,
+        version: parse_quality::parse_version(path, None)?,
+        pattern: path.file_stem().and_then(|f| naming.episode(&f.to_string_lossy())).map(|m| m.pattern),
+    })
 }
 
-pub fn directories(movie_directory: Option<&str>, tv_directory: Option<&str>) -> Result<(Vec<Movie>, Vec<TvShow>), Error> {
-    let movies = index_movie_directory(movie_directory)?;
-    let tv = index_tv_directory(tv_directory)?;
-    Ok((movies, tv))
+pub fn directories(movie_directory: Option<&str>, tv_directory: Option<&str>, naming: &NamingPatterns, scan: &ScanOptions) -> Result<Library, Error> {
+    let mut scanner = Scanner::new(scan)?;
+    let mut problems = Problems::default();
+    let movies = index_movie_directory(movie_directory, naming, &mut scanner, &mut problems)?;
+    let tv_shows = index_tv_directory(tv_directory, naming, &mut scanner, &mut problems)?;
+    Ok(Library { folder: None, movies, tv_shows, failures: problems.failures, conflicts: problems.conflicts })
+}
+
+/// Re-indexes only the part of the library that `folder` belongs to: the
+/// tv show folder it is in, or the whole movie directory.
+pub fn folder(movie_directory: Option<&str>, tv_directory: Option<&str>, folder: &str, naming: &NamingPatterns, scan: &ScanOptions) -> Result<Library, Error> {
+    let folder = Path::new(folder);
+    let mut scanner = Scanner::new(scan)?;
+    let mut problems = Problems::default();
+
+    if let Some(tv_directory) = tv_directory.filter(|d|folder.starts_with(d)) {
+        let root_dir = Path::new(tv_directory);
+        let show_dir = match folder.strip_prefix(root_dir)?.components().next() {
+            Some(show) => root_dir.join(show),
+            None => {
+                let tv_shows = index_tv_directory(Some(tv_directory), naming, &mut scanner, &mut problems)?;
+                return Ok(Library { folder: Some(tv_directory.to_owned()), movies: vec![], tv_shows, failures: problems.failures, conflicts: problems.conflicts });
+            },
+        };
+        let mut result = BTreeMap::new();
+        index_tv_show_directories(root_dir, std::slice::from_ref(&show_dir), &mut result, naming, &mut scanner, &mut problems);
+        Ok(Library {
+            folder: Some(show_dir.to_string_lossy().into_owned()),
+            movies: vec![],
+            tv_shows: result.into_values().collect(),
+            failures: problems.failures,
+            conflicts: problems.conflicts,
+        })
+    } else if let Some(movie_directory) = movie_directory.filter(|d|folder.starts_with(d)) {
+        let movies = index_movie_directory(Some(movie_directory), naming, &mut scanner, &mut problems)?;
+        Ok(Library { folder: Some(movie_directory.to_owned()), movies, tv_shows: vec![], failures: problems.failures, conflicts: problems.conflicts })
+    } else {
+        Err(format_err!("{:?} is not inside the movie or tv directory", folder))
+    }
+}
+
+#[test]
+fn stacks() {
+    let files = [
+        "/movies/Alien (1979) - cd1.mp4",
+        "/movies/Alien (1979) - cd2.mp4",
+        "/movies/The Godfather Part 2 (1974).mkv",
+        "/movies/The Godfather Part 3 (1990).mkv",
+        "/movies/Kill Bill (2003) - part1.mp4",
+        "/movies/Kill Bill (2003) - part2.mp4",
+        "/movies/Heat (1995) - cd1.mp4",
+        "/movies/other/Heat (1995) - cd2.mp4",
+    ].iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    let mut stacked = stacked_files(&files).into_iter().collect::<Vec<_>>();
+    stacked.sort();
+    assert_eq!(vec![&files[0], &files[1], &files[4], &files[5]], stacked.iter().collect::<Vec<_>>());
 }
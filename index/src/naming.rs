@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::path::Path;
+
+use failure::{Error, format_err};
+use regex::{Captures, Regex};
+use serde_derive::Deserialize;
+
+/// The naming patterns file as it is written, e.g.
+///
+/// ```json
+/// {
+///     "movies": ["^(?P<title>.+) \\[(?P<year>\\d{4})\\]$"],
+///     "tv": ["^ep(?P<season>\\d+)_(?P<episode>\\d+)$"]
+/// }
+/// ```
+#[derive(Deserialize)]
+struct NamingConfig {
+    #[serde(default)]
+    movies: Vec<String>,
+    #[serde(default)]
+    tv: Vec<String>,
+}
+
+/// User-defined patterns tried before the built-in ones. Names are matched
+/// without their extension, movie patterns need a `title` group and may have
+/// `year` and `edition` ones, tv patterns need an `episode` group and may
+/// have a `season` one.
+#[derive(Clone, Debug, Default)]
+pub struct NamingPatterns {
+    movies: Vec<Regex>,
+    tv: Vec<Regex>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovieMatch {
+    pub title: String,
+    pub year: Option<u16>,
+    pub edition: Option<String>,
+    /// The pattern that matched.
+    pub pattern: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpisodeMatch {
+    pub season: Option<u16>,
+    pub episode: u16,
+    /// The pattern that matched.
+    pub pattern: String,
+}
+
+impl NamingPatterns {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let config: NamingConfig = serde_json::from_reader(File::open(path)?)
+            .map_err(|e| format_err!("could not read naming patterns from {:?}: {}", path, e))?;
+        Self::new(&config.movies, &config.tv)
+    }
+
+    pub fn new(movies: &[String], tv: &[String]) -> Result<Self, Error> {
+        Ok(Self {
+            movies: compile(movies, &["title"])?,
+            tv: compile(tv, &["episode"])?,
+        })
+    }
+
+    pub fn movie(&self, name: &str) -> Option<MovieMatch> {
+        self.movies.iter().filter_map(|pattern| {
+            let cap = pattern.captures(name)?;
+            Some(MovieMatch {
+                title: group(&cap, "title")?.to_owned(),
+                year: group(&cap, "year").and_then(|y| y.parse::<u16>().ok()),
+                edition: group(&cap, "edition").map(str::to_owned),
+                pattern: pattern.as_str().to_owned(),
+            })
+        })
+        .next()
+    }
+
+    pub fn episode(&self, name: &str) -> Option<EpisodeMatch> {
+        self.tv.iter().filter_map(|pattern| {
+            let cap = pattern.captures(name)?;
+            Some(EpisodeMatch {
+                season: group(&cap, "season").and_then(|s| s.parse::<u16>().ok()),
+                episode: group(&cap, "episode")?.parse::<u16>().ok()?,
+                pattern: pattern.as_str().to_owned(),
+            })
+        })
+        .next()
+    }
+}
+
+fn compile(patterns: &[String], required: &[&str]) -> Result<Vec<Regex>, Error> {
+    patterns.iter().map(|pattern| {
+        let regex = Regex::new(pattern).map_err(|e| format_err!("invalid naming pattern {:?}: {}", pattern, e))?;
+        for name in required {
+            if !regex.capture_names().any(|n| n == Some(name)) {
+                return Err(format_err!("naming pattern {:?} needs a `{}` group", pattern, name));
+            }
+        }
+        Ok(regex)
+    })
+    .collect()
+}
+
+fn group<'a>(cap: &Captures<'a>, name: &str) -> Option<&'a str> {
+    cap.name(name).map(|m| m.as_str().trim()).filter(|s| !s.is_empty())
+}
+
+#[test]
+fn movie_patterns() {
+    let naming = NamingPatterns::new(&[r"^(?P<title>.+) \[(?P<year>\d{4})\](?: \{(?P<edition>[^}]+)\})?$".to_owned()], &[]).unwrap();
+
+    assert_eq!(Some(("Alien".to_owned(), Some(1979), None)), naming.movie("Alien [1979]").map(|m| (m.title, m.year, m.edition)));
+    assert_eq!(Some(("Alien".to_owned(), Some(1979), Some("Director's Cut".to_owned()))),
+        naming.movie("Alien [1979] {Director's Cut}").map(|m| (m.title, m.year, m.edition)));
+    assert_eq!(None, naming.movie("Alien (1979)"));
+}
+
+#[test]
+fn episode_patterns() {
+    let naming = NamingPatterns::new(&[], &[r"^ep(?P<season>\d+)_(?P<episode>\d+)$".to_owned(), r"^part (?P<episode>\d+)$".to_owned()]).unwrap();
+
+    assert_eq!(Some((Some(2), 5)), naming.episode("ep2_05").map(|m| (m.season, m.episode)));
+    assert_eq!(Some((None, 3)), naming.episode("part 3").map(|m| (m.season, m.episode)));
+    assert_eq!(None, naming.episode("S01E01"));
+}
+
+#[test]
+fn invalid_patterns() {
+    assert!(NamingPatterns::new(&["(unclosed".to_owned()], &[]).is_err());
+    assert!(NamingPatterns::new(&[r"^(?P<name>.+)$".to_owned()], &[]).is_err());
+    assert!(NamingPatterns::new(&[], &[r"^(?P<season>\d+)$".to_owned()]).is_err());
+}
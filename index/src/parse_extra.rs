@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use data::{Extra, ExtraKind};
+
+/// Folders kept next to a movie or show for material other than the feature itself.
+const EXTRAS_FOLDERS: &[(&str, ExtraKind)] = &[
+    ("trailers", ExtraKind::Trailer),
+    ("featurettes", ExtraKind::Featurette),
+    ("behind the scenes", ExtraKind::BehindTheScenes),
+    ("deleted scenes", ExtraKind::DeletedScene),
+    ("interviews", ExtraKind::Interview),
+    ("scenes", ExtraKind::Scene),
+    ("shorts", ExtraKind::Short),
+    ("extras", ExtraKind::Other),
+    ("other", ExtraKind::Other),
+];
+
+/// Suffixes marking a file next to the feature as an extra, e.g. `Alien (1979)-trailer.mp4`.
+const EXTRAS_SUFFIXES: &[(&str, ExtraKind)] = &[
+    ("-trailer", ExtraKind::Trailer),
+    ("-featurette", ExtraKind::Featurette),
+    ("-behindthescenes", ExtraKind::BehindTheScenes),
+    ("-deleted", ExtraKind::DeletedScene),
+    ("-interview", ExtraKind::Interview),
+    ("-scene", ExtraKind::Scene),
+    ("-short", ExtraKind::Short),
+    ("-other", ExtraKind::Other),
+];
+
+/// An extra found inside `folder` and, for suffixed files, the name of the
+/// feature it belongs to with the suffix taken off.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtraFile {
+    pub extra: Extra,
+    pub feature_name: Option<String>,
+}
+
+/// Works out whether a file inside `folder` is an extra rather than the feature itself.
+pub fn parse(folder: &Path, path: &Path) -> Option<ExtraFile> {
+    let file_path = path.to_str()?.to_owned();
+    let stem = path.file_stem()?.to_string_lossy();
+    let ends_with = |suffix: &str| stem.len() >= suffix.len() && stem.is_char_boundary(stem.len() - suffix.len())
+        && stem[stem.len() - suffix.len()..].eq_ignore_ascii_case(suffix);
+
+    if let Some((suffix, kind)) = EXTRAS_SUFFIXES.iter().find(|(suffix, _)| ends_with(suffix)) {
+        let name = &stem[..stem.len() - suffix.len()];
+        let extension = path.extension().map_or(String::new(), |e| format!(".{}", e.to_string_lossy()));
+        return Some(ExtraFile {
+            extra: Extra { kind: *kind, title: name.trim().to_owned(), file_path },
+            feature_name: Some(format!("{}{}", name, extension)),
+        });
+    }
+
+    let kind = path.strip_prefix(folder).ok()?.parent()?
+        .components().rev()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .filter_map(|c| EXTRAS_FOLDERS.iter().find(|(name, _)| c.eq_ignore_ascii_case(name)))
+        .map(|(_, kind)| *kind)
+        .next()?;
+    Some(ExtraFile { extra: Extra { kind, title: stem.into_owned(), file_path }, feature_name: None })
+}
+
+#[test]
+fn extras() {
+    let cases = vec![
+        ("/movies/Alien (1979)", "/movies/Alien (1979)/Trailers/Teaser.mp4", Some((ExtraKind::Trailer, "Teaser", None))),
+        ("/movies/Alien (1979)", "/movies/Alien (1979)/Behind The Scenes/Chestburster.mp4", Some((ExtraKind::BehindTheScenes, "Chestburster", None))),
+        ("/movies/Alien (1979)", "/movies/Alien (1979)/featurettes/Sets/Nostromo.mp4", Some((ExtraKind::Featurette, "Nostromo", None))),
+        ("/movies/Alien (1979)", "/movies/Alien (1979)/Alien (1979)-trailer.mp4", Some((ExtraKind::Trailer, "Alien (1979)", Some("Alien (1979).mp4")))),
+        ("/movies", "/movies/Alien (1979)-Trailer.mp4", Some((ExtraKind::Trailer, "Alien (1979)", Some("Alien (1979).mp4")))),
+        ("/movies/Alien (1979)", "/movies/Alien (1979)/Deleted Scenes/Cocoon-deleted.mp4", Some((ExtraKind::DeletedScene, "Cocoon", Some("Cocoon.mp4")))),
+        ("/tv/Show", "/tv/Show/Season 1/Extras/Making Of.mp4", Some((ExtraKind::Other, "Making Of", None))),
+        ("/movies/Alien (1979)", "/movies/Alien (1979)/Alien (1979).mp4", None),
+        ("/movies/Scenes (2010)", "/movies/Scenes (2010)/Scenes (2010).mp4", None),
+        ("/movies", "/movies/Trailer Park Boys (1999).mp4", None),
+    ];
+
+    for (folder, file_path, expected) in cases {
+        let expected = expected.map(|(kind, title, feature_name): (_, &str, Option<&str>)| ExtraFile {
+            extra: Extra { kind, title: title.to_owned(), file_path: file_path.to_owned() },
+            feature_name: feature_name.map(str::to_owned),
+        });
+        assert_eq!(expected, parse(Path::new(folder), Path::new(file_path)), "{}", file_path);
+    }
+}
@@ -6,51 +6,99 @@ use regex::Regex;
 
 use data::Movie;
 
-pub fn parse<'a>(search_path: &Path, path: &'a Path) -> Result<Movie, Error> {
-    let (title, year) = parse_title(search_path, path)?;
+use crate::naming::{MovieMatch, NamingPatterns};
+use crate::parse_quality;
+use crate::parse_release;
+
+/// Parses a movie file found either directly in the search path or anywhere inside
+/// a folder of its own, in which case the folder names the movie.
+pub fn parse(search_path: &Path, path: &Path, naming: &NamingPatterns) -> Result<Movie, Error> {
+    parse_stacked(search_path, path, naming, false)
+}
+
+/// Parses a movie file as [parse](fn.parse.html) does, where `stacked` says whether the file is
+/// one of several parts of the movie, so that a `part1` in its name is a part tag.
+pub fn parse_stacked(search_path: &Path, path: &Path, naming: &NamingPatterns, stacked: bool) -> Result<Movie, Error> {
+    let folder_name =
+        path.strip_prefix(search_path)?.components().next().ok_or(format_err!("failed to parse folder"))?
+            .as_os_str().to_string_lossy();
+    let file_name = path.file_name().ok_or(format_err!("failed to parse file name"))?.to_string_lossy();
+
+    if let Some(MovieMatch { title, year, edition, .. }) = match_pattern(search_path, path, naming) {
+        return Ok(Movie {
+            title: parse_release::nfc(&title),
+            year,
+            versions: vec![parse_quality::parse_version(path, edition)?],
+            extras: vec![],
+        });
+    }
+
+    let (name, folder_edition) = parse_edition(&folder_name);
+    let name = parse_part(&name, stacked).map_or(name, |(_, name)| name);
+    // Files directly in the search path are named after the movie, less their extension.
+    let name = match Path::new(&name).file_stem() {
+        Some(stem) if folder_name == file_name => stem.to_string_lossy().into_owned(),
+        _ => name,
+    };
+    let (title, year) = parse_release::parse_release_name(&name);
+    let edition = Some(&file_name).filter(|f| **f != folder_name).and_then(|f| parse_edition(f).1).or(folder_edition);
 
     Ok(Movie {
-        title: title.to_owned(),
-        year: year,
-        file_path: path.to_str().ok_or(format_err!("should be a path"))?.to_owned()
+        title,
+        year,
+        versions: vec![parse_quality::parse_version(path, edition)?],
+        extras: vec![],
     })
 }
 
-fn parse_title<'a>(base_path: &Path, path: &'a Path) -> Result<(&'a str, Option<u16>), Error> {
+/// Tries the user's naming patterns on the movie's folder, then on its file name.
+pub fn match_pattern(search_path: &Path, path: &Path, naming: &NamingPatterns) -> Option<MovieMatch> {
+    let folder = search_path.join(path.strip_prefix(search_path).ok()?.components().next()?);
+    let folder_name = parse_release::nfc(&if folder == path { path.file_stem() } else { folder.file_name() }?.to_string_lossy());
+    let file_name = parse_release::nfc(&path.file_stem()?.to_string_lossy());
+    naming.movie(&folder_name).or_else(|| naming.movie(&file_name))
+}
+
+/// Splits an edition tag such as `{edition-Final Cut}` or `- Director's Cut` off a name.
+fn parse_edition(name: &str) -> (String, Option<String>) {
     lazy_static! {
-        static ref TITLE_FORMAT_1: Regex = Regex::new(r"([^']+)\s+\((\d{4})\)").unwrap();
+        static ref EDITION_FORMAT_1: Regex = Regex::new(r"\s*\{edition-([^}]+)\}").unwrap();
     }
     lazy_static! {
-        static ref TITLE_FORMAT_2: Regex = Regex::new(r"([^']+)\.").unwrap();
+        static ref EDITION_FORMAT_2: Regex = Regex::new(r"(?i)\s+-\s+((?:[\w']+\s+){0,2}(?:cut|edition|version)|unrated|extended|theatrical|remastered|imax)\b").unwrap();
     }
 
-    let folder_name =
-        path.strip_prefix(base_path)?.components().next().ok_or(format_err!("failed to parse folder"))?
-            .as_os_str().to_str().ok_or(format_err!("failed to parse folder"))?;
-            
-    match TITLE_FORMAT_1.captures_iter(folder_name).nth(0) {
+    match EDITION_FORMAT_1.captures(name).or_else(|| EDITION_FORMAT_2.captures(name)) {
         Some (cap) => {
-            let title = cap.get(1).map(|m| m.as_str()).ok_or(format_err!("failed to parse title"))?;
-            let year = cap.get(2).map(|m| m.as_str()).ok_or(format_err!("failed to parse year"))?.parse::<u16>()?;
-            Ok((title, Some(year)))
-        },
-        None => {
-            match TITLE_FORMAT_2.captures_iter(folder_name).nth(0) {
-                Some (cap) => {
-                    let title = cap.get(1).map(|m| m.as_str()).ok_or(format_err!("failed to parse title"))?;
-                    Ok((title, None))
-                },
-                None => {
-                    Ok((folder_name, None))
-                },
-            }
+            let tag = cap.get(0).unwrap();
+            let edition = cap.get(1).map(|m| m.as_str().trim().to_owned());
+            (format!("{}{}", &name[..tag.start()], &name[tag.end()..]), edition)
         },
+        None => (name.to_owned(), None),
+    }
+}
+
+/// Splits the part number off a file that is one of several parts (`cd1`, `disc2`, ...)
+/// of the same movie, leaving the name the parts share. `part1` only counts when the file
+/// is known to be `stacked` with its other parts, as titles such as `The Godfather Part 2`
+/// use it too.
+pub fn parse_part(name: &str, stacked: bool) -> Option<(u16, String)> {
+    lazy_static! {
+        static ref PART_FORMAT: Regex = Regex::new(r"(?i)[ ._-]+(cd|dvd|disc|disk|part|pt)[ ._-]?(\d{1,2})\b").unwrap();
+    }
+
+    let cap = PART_FORMAT.captures(name)?;
+    if !stacked && cap.get(1)?.as_str().eq_ignore_ascii_case("part") {
+        return None;
     }
+    let tag = cap.get(0)?;
+    let part = cap.get(2)?.as_str().parse::<u16>().ok()?;
+    Some((part, format!("{}{}", &name[..tag.start()], &name[tag.end()..])))
 }
 
 #[test]
 fn a_clockwork_orange(){
-    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/A Clockwork Orange (1971).mkv")) {
+    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/A Clockwork Orange (1971).mkv"), &NamingPatterns::default()) {
         Ok(Movie { ref title, year: Some (1971),.. }) if title == "A Clockwork Orange" => (),
         result => assert!(false, "{:?}", result)
     }
@@ -58,7 +106,7 @@ fn a_clockwork_orange(){
 
 #[test]
 fn american_history_x(){
-    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/American History X.mp4")) {
+    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/American History X.mp4"), &NamingPatterns::default()) {
         Ok(Movie { ref title, year: None,.. }) if title == "American History X" => (),
         result => assert!(false, "{:?}", result)
     }
@@ -66,7 +114,7 @@ fn american_history_x(){
 
 #[test]
 fn great_escape(){
-    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/Great Escape.m4v")) {
+    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/Great Escape.m4v"), &NamingPatterns::default()) {
         Ok(Movie { ref title, year: None,.. }) if title == "Great Escape" => (),
         result => assert!(false, "{:?}", result)
     }
@@ -74,8 +122,154 @@ fn great_escape(){
 
 #[test]
 fn die_hard(){
-    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/Die Hard.m4v")) {
+    match parse(Path::new("/storage/movies/"), Path::new("/storage/movies/Die Hard.m4v"), &NamingPatterns::default()) {
         Ok(Movie { ref title, year: None,.. }) if title == "Die Hard" => (),
         result => assert!(false, "{:?}", result)
     }
 }
+
+#[test]
+fn movie_folders(){
+    let cases = vec![
+        ("Alien (1979)/Alien (1979).mp4", "Alien", Some(1979), None),
+        ("Alien (1979)/Alien.1979.1080p.BluRay.mp4", "Alien", Some(1979), None),
+        ("Alien (1979)/Alien (1979) - Director's Cut.mp4", "Alien", Some(1979), Some("Director's Cut")),
+        ("Alien (1979) {edition-Director's Cut}/Alien.mp4", "Alien", Some(1979), Some("Director's Cut")),
+        ("Alien/CD1/Alien.mp4", "Alien", None, None),
+    ];
+
+    for (file_name, expected_title, expected_year, expected_edition) in cases {
+        let path = Path::new("/storage/movies/").join(file_name);
+        match parse(Path::new("/storage/movies/"), &path, &NamingPatterns::default()) {
+            Ok(Movie { ref title, year, ref versions, .. }) if title == expected_title && year == expected_year
+                && versions[0].edition.as_ref().map(String::as_str) == expected_edition => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[test]
+fn release_names(){
+    let cases = vec![
+        ("The.Matrix.1999.1080p.BluRay.x264-GROUP.mkv", "The Matrix", Some(1999)),
+        ("The.Matrix.1999.1080p.BluRay.x264-GROUP/The.Matrix.1999.1080p.BluRay.x264-GROUP.mkv", "The Matrix", Some(1999)),
+        ("2001: A Space Odyssey (1968).mkv", "2001: A Space Odyssey", Some(1968)),
+        ("Blade_Runner_2049_2017_2160p.mkv", "Blade Runner 2049", Some(2017)),
+        ("Blade Runner 2049.mkv", "Blade Runner 2049", None),
+        ("Blade.Runner.2049.1080p.BluRay.mkv", "Blade Runner 2049", None),
+        ("Dr.Strangelove.1964.720p.mkv", "Dr. Strangelove", Some(1964)),
+    ];
+
+    for (file_name, expected_title, expected_year) in cases {
+        let path = Path::new("/storage/movies/").join(file_name);
+        match parse(Path::new("/storage/movies/"), &path, &NamingPatterns::default()) {
+            Ok(Movie { ref title, year, .. }) if title == expected_title && year == expected_year => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[test]
+fn unicode_names(){
+    let cases = vec![
+        ("Ame\u{301}lie (2001).mp4", "Amélie", Some(2001)),
+        ("Amélie (2001)/Ame\u{301}lie.mp4", "Amélie", Some(2001)),
+        ("千と千尋の神隠し (2001).mp4", "千と千尋の神隠し", Some(2001)),
+    ];
+
+    for (file_name, expected_title, expected_year) in cases {
+        let path = Path::new("/storage/movies/").join(file_name);
+        match parse(Path::new("/storage/movies/"), &path, &NamingPatterns::default()) {
+            Ok(Movie { ref title, year, .. }) if title == expected_title && year == expected_year => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names(){
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = Path::new("/storage/movies/").join(OsStr::from_bytes(b"Am\xe9lie (2001).mp4"));
+    match parse(Path::new("/storage/movies/"), &path, &NamingPatterns::default()) {
+        Err(ref err) if err.to_string().contains("UTF-8") => (),
+        result => assert!(false, "{:?}", result)
+    }
+}
+
+#[test]
+fn parts(){
+    let cases = vec![
+        ("Alien (1979) - cd1.mp4", Some((1, "Alien (1979).mp4"))),
+        ("Alien (1979) CD2.mp4", Some((2, "Alien (1979).mp4"))),
+        ("Alien.1979.part1.mp4", Some((1, "Alien.1979.mp4"))),
+        ("Alien (1979) - Part 2.mp4", Some((2, "Alien (1979).mp4"))),
+        ("Alien (1979) - disc 3.mp4", Some((3, "Alien (1979).mp4"))),
+        ("Alien (1979).mp4", None),
+        ("Apartment 1979.mp4", None),
+        ("Alien (1979) 1080p.mp4", None),
+    ];
+
+    for (file_name, expected) in cases {
+        let expected = expected.map(|(part, name)| (part, name.to_owned()));
+        assert_eq!(expected, parse_part(file_name, true), "{}", file_name);
+    }
+    assert_eq!(None, parse_part("Alien (1979) - Part 2.mp4", false));
+    assert_eq!(Some((2, "Alien (1979).mp4".to_owned())), parse_part("Alien (1979) - pt2.mp4", false));
+
+    let search_path = Path::new("/storage/movies/");
+    let cases = vec![
+        ("Alien - cd1.mp4", false, "Alien", None),
+        ("The Godfather Part 2 (1974).mkv", false, "The Godfather Part 2", Some(1974)),
+        ("The Godfather (1972) Part 2.mkv", true, "The Godfather", Some(1972)),
+    ];
+
+    for (file_name, stacked, expected_title, expected_year) in cases {
+        match parse_stacked(search_path, &search_path.join(file_name), &NamingPatterns::default(), stacked) {
+            Ok(Movie { ref title, year, .. }) if title == expected_title && year == expected_year => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[test]
+fn editions(){
+    let cases = vec![
+        ("Blade Runner (1982) {edition-Final Cut}.mp4", Some(1982), Some("Final Cut")),
+        ("Blade Runner (1982) - Director's Cut.mp4", Some(1982), Some("Director's Cut")),
+        ("Blade Runner (1982) - The Final Cut 2160p.mp4", Some(1982), Some("The Final Cut")),
+        ("Blade Runner - Extended.mp4", None, Some("Extended")),
+        ("Blade Runner (1982).mp4", Some(1982), None),
+    ];
+
+    for (file_name, expected_year, expected_edition) in cases {
+        let path = Path::new("/storage/movies/").join(file_name);
+        match parse(Path::new("/storage/movies/"), &path, &NamingPatterns::default()) {
+            Ok(Movie { ref title, year, ref versions, .. }) if title == "Blade Runner" && year == expected_year
+                && versions[0].edition.as_ref().map(String::as_str) == expected_edition => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[test]
+fn naming_patterns(){
+    let naming = NamingPatterns::new(&[r"^(?P<title>.+) \[(?P<year>\d{4})\](?: \{(?P<edition>[^}]+)\})?$".to_owned()], &[]).unwrap();
+    let cases = vec![
+        ("Alien [1979].mp4", "Alien", Some(1979), None),
+        ("Alien [1979] {Director's Cut}.mp4", "Alien", Some(1979), Some("Director's Cut")),
+        ("Alien [1979]/Alien.mp4", "Alien", Some(1979), None),
+        ("Alien (1979).mp4", "Alien", Some(1979), None),
+    ];
+
+    for (file_name, expected_title, expected_year, expected_edition) in cases {
+        let path = Path::new("/storage/movies/").join(file_name);
+        match parse(Path::new("/storage/movies/"), &path, &naming) {
+            Ok(Movie { ref title, year, ref versions, .. }) if title == expected_title && year == expected_year
+                && versions[0].edition.as_ref().map(String::as_str) == expected_edition => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use failure::{Error, format_err};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use data::{MediaVersion, Quality};
+
+/// Sources from best to worst, with the name they are shown as.
+const SOURCES: &[(&str, &str)] = &[
+    ("remux", "Remux"),
+    ("bluray", "BluRay"),
+    ("blu-ray", "BluRay"),
+    ("bdrip", "BluRay"),
+    ("brrip", "BluRay"),
+    ("web-dl", "WEB-DL"),
+    ("webdl", "WEB-DL"),
+    ("webrip", "WEBRip"),
+    ("web", "WEB-DL"),
+    ("hdtv", "HDTV"),
+    ("dvdrip", "DVD"),
+    ("dvd", "DVD"),
+];
+
+pub fn parse_version(path: &Path, edition: Option<String>) -> Result<MediaVersion, Error> {
+    // The library keeps paths as text, so a file whose path isn't UTF-8 couldn't be opened again.
+    let file_path = path.to_str().ok_or(format_err!("the path is not valid UTF-8, rename the file to index it"))?;
+    let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or(file_path);
+    let quality = parse_quality(file_name);
+
+    let tags = match (quality.resolution, &quality.source) {
+        (Some(resolution), Some(source)) => Some(format!("{}p {}", resolution, source)),
+        (Some(resolution), None) => Some(format!("{}p", resolution)),
+        (None, Some(source)) => Some(source.to_owned()),
+        (None, None) => None,
+    };
+    let name = match (&edition, tags) {
+        (Some(edition), Some(tags)) => format!("{} - {}", edition, tags),
+        (Some(edition), None) => edition.to_owned(),
+        (None, Some(tags)) => tags,
+        (None, None) => file_name.to_owned(),
+    };
+
+    Ok(MediaVersion { name, file_path: file_path.to_owned(), parts: vec![], edition, quality, fingerprint: None, added: None })
+}
+
+pub fn parse_quality(file_name: &str) -> Quality {
+    lazy_static! {
+        static ref RESOLUTION_FORMAT: Regex = Regex::new(r"(?i)\b(?:(2160|1080|720|576|480)[pi]|(4k|uhd))\b").unwrap();
+        static ref SOURCE_FORMAT: Regex = Regex::new(r"(?i)\b(remux|blu-?ray|bdrip|brrip|web-?dl|webrip|web|hdtv|dvdrip|dvd)\b").unwrap();
+        static ref TAGS_START_FORMAT: Regex = Regex::new(r"(?i)\bs\d{1,4}[ .-]?e\d{1,4}|\b\d{1,2}x\d{2,3}\b|\b(?:19|20)\d{2}\b|\b(?:2160|1080|720|576|480)[pi]\b").unwrap();
+    }
+    let original = file_name;
+    // Underscores count as word characters, but release names use them as separators.
+    let file_name = &file_name.replace('_', " ");
+    let tags_start = TAGS_START_FORMAT.find(file_name).map(|m| m.start());
+
+    let resolution = RESOLUTION_FORMAT.captures(file_name).and_then(|cap| {
+        match (cap.get(1), cap.get(2)) {
+            (Some(m), _) => m.as_str().parse::<u16>().ok(),
+            (None, Some(_)) => Some(2160),
+            (None, None) => None,
+        }
+    });
+
+    // Remux releases also mention their disc source, so take the best source named.
+    let source = SOURCE_FORMAT.captures_iter(file_name)
+        .filter_map(|cap| cap.get(1))
+        .filter(|m| !m.as_str().eq_ignore_ascii_case("web") || is_web_tag(original, m.start(), m.end(), tags_start))
+        .filter_map(|m| SOURCES.iter().position(|(tag, _)| tag.eq_ignore_ascii_case(m.as_str())))
+        .min()
+        .map(|i| SOURCES[i].1.to_owned());
+
+    Quality { resolution, source }
+}
+
+/// A bare `web` is also an everyday word, so it only names the source when it is written like
+/// a scene tag, `WEB` or between dots, after the episode, year or resolution.
+fn is_web_tag(file_name: &str, start: usize, end: usize, tags_start: Option<usize>) -> bool {
+    let dotted = file_name[..start].ends_with(['.', '_'])
+        && (end == file_name.len() || file_name[end..].starts_with(['.', '_', '-']));
+    tags_start.is_some_and(|tags_start| start > tags_start) && (dotted || &file_name[start..end] == "WEB")
+}
+
+/// Orders versions from best to worst: highest resolution first, then best source.
+pub fn rank(quality: &Quality) -> (u16, usize) {
+    let source = quality.source.as_ref()
+        .and_then(|s| SOURCES.iter().position(|(_, name)| name == s))
+        .map_or(0, |i| SOURCES.len() - i);
+    (quality.resolution.unwrap_or(0), source)
+}
+
+/// Orders versions of the same movie or episode: the plain edition first, then by
+/// quality, with the path keeping the order stable.
+pub fn compare(a: &MediaVersion, b: &MediaVersion) -> Ordering {
+    (a.edition.is_some(), &a.edition).cmp(&(b.edition.is_some(), &b.edition))
+        .then_with(|| rank(&b.quality).cmp(&rank(&a.quality)))
+        .then_with(|| a.file_path.cmp(&b.file_path))
+}
+
+#[test]
+fn qualities() {
+    let quality = |resolution, source: Option<&str>| Quality { resolution, source: source.map(str::to_owned) };
+
+    let cases = vec![
+        ("Show S01E01.mp4", quality(None, None)),
+        ("Show S01E01 720p.mp4", quality(Some(720), None)),
+        ("Show.S01E01.1080p.BluRay.x264-GROUP.mp4", quality(Some(1080), Some("BluRay"))),
+        ("Show.S01E01.1080i.HDTV.mp4", quality(Some(1080), Some("HDTV"))),
+        ("Show.S01E01.2160p.WEB-DL.mp4", quality(Some(2160), Some("WEB-DL"))),
+        ("Show S01E01 [4K].mp4", quality(Some(2160), None)),
+        ("Show S01E01 UHD BluRay Remux.mp4", quality(Some(2160), Some("Remux"))),
+        ("Show.S01E01.WEBRip.mp4", quality(None, Some("WEBRip"))),
+        ("Show.S01E01.480p.DVDRip.mp4", quality(Some(480), Some("DVD"))),
+        ("Show S01E01 - The Web.mp4", quality(None, None)),
+        ("The.Web.S01E01.mp4", quality(None, None)),
+        ("Show.S01E01.1080p.web.h264-GROUP.mp4", quality(Some(1080), Some("WEB-DL"))),
+        ("Show S01E01 WEB x264.mp4", quality(None, Some("WEB-DL"))),
+        ("Movie (2019) WEB.mp4", quality(None, Some("WEB-DL"))),
+        ("Show S01E01 - 1080 Days.mp4", quality(None, None)),
+        ("Webster S01E01.mp4", quality(None, None)),
+        ("Show_S01E01_720p_HDTV.mp4", quality(Some(720), Some("HDTV"))),
+    ];
+
+    for (file_name, expected) in cases {
+        assert_eq!(expected, parse_quality(file_name), "{}", file_name);
+    }
+}
+
+#[test]
+fn version_names() {
+    let cases = vec![
+        ("/tv/Show/Show.S01E01.1080p.BluRay.mp4", None, "1080p BluRay"),
+        ("/tv/Show/Show.S01E01.720p.mp4", None, "720p"),
+        ("/tv/Show/Show.S01E01.HDTV.mp4", None, "HDTV"),
+        ("/tv/Show/Show S01E01.mp4", None, "Show S01E01.mp4"),
+        ("/movies/Alien (1979) {edition-Director's Cut} 2160p.mp4", Some("Director's Cut"), "Director's Cut - 2160p"),
+        ("/movies/Alien (1979) - Director's Cut.mp4", Some("Director's Cut"), "Director's Cut"),
+    ];
+
+    for (file_path, edition, expected) in cases {
+        match parse_version(Path::new(file_path), edition.map(str::to_owned)) {
+            Ok(MediaVersion { ref name, .. }) if name == expected => (),
+            result => assert!(false, "{}: {:?}", file_path, result)
+        }
+    }
+}
+
+#[test]
+fn ranks() {
+    let quality = |resolution, source: Option<&str>| Quality { resolution, source: source.map(str::to_owned) };
+
+    assert!(rank(&quality(Some(1080), None)) > rank(&quality(Some(720), Some("Remux"))));
+    assert!(rank(&quality(Some(1080), Some("BluRay"))) > rank(&quality(Some(1080), Some("WEB-DL"))));
+    assert!(rank(&quality(Some(1080), Some("HDTV"))) > rank(&quality(Some(1080), None)));
+    assert!(rank(&quality(None, Some("DVD"))) > rank(&quality(None, None)));
+}
+
+#[test]
+fn version_order() {
+    let version = |file_path: &str, edition: Option<&str>| parse_version(Path::new(file_path), edition.map(str::to_owned)).unwrap();
+    let mut versions = vec![
+        version("/movies/Alien (1979) - Director's Cut.mp4", Some("Director's Cut")),
+        version("/movies/Alien (1979) 720p.mp4", None),
+        version("/movies/Alien (1979) b.mp4", None),
+        version("/movies/Alien (1979) 1080p.mp4", None),
+        version("/movies/Alien (1979) a.mp4", None),
+    ];
+    versions.sort_by(compare);
+
+    let names = versions.iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(vec!["1080p", "720p", "Alien (1979) a.mp4", "Alien (1979) b.mp4", "Director's Cut"], names);
+}
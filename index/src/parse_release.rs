@@ -0,0 +1,151 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+/// Tokens that only appear in the technical part of a release name, after the title.
+const SCENE_TOKENS: &[&str] = &[
+    "4k", "uhd", "remux", "bluray", "blu-ray", "bdrip", "brrip", "bdremux", "web-dl", "webdl", "webrip",
+    "hdtv", "pdtv", "dvdrip", "dvdscr", "hdrip", "hdcam", "x264", "x265", "h264", "h265", "hevc", "avc",
+    "xvid", "divx", "10bit", "8bit", "hdr", "hdr10", "aac", "aac2", "ac3", "dts", "dts-hd", "truehd",
+    "atmos", "flac", "ddp5", "dd5", "ddp", "mp3",
+];
+
+/// Tags scene groups put between the title and the technical part, in capitals.
+const RELEASE_TAGS: &[&str] = &[
+    "PROPER", "REPACK", "RERIP", "EXTENDED", "UNRATED", "REMASTERED", "LIMITED", "INTERNAL", "MULTI",
+    "SUBBED", "DUBBED", "DC", "WEB", "DVD", "COMPLETE",
+];
+
+/// Abbreviations that keep their dot when dots separate the words of a name.
+const ABBREVIATIONS: &[&str] = &["Mr", "Mrs", "Ms", "Dr", "St", "Jr", "Sr", "Vs", "Vol"];
+
+/// Composes accented letters, as file names from macOS keep them decomposed
+/// and the same title would otherwise be two different movies or shows.
+pub fn nfc(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// Splits a movie or show name into its title and year, cleaning up scene
+/// release names such as `The.Matrix.1999.1080p.BluRay.x264-GROUP`.
+pub fn parse_release_name(name: &str) -> (String, Option<u16>) {
+    lazy_static! {
+        static ref YEAR_FORMAT: Regex = Regex::new(r"^[(\[]?((?:19|20)\d{2})[)\]]?(?:-\w+)?$").unwrap();
+        static ref RESOLUTION_FORMAT: Regex = Regex::new(r"(?i)^\d{3,4}[pi]$").unwrap();
+        static ref EPISODE_FORMAT: Regex = Regex::new(r"(?i)^s\d{1,4}(?:e\d{1,4})?$").unwrap();
+        static ref LEADING_GROUP_FORMAT: Regex = Regex::new(r"^\s*\[[^\]]*\]\s*").unwrap();
+        // Years further ahead belong to the title, as in `Blade Runner 2049`.
+        static ref LATEST_YEAR: u16 = current_year() + 1;
+    }
+
+    let name = nfc(name);
+    let name = LEADING_GROUP_FORMAT.replace(&name, "");
+    // Names without spaces use dots as separators, otherwise dots belong to the title.
+    let scene_style = !name.contains(' ');
+    let tokens = name
+        .split(|c: char| c == ' ' || c == '_' || (scene_style && c == '.'))
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
+
+    let is_scene_token = |token: &str| {
+        let token = token.trim_matches(|c| "()[]{}".contains(c));
+        let token = token.split('-').next().unwrap_or(token);
+        RESOLUTION_FORMAT.is_match(token)
+            || EPISODE_FORMAT.is_match(token)
+            || SCENE_TOKENS.iter().any(|t| t.eq_ignore_ascii_case(token))
+    };
+    let technical = tokens.iter().position(|t| is_scene_token(t)).unwrap_or(tokens.len());
+
+    // The last year before the technical part, as titles can start with or contain a year.
+    let year = tokens[..technical].iter().enumerate().skip(1).rev()
+        .filter_map(|(i, t)| Some((i, YEAR_FORMAT.captures(t)?.get(1)?.as_str().parse::<u16>().ok()?)))
+        .find(|&(_, year)| year <= *LATEST_YEAR);
+    let end = year.map_or(technical, |(i, _)| i);
+
+    let mut title = tokens[..end].to_vec();
+    while title.len() > 1 && title.last().is_some_and(|t| RELEASE_TAGS.contains(t)) {
+        title.pop();
+    }
+    let title = title.iter()
+        .map(|t| if scene_style && ABBREVIATIONS.contains(t) { format!("{}.", t) } else { (*t).to_owned() })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let title = title.trim_end_matches(|c: char| c == '-' || c == '(' || c == '[' || c.is_whitespace()).trim();
+
+    match title {
+        "" => (name.trim().to_owned(), None),
+        title => (title.to_owned(), year.map(|(_, y)| y)),
+    }
+}
+
+fn current_year() -> u16 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    // Seconds in an average Gregorian year.
+    1970 + (seconds / 31_556_952) as u16
+}
+
+#[test]
+fn release_names() {
+    let cases = vec![
+        // Plain names
+        ("The Matrix", "The Matrix", None),
+        ("The Matrix (1999)", "The Matrix", Some(1999)),
+        ("A Clockwork Orange (1971)", "A Clockwork Orange", Some(1971)),
+        ("American History X", "American History X", None),
+        ("Schindler's List (1993)", "Schindler's List", Some(1993)),
+        ("Charlotte's Web (1973)", "Charlotte's Web", Some(1973)),
+        ("Mr. Robot", "Mr. Robot", None),
+        ("Mr. Robot (2015)", "Mr. Robot", Some(2015)),
+        ("Dr. Strangelove (1964)", "Dr. Strangelove", Some(1964)),
+        ("2001: A Space Odyssey (1968)", "2001: A Space Odyssey", Some(1968)),
+        ("2001: A Space Odyssey", "2001: A Space Odyssey", None),
+        ("1917 (2019)", "1917", Some(2019)),
+        ("1984", "1984", None),
+        ("Blade Runner 2049 (2017)", "Blade Runner 2049", Some(2017)),
+        ("Blade Runner 2049", "Blade Runner 2049", None),
+        ("Spider-Man (2002)", "Spider-Man", Some(2002)),
+        ("WALL·E (2008)", "WALL·E", Some(2008)),
+        ("Amélie (2001)", "Amélie", Some(2001)),
+        ("Ocean's Eleven [2001]", "Ocean's Eleven", Some(2001)),
+        ("The Matrix 1999", "The Matrix", Some(1999)),
+        ("Alien (1979) 1080p", "Alien", Some(1979)),
+        ("Alien 1080p", "Alien", None),
+        ("Se7en (1995)", "Se7en", Some(1995)),
+        // Scene releases
+        ("The.Matrix.1999.1080p.BluRay.x264-GROUP", "The Matrix", Some(1999)),
+        ("The.Matrix.1999", "The Matrix", Some(1999)),
+        ("The.Matrix.1999-GROUP", "The Matrix", Some(1999)),
+        ("The_Matrix_1999_720p", "The Matrix", Some(1999)),
+        ("The Matrix 1999 1080p BluRay x264", "The Matrix", Some(1999)),
+        ("The.Matrix.1080p.BluRay.x264-GROUP", "The Matrix", None),
+        ("Blade.Runner.2049.2017.2160p.UHD.BluRay.REMUX.HDR.HEVC.Atmos-GROUP", "Blade Runner 2049", Some(2017)),
+        ("2012.2009.1080p.BluRay", "2012", Some(2009)),
+        ("1917.2019.1080p.WEB-DL.DDP5.1.H264-GROUP", "1917", Some(2019)),
+        ("Charlottes.Web.1973.1080p.WEBRip", "Charlottes Web", Some(1973)),
+        ("Inception.2010.PROPER.1080p.BluRay", "Inception", Some(2010)),
+        ("Inception.PROPER.1080p.BluRay", "Inception", None),
+        ("Inception.EXTENDED.2010.1080p", "Inception", Some(2010)),
+        ("Mr.Robot.S01.1080p.WEB-DL", "Mr. Robot", None),
+        ("Mr.Robot.S01E01.720p.HDTV.x264-GROUP", "Mr. Robot", None),
+        ("Dr.Strangelove.1964.1080p", "Dr. Strangelove", Some(1964)),
+        ("Spider-Man.Homecoming.2017.1080p", "Spider-Man Homecoming", Some(2017)),
+        ("The.Office.US.2005.S01.720p", "The Office US", Some(2005)),
+        ("Star.Wars.Episode.IV.A.New.Hope.1977.1080p", "Star Wars Episode IV A New Hope", Some(1977)),
+        ("Heat.1995.720p.HDTV.XviD.AC3", "Heat", Some(1995)),
+        ("Up.2009.DVDRip.XviD", "Up", Some(2009)),
+        ("Her.2013.[1080p]", "Her", Some(2013)),
+        ("[YTS.MX] Parasite (2019) [1080p]", "Parasite", Some(2019)),
+        ("[Group] Akira (1988)", "Akira", Some(1988)),
+        ("Amelie.2001.FRENCH.1080p", "Amelie", Some(2001)),
+        ("Seven.Samurai.1954.REMASTERED.1080p", "Seven Samurai", Some(1954)),
+        ("1080p", "1080p", None),
+        // Decomposed accents, as written by macOS
+        ("Ame\u{301}lie (2001)", "Amélie", Some(2001)),
+        ("Pok\u{e9}mon.2000.720p", "Pokémon", Some(2000)),
+    ];
+
+    for (name, expected_title, expected_year) in cases {
+        assert_eq!((expected_title.to_owned(), expected_year), parse_release_name(name), "{}", name);
+    }
+}
@@ -2,42 +2,387 @@ use std::path::Path;
 
 use failure::{Error, format_err};
 use lazy_static::lazy_static;
-use regex::Regex;
+use regex::{Captures, Regex};
 
-pub fn parse_season_and_episode(path: &Path) -> Result<(u16, u16), Error> {
+use crate::naming::NamingPatterns;
+use crate::parse_release;
+
+/// Which episode(s) a file contains, as worked out from its name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EpisodeNumbering {
+    /// `S01E02`, `1x02` or `Show - 102`, possibly covering several episodes.
+    Season { season: u16, episodes: Vec<u16> },
+    /// Daily shows named after the date they aired.
+    Date { year: u16, month: u16, day: u16 },
+    /// Anime style numbering counting up from the first episode of the show.
+    Absolute { episodes: Vec<u16> },
+}
+
+impl EpisodeNumbering {
+    /// The series and episode numbers the file should be listed under.
+    ///
+    /// Daily shows get a series per year with the episode numbered `MMDD`,
+    /// absolute numbering is all put in the first series.
+    pub fn season_and_episodes(&self) -> (u16, Vec<u16>) {
+        match self {
+            EpisodeNumbering::Season { season, episodes } => (*season, episodes.clone()),
+            EpisodeNumbering::Date { year, month, day } => (*year, vec![month * 100 + day]),
+            EpisodeNumbering::Absolute { episodes } => (1, episodes.clone()),
+        }
+    }
+}
+
+/// Where a file belongs in a show once its season folder has been taken into account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpisodeLocation {
+    pub season: u16,
+    pub episodes: Vec<u16>,
+    /// The season of the folder the file is in, when it disagrees with the file name.
+    pub conflicting_season: Option<u16>,
+}
+
+/// Works out which episodes a file inside `show_path` contains, falling back
+/// to the `Season 01`/`S1`/`Specials` folder it is in when the file name
+/// does not give the season. The user's naming patterns are tried first.
+pub fn locate_episodes(show_path: &Path, path: &Path, naming: &NamingPatterns) -> Result<EpisodeLocation, Error> {
+    let folder_season = parse_season_folder(show_path, path);
+
+    if let Some(found) = path.file_stem().and_then(|f| naming.episode(&f.to_string_lossy())) {
+        let season = found.season.or(folder_season).unwrap_or(1);
+        return Ok(EpisodeLocation { season, episodes: vec![found.episode], conflicting_season: folder_season.filter(|f| *f != season) });
+    }
+
+    match parse_episodes(path) {
+        Ok(EpisodeNumbering::Absolute { episodes }) if folder_season.is_some() => {
+            Ok(EpisodeLocation { season: folder_season.unwrap_or(1), episodes, conflicting_season: None })
+        },
+        Ok(numbering @ EpisodeNumbering::Season { .. }) => {
+            let (season, episodes) = numbering.season_and_episodes();
+            Ok(EpisodeLocation { season, episodes, conflicting_season: folder_season.filter(|f| *f != season) })
+        },
+        Ok(numbering) => {
+            let (season, episodes) = numbering.season_and_episodes();
+            Ok(EpisodeLocation { season, episodes, conflicting_season: None })
+        },
+        Err(err) => match (folder_season, parse_episode(path)) {
+            (Some(season), Some(episode)) => Ok(EpisodeLocation { season, episodes: vec![episode], conflicting_season: None }),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Season number of the closest `Season 01`, `Series 2`, `S1` or `Specials`
+/// (season 0) folder between `show_path` and the file.
+pub fn parse_season_folder(show_path: &Path, path: &Path) -> Option<u16> {
     lazy_static! {
-        static ref SEASON_EPISODE_FORMAT_1: Regex = Regex::new(r"[Ss](\d*?)[Ee](\d*)").unwrap();
+        static ref SEASON_FOLDER_FORMAT: Regex = Regex::new(r"(?i)^(?:season|series|s)[ ._-]*(\d{1,4})$").unwrap();
+        static ref SPECIALS_FOLDER_FORMAT: Regex = Regex::new(r"(?i)^specials?$").unwrap();
     }
 
-    let file_name =
-        path.file_name().ok_or(format_err!("failed to parse filename"))?
-            .to_str().ok_or(format_err!("failed to parse filename"))?;
-            
-    let cap = SEASON_EPISODE_FORMAT_1.captures_iter(file_name).nth(0).ok_or(format_err!("could not parse season or episode number"))?;
+    path.strip_prefix(show_path).ok()?.parent()?
+        .components().rev()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .filter_map(|folder| {
+            if SPECIALS_FOLDER_FORMAT.is_match(&folder) {
+                return Some(0);
+            }
+            SEASON_FOLDER_FORMAT.captures(&folder)?.get(1)?.as_str().parse::<u16>().ok()
+        })
+        .next()
+}
 
-    let season = cap.get(1).map(|m| m.as_str()).ok_or(format_err!("could not parse season or episode number"))?.parse::<u16>()?;
-    let episode = cap.get(2).map(|m| m.as_str()).ok_or(format_err!("could not parse season or episode number"))?.parse::<u16>()?;
+/// Episode number of a file that does not say which season it is from,
+/// e.g. `Episode 5.mp4` or `05 - Title.mp4`.
+fn parse_episode(path: &Path) -> Option<u16> {
+    lazy_static! {
+        static ref EPISODE_FORMAT_1: Regex = Regex::new(r"(?i)(?:^|[^a-z0-9])(?:e|ep|episode)[ ._-]*(\d{1,4})(?:[^0-9]|$)").unwrap();
+        static ref EPISODE_FORMAT_2: Regex = Regex::new(r"^(\d{1,3})(?:[ ._-]|$)").unwrap();
+    }
 
-    Ok((season, episode))
+    let file_name = path.file_stem()?.to_string_lossy();
+    EPISODE_FORMAT_1.captures(&file_name)
+        .or_else(|| EPISODE_FORMAT_2.captures(&file_name))?
+        .get(1)?.as_str().parse::<u16>().ok()
 }
 
-pub fn parse_title<'a>(base_path: &Path, path: &'a Path) -> Result<(&'a str, Option<u16>), Error> {
+pub fn parse_episodes(path: &Path) -> Result<EpisodeNumbering, Error> {
     lazy_static! {
-        static ref TITLE_FORMAT_1: Regex = Regex::new(r"([^']+)\s+\((\d{4})\)").unwrap();
+        static ref SEASON_EPISODE_FORMAT_1: Regex = Regex::new(r"(?i)(?:^|[^a-z0-9])s(\d{1,4})[ ._-]?e(\d{1,4})").unwrap();
+        static ref SEASON_EPISODE_FORMAT_1_NEXT: Regex = Regex::new(r"(?i)^(?:-e?(\d{1,4})|[ ._]?e(\d{1,4}))(?:[^0-9pi]|$)").unwrap();
+        static ref SEASON_EPISODE_FORMAT_2: Regex = Regex::new(r"(?i)(?:^|[^a-z0-9])(\d{1,2})x(\d{2,3})(?:[^0-9]|$)").unwrap();
+        static ref SEASON_EPISODE_FORMAT_2_NEXT: Regex = Regex::new(r"(?i)^(?:-(\d{2,3})|x(\d{2,3}))(?:[^0-9pi]|$)").unwrap();
+        static ref DATE_FORMAT: Regex = Regex::new(r"(?:^|[^0-9])((?:19|20)\d{2})[ ._-](\d{2})[ ._-](\d{2})(?:[^0-9]|$)").unwrap();
+        static ref ABSOLUTE_FORMAT: Regex = Regex::new(r"(?i)^\[[^\]]*\].*?[ ._]-[ ._](\d{1,4})(?:-(\d{1,4}))?(?:v\d+)?(?:[^0-9]|$)").unwrap();
+        static ref SEASON_EPISODE_FORMAT_3: Regex = Regex::new(r"(?:^|\s-\s)(\d)(\d{2})(?:[\s._-]|$)").unwrap();
+    }
+
+    let file_name = path.file_stem().ok_or(format_err!("failed to parse filename"))?.to_string_lossy();
+    let file_name = &*file_name;
+
+    if let Some(cap) = SEASON_EPISODE_FORMAT_1.captures(file_name) {
+        let season = number(&cap, 1)?;
+        let episodes = following_episodes(&cap, file_name, &SEASON_EPISODE_FORMAT_1_NEXT)?;
+        return Ok(EpisodeNumbering::Season { season, episodes });
+    }
+
+    if let Some(cap) = SEASON_EPISODE_FORMAT_2.captures(file_name) {
+        let season = number(&cap, 1)?;
+        let episodes = following_episodes(&cap, file_name, &SEASON_EPISODE_FORMAT_2_NEXT)?;
+        return Ok(EpisodeNumbering::Season { season, episodes });
+    }
+
+    if let Some(cap) = DATE_FORMAT.captures(file_name) {
+        let (year, month, day) = (number(&cap, 1)?, number(&cap, 2)?, number(&cap, 3)?);
+        if (1..=12).contains(&month) && (1..=31).contains(&day) {
+            return Ok(EpisodeNumbering::Date { year, month, day });
+        }
+    }
+
+    if let Some(cap) = ABSOLUTE_FORMAT.captures(file_name) {
+        let first = number(&cap, 1)?;
+        let last = match cap.get(2) {
+            Some(last) => last.as_str().parse::<u16>()?,
+            None => first,
+        };
+        let episodes = if last > first && last - first < MAX_EPISODES_PER_FILE {
+            (first..=last).collect()
+        } else {
+            vec![first]
+        };
+        return Ok(EpisodeNumbering::Absolute { episodes });
+    }
+
+    if let Some(cap) = SEASON_EPISODE_FORMAT_3.captures(file_name) {
+        return Ok(EpisodeNumbering::Season { season: number(&cap, 1)?, episodes: vec![number(&cap, 2)?] });
+    }
+
+    Err(format_err!("could not parse season or episode number"))
+}
+
+/// Ranges of more episodes than this in one file are taken to be something else, such as the
+/// year in `[Group] Show - 01-2019`, and only the first episode is kept.
+const MAX_EPISODES_PER_FILE: u16 = 50;
+
+fn number(cap: &Captures, group: usize) -> Result<u16, Error> {
+    Ok(cap.get(group).map(|m| m.as_str()).ok_or(format_err!("could not parse season or episode number"))?.parse::<u16>()?)
+}
+
+/// Reads the episode in group 2 of `cap` plus any ranges (`-E05`, group 1 of
+/// `next`) or extra episodes (`E02E03`, group 2 of `next`) that directly follow it.
+fn following_episodes(cap: &Captures, file_name: &str, next: &Regex) -> Result<Vec<u16>, Error> {
+    let mut episodes = vec![number(cap, 2)?];
+    let mut rest = &file_name[cap.get(2).map_or(file_name.len(), |m| m.end())..];
+
+    while let Some(next_cap) = next.captures(rest) {
+        let (m, is_range) = match (next_cap.get(1), next_cap.get(2)) {
+            (Some(m), _) => (m, true),
+            (None, Some(m)) => (m, false),
+            (None, None) => break,
+        };
+        let episode = m.as_str().parse::<u16>()?;
+        let last = *episodes.last().unwrap_or(&0);
+        if episode <= last || is_range && episode - last >= MAX_EPISODES_PER_FILE {
+            break;
+        }
+        if is_range {
+            episodes.extend(last + 1..=episode);
+        } else {
+            episodes.push(episode);
+        }
+        rest = &rest[m.end()..];
     }
 
+    Ok(episodes)
+}
+
+/// The show's title and year, from the name of its folder.
+pub fn parse_title(base_path: &Path, path: &Path) -> Result<(String, Option<u16>), Error> {
     let folder_name =
         path.strip_prefix(base_path)?.components().next().ok_or(format_err!("failed to parse folder"))?
-            .as_os_str().to_str().ok_or(format_err!("failed to parse folder"))?;
-            
-    match TITLE_FORMAT_1.captures_iter(folder_name).nth(0) {
-        Some (cap) => {
-            let title = cap.get(1).map(|m| m.as_str()).ok_or(format_err!("failed to parse title"))?;
-            let year = cap.get(2).map(|m| m.as_str()).ok_or(format_err!("failed to parse year"))?.parse::<u16>()?;
-            Ok((title, Some(year)))
-        },
-        None => {
-            Ok((folder_name, None))
-        },
+            .as_os_str().to_string_lossy();
+
+    Ok(parse_release::parse_release_name(&folder_name))
+}
+
+#[test]
+fn episode_numbering() {
+    use self::EpisodeNumbering::*;
+
+    let cases = vec![
+        ("Show S01E01.mp4", Season { season: 1, episodes: vec![1] }),
+        ("Show s02e10.mp4", Season { season: 2, episodes: vec![10] }),
+        ("Show.S10E100.720p.HDTV.mp4", Season { season: 10, episodes: vec![100] }),
+        ("Show S01 E02.mp4", Season { season: 1, episodes: vec![2] }),
+        ("Show S01.E02.mp4", Season { season: 1, episodes: vec![2] }),
+        ("Show S00E01 - Christmas Special.mp4", Season { season: 0, episodes: vec![1] }),
+        ("Show S01E01E02.mp4", Season { season: 1, episodes: vec![1, 2] }),
+        ("Show S01E01E02E03.mp4", Season { season: 1, episodes: vec![1, 2, 3] }),
+        ("Show S01E01 E02.mp4", Season { season: 1, episodes: vec![1, 2] }),
+        ("Show S01E01-E03.mp4", Season { season: 1, episodes: vec![1, 2, 3] }),
+        ("Show S01E01-03.mp4", Season { season: 1, episodes: vec![1, 2, 3] }),
+        ("Show S01E01-720p.mp4", Season { season: 1, episodes: vec![1] }),
+        ("Show S01E02-E01.mp4", Season { season: 1, episodes: vec![2] }),
+        ("Show S01E01-E999.mp4", Season { season: 1, episodes: vec![1] }),
+        ("Show S01E01 - Pilot.mp4", Season { season: 1, episodes: vec![1] }),
+        ("Show - 1x02.mp4", Season { season: 1, episodes: vec![2] }),
+        ("Show 12x103.mp4", Season { season: 12, episodes: vec![103] }),
+        ("Show 1x02x03.mp4", Season { season: 1, episodes: vec![2, 3] }),
+        ("Show 1x02-04.mp4", Season { season: 1, episodes: vec![2, 3, 4] }),
+        ("Show - 102.mp4", Season { season: 1, episodes: vec![2] }),
+        ("Show - 923 - Title.mp4", Season { season: 9, episodes: vec![23] }),
+        ("102 - Title.mp4", Season { season: 1, episodes: vec![2] }),
+        ("Show 2019.03.14.mp4", Date { year: 2019, month: 3, day: 14 }),
+        ("Show - 2019-03-14 - Guest.mp4", Date { year: 2019, month: 3, day: 14 }),
+        ("Show 1999 12 31.mp4", Date { year: 1999, month: 12, day: 31 }),
+        ("[Group] Show - 153.mp4", Absolute { episodes: vec![153] }),
+        ("[Group] Show - 153 [1080p].mp4", Absolute { episodes: vec![153] }),
+        ("[Group] Show - 07v2 [720p].mp4", Absolute { episodes: vec![7] }),
+        ("[Group] Show - 1000.mp4", Absolute { episodes: vec![1000] }),
+        ("[Group] Show - 01-03.mp4", Absolute { episodes: vec![1, 2, 3] }),
+        ("[Group] Show - 03-01.mp4", Absolute { episodes: vec![3] }),
+        ("[Group] Show - 01-1000.mp4", Absolute { episodes: vec![1] }),
+        ("[Group] Show Two - Part - 12.mp4", Absolute { episodes: vec![12] }),
+        ("[Group] Show - S02E03.mp4", Season { season: 2, episodes: vec![3] }),
+    ];
+
+    for (file_name, expected) in cases {
+        match parse_episodes(Path::new(file_name)) {
+            Ok(ref numbering) if *numbering == expected => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[test]
+fn unparseable_episode_numbering() {
+    let cases = vec![
+        "Show.mp4",
+        "Show Extras.mp4",
+        "Show 720p.mp4",
+        "Show H.264.mp4",
+        "Show 2019.13.14.mp4",
+        "Show - 1999.mp4",
+        "Show 1920x1080.mp4",
+    ];
+
+    for file_name in cases {
+        match parse_episodes(Path::new(file_name)) {
+            Err(_) => (),
+            result => assert!(false, "{}: {:?}", file_name, result)
+        }
+    }
+}
+
+#[test]
+fn season_and_episodes() {
+    assert_eq!((2019, vec![314]), EpisodeNumbering::Date { year: 2019, month: 3, day: 14 }.season_and_episodes());
+    assert_eq!((1, vec![153]), EpisodeNumbering::Absolute { episodes: vec![153] }.season_and_episodes());
+    assert_eq!((3, vec![1, 2]), EpisodeNumbering::Season { season: 3, episodes: vec![1, 2] }.season_and_episodes());
+}
+
+#[test]
+fn season_folders() {
+    let cases = vec![
+        ("/tv/Show/Season 01/Show S01E01.mp4", Some(1)),
+        ("/tv/Show/season 2/Show S02E01.mp4", Some(2)),
+        ("/tv/Show/Series 3/Show S03E01.mp4", Some(3)),
+        ("/tv/Show/S4/Show S04E01.mp4", Some(4)),
+        ("/tv/Show/Season.05/Show S05E01.mp4", Some(5)),
+        ("/tv/Show/Specials/Show S00E01.mp4", Some(0)),
+        ("/tv/Show/Special/Show S00E01.mp4", Some(0)),
+        ("/tv/Show/Season 01/Extras/Show S01E01.mp4", Some(1)),
+        ("/tv/Show/Season 01/Season 02/Show S02E01.mp4", Some(2)),
+        ("/tv/Show/Show S01E01.mp4", None),
+        ("/tv/Show/Seasonal/Show S01E01.mp4", None),
+        ("/tv/Season 01/Show S01E01.mp4", None),
+    ];
+
+    for (file_path, expected) in cases {
+        assert_eq!(expected, parse_season_folder(Path::new("/tv/Show"), Path::new(file_path)), "{}", file_path);
+    }
+}
+
+#[test]
+fn episode_locations() {
+    let location = |season, episodes: Vec<u16>, conflicting_season| EpisodeLocation { season, episodes, conflicting_season };
+
+    let cases = vec![
+        ("/tv/Show/Season 01/Show S01E01.mp4", location(1, vec![1], None)),
+        ("/tv/Show/Show S01E01.mp4", location(1, vec![1], None)),
+        ("/tv/Show/Season 02/Show S01E01.mp4", location(1, vec![1], Some(2))),
+        ("/tv/Show/Specials/Show S00E03.mp4", location(0, vec![3], None)),
+        ("/tv/Show/Specials/Episode 3.mp4", location(0, vec![3], None)),
+        ("/tv/Show/Season 2/Episode 3.mp4", location(2, vec![3], None)),
+        ("/tv/Show/Season 2/Ep.03.mp4", location(2, vec![3], None)),
+        ("/tv/Show/Season 2/03 - Title.mp4", location(2, vec![3], None)),
+        ("/tv/Show/Season 2/[Group] Show - 153.mp4", location(2, vec![153], None)),
+        ("/tv/Show/[Group] Show - 153.mp4", location(1, vec![153], None)),
+        ("/tv/Show/Season 5/Show 2019.03.14.mp4", location(2019, vec![314], None)),
+    ];
+
+    for (file_path, expected) in cases {
+        match locate_episodes(Path::new("/tv/Show"), Path::new(file_path), &NamingPatterns::default()) {
+            Ok(ref location) if *location == expected => (),
+            result => assert!(false, "{}: {:?}", file_path, result)
+        }
+    }
+
+    for file_path in vec!["/tv/Show/Episode 3.mp4", "/tv/Show/Season 2/Title.mp4"] {
+        match locate_episodes(Path::new("/tv/Show"), Path::new(file_path), &NamingPatterns::default()) {
+            Err(_) => (),
+            result => assert!(false, "{}: {:?}", file_path, result)
+        }
+    }
+}
+
+#[test]
+fn naming_patterns() {
+    let naming = NamingPatterns::new(&[], &[r"^ep(?P<season>\d+)_(?P<episode>\d+)$".to_owned(), r"^part (?P<episode>\d+)$".to_owned()]).unwrap();
+    let location = |season, episodes: Vec<u16>, conflicting_season| EpisodeLocation { season, episodes, conflicting_season };
+
+    let cases = vec![
+        ("/tv/Show/ep2_05.mp4", location(2, vec![5], None)),
+        ("/tv/Show/Season 3/ep2_05.mp4", location(2, vec![5], Some(3))),
+        ("/tv/Show/Season 3/part 4.mp4", location(3, vec![4], None)),
+        ("/tv/Show/part 4.mp4", location(1, vec![4], None)),
+        ("/tv/Show/Show S01E02.mp4", location(1, vec![2], None)),
+    ];
+
+    for (file_path, expected) in cases {
+        match locate_episodes(Path::new("/tv/Show"), Path::new(file_path), &naming) {
+            Ok(ref location) if *location == expected => (),
+            result => assert!(false, "{}: {:?}", file_path, result)
+        }
+    }
+}
+
+#[test]
+fn titles() {
+    let cases = vec![
+        ("Mr. Robot/Season 1/Mr. Robot S01E01.mkv", "Mr. Robot", None),
+        ("Doctor Who (2005)/Season 1/Doctor Who S01E01.mkv", "Doctor Who", Some(2005)),
+        ("Mr.Robot.S01.1080p.WEB-DL.x264-GROUP/Mr.Robot.S01E01.mkv", "Mr. Robot", None),
+        ("The.Office.US.2005.S01.720p/The.Office.US.S01E01.mkv", "The Office US", Some(2005)),
+        ("Pokémon (1997)/Season 1/Pokémon S01E01.mkv", "Pokémon", Some(1997)),
+        ("Poke\u{301}mon (1997)/Season 1/Poke\u{301}mon S01E01.mkv", "Pokémon", Some(1997)),
+    ];
+
+    for (file_name, expected_title, expected_year) in cases {
+        let path = Path::new("/tv/").join(file_name);
+        assert_eq!((expected_title.to_owned(), expected_year), parse_title(Path::new("/tv/"), &path).unwrap(), "{}", file_name);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let show = Path::new("/tv/").join(OsStr::from_bytes(b"Pok\xe9mon (1997)"));
+    assert_eq!(("Pok\u{fffd}mon".to_owned(), Some(1997)), parse_title(Path::new("/tv/"), &show.join("S01E01.mkv")).unwrap());
+    match locate_episodes(&show, &show.join(OsStr::from_bytes(b"Season 1/Pok\xe9mon S01E02.mkv")), &NamingPatterns::default()) {
+        Ok(EpisodeLocation { season: 1, ref episodes, .. }) if *episodes == vec![2] => (),
+        result => assert!(false, "{:?}", result)
     }
 }
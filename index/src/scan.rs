@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use failure::{Error, format_err};
+use ignore::WalkBuilder;
+use lazy_static::lazy_static;
+use log::{trace, warn};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::prelude::*;
+
+use data::Fingerprint;
+
+use crate::fingerprint::FingerprintCache;
+
+lazy_static! {
+    static ref FILE_TYPES: HashSet<&'static str> = HashSet::from_iter(vec!["ogg", "mp4", "m4v", "webm"]);
+}
+
+/// Files listing, with gitignore syntax, what to leave out of the library below the folder they are in.
+pub const IGNORE_FILE_NAME: &str = ".carolusignore";
+
+/// Folders that NAS boxes and operating systems leave around, which never hold the library's media.
+const JUNK_FOLDERS: &[&str] = &[
+    "@eaDir", "@Recycle", "#recycle", "#snapshot", ".Trash", ".Trashes", "lost+found", "$RECYCLE.BIN",
+    "System Volume Information",
+];
+
+/// How the library folders are walked.
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Whether symlinked files and folders are indexed. Symlinks that loop back
+    /// on a folder above them are skipped either way.
+    pub follow_symlinks: bool,
+    /// How many folders deep to look inside a movie or show folder, unlimited when `None`.
+    pub max_depth: Option<usize>,
+    /// How many folders are read and parsed at the same time. Spinning disks and
+    /// network mounts slow down when asked for too much at once, so keep it low for them.
+    pub jobs: usize,
+    /// Fingerprints of the files seen before, shared between scans.
+    pub fingerprints: Arc<FingerprintCache>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions { follow_symlinks: true, max_depth: None, jobs: 4, fingerprints: Arc::new(FingerprintCache::default()) }
+    }
+}
+
+#[cfg(unix)]
+type FileId = (u64, u64);
+
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+/// Identifies a file however it is reached, through hardlinks and symlinks included.
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path) -> Option<FileId> {
+    fs::canonicalize(path).ok()
+}
+
+/// Walks the library folders for a single scan, so that a file reachable from
+/// several places is only indexed the first time it is found.
+pub struct Scanner {
+    options: ScanOptions,
+    seen: HashSet<FileId>,
+    pool: ThreadPool,
+}
+
+impl Scanner {
+    pub fn new(options: &ScanOptions) -> Result<Self, Error> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(options.jobs.max(1))
+            .thread_name(|i| format!("scan-{}", i))
+            .build()
+            .map_err(|e| format_err!("could not start the scanner: {}", e))?;
+        Ok(Scanner { options: options.clone(), seen: HashSet::new(), pool })
+    }
+
+    /// Runs work, such as parsing what was found, on the scanner's threads.
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(op)
+    }
+
+    /// The folders and video files directly inside the movie or tv directory.
+    pub fn entries(&mut self, folder: &Path) -> Result<Vec<PathBuf>, Error> {
+        let entries = self.walk(folder, Some(1))?.into_iter()
+            .filter(|path| path.is_dir() || is_video_file(path))
+            .collect();
+        Ok(self.unseen(entries))
+    }
+
+    /// Identifies the content of a video file, to follow it when it is renamed or moved.
+    pub fn fingerprint(&self, path: &Path) -> Option<Fingerprint> {
+        self.options.fingerprints.get(path)
+    }
+
+    /// The video files anywhere inside each of several movie or show folders, which
+    /// are walked at the same time.
+    pub fn video_files(&mut self, folders: &[PathBuf]) -> Vec<Result<Vec<PathBuf>, Error>> {
+        let found = self.install(|| folders.par_iter().map(|folder| {
+            Ok(self.walk(folder, self.options.max_depth)?.into_iter()
+                .filter(|path| path.is_file() && is_video_file(path))
+                .collect::<Vec<_>>())
+        }).collect::<Vec<_>>());
+        // Duplicates are dropped afterwards, in order, so the same copy is kept whichever folder is read first.
+        found.into_iter().map(|files| files.map(|files| self.unseen(files))).collect()
+    }
+
+    fn walk(&self, folder: &Path, max_depth: Option<usize>) -> Result<Vec<PathBuf>, Error> {
+        if !folder.is_dir() {
+            return Err(format_err!("{:?} is not a folder", folder));
+        }
+
+        let mut paths = vec![];
+        let walker = WalkBuilder::new(folder)
+            .follow_links(self.options.follow_symlinks)
+            .max_depth(max_depth)
+            .hidden(true)
+            .parents(true)
+            .ignore(false)
+            .git_ignore(false)
+            .git_global(false)
+            .git_exclude(false)
+            .add_custom_ignore_filename(IGNORE_FILE_NAME)
+            .filter_entry(|entry| !is_junk(entry.file_name()))
+            .build();
+        for entry in walker {
+            match entry {
+                // The walker never follows a symlink it was told not to, but still lists it.
+                Ok(ref entry) if entry.depth() == 0 || (entry.path_is_symlink() && !self.options.follow_symlinks) => (),
+                Ok(entry) => paths.push(entry.path().to_path_buf()),
+                Err(err) => warn!("Skipped part of {:?}: {}", folder, err),
+            }
+        }
+        // Sorted so that the same copy of a file is kept from one scan to the next,
+        // preferring the file itself over symlinks to it.
+        paths.sort_by_cached_key(|path| (fs::symlink_metadata(path).map(|m| m.file_type().is_symlink()).unwrap_or(false), path.clone()));
+        Ok(paths)
+    }
+
+    /// Drops the files already found, keeping folders as they are.
+    fn unseen(&mut self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        let seen = &mut self.seen;
+        paths.into_iter()
+            .filter(|path| {
+                if path.is_dir() {
+                    return true;
+                }
+                match file_id(path) {
+                    Some(id) if !seen.insert(id) => {
+                        trace!("Skipped {:?}, it was already found elsewhere", path);
+                        false
+                    },
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+}
+
+fn is_junk(name: &OsStr) -> bool {
+    JUNK_FOLDERS.iter().any(|junk| name == OsStr::new(junk))
+}
+
+/// Whether a file has the extension of a video file that can be played.
+pub fn is_video_file(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| FILE_TYPES.contains(&*e))
+}
+
+/// When a file was added to the library, which is taken to be when it was last modified,
+/// in seconds since the epoch.
+pub fn added(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+#[test]
+fn video_files_only() {
+    let cases = vec![
+        ("/movies/Alien (1979).mp4", true),
+        ("/movies/Alien (1979).MP4", true),
+        ("/movies/Alien (1979)/Alien.m4v", true),
+        ("/movies/Alien (1979)/Alien.srt", false),
+        ("/movies/Alien (1979)/Alien", false),
+        ("/movies/Amélie (2001).webm", true),
+    ];
+
+    for (file_path, expected) in cases {
+        assert_eq!(expected, is_video_file(Path::new(file_path)), "{}", file_path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn scanning() {
+    use std::os::unix::fs::symlink;
+
+    let root = std::env::temp_dir().join(format!("carolus-scan-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let show = root.join("Show");
+    for folder in &["Season 1/Extras", "Season 2", "@eaDir", ".Trash-1000", "lost+found", "Samples", "A/B/C"] {
+        fs::create_dir_all(show.join(folder)).unwrap();
+    }
+    for file in &["Season 1/S01E01.mp4", "Season 1/S01E01.srt", "Season 1/Extras/Blooper.mp4", "Season 2/S02E01.MP4",
+                  "Season 2/.S02E01.mp4", "@eaDir/S01E01.mp4", ".Trash-1000/S01E03.mp4", "lost+found/S01E04.mp4",
+                  "Samples/S01E01-sample.mp4", "Season 2/S02E02.sample.mp4", "A/B/C/S03E01.mp4"] {
+        fs::write(show.join(file), b"").unwrap();
+    }
+    fs::write(show.join(IGNORE_FILE_NAME), b"Samples/\n*.sample.mp4\n").unwrap();
+    fs::hard_link(show.join("Season 1/S01E01.mp4"), show.join("Season 1/S01E01 copy.mp4")).unwrap();
+    symlink(show.join("Season 2"), show.join("Season 2/Loop")).unwrap();
+    symlink(show.join("Season 2/S02E01.MP4"), show.join("S02E01 link.mp4")).unwrap();
+
+    let relative = |paths: Vec<PathBuf>| paths.iter()
+        .map(|p| p.strip_prefix(&show).unwrap().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    let video_files = |options: ScanOptions| Scanner::new(&options).unwrap().video_files(&[show.clone()]).remove(0).unwrap();
+
+    let files = video_files(ScanOptions::default());
+    assert_eq!(vec!["A/B/C/S03E01.mp4", "Season 1/Extras/Blooper.mp4", "Season 1/S01E01 copy.mp4", "Season 2/S02E01.MP4"],
+        relative(files));
+
+    let files = video_files(ScanOptions { follow_symlinks: false, max_depth: Some(2), jobs: 1, ..ScanOptions::default() });
+    assert_eq!(vec!["Season 1/S01E01 copy.mp4", "Season 2/S02E01.MP4"], relative(files));
+
+    let entries = Scanner::new(&ScanOptions::default()).unwrap().entries(&root).unwrap();
+    assert_eq!(vec![show.clone()], entries);
+
+    fs::remove_dir_all(&root).unwrap();
+}
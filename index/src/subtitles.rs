@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SUBTITLE_TYPES: &[&str] = &["srt", "vtt", "ass", "ssa"];
+
+/// A subtitle file kept next to a video, named after it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubtitleFile {
+    pub file_path: PathBuf,
+    /// What follows the video's name, such as `en` in `Alien (1979).en.srt`.
+    pub language: Option<String>,
+}
+
+/// The subtitle files next to a video, in name order.
+pub fn subtitle_files(video: &Path) -> Vec<SubtitleFile> {
+    let (folder, stem) = match (video.parent(), video.file_stem()) {
+        (Some(folder), Some(stem)) => (folder, stem.to_string_lossy()),
+        _ => return vec![],
+    };
+    let mut subtitles = fs::read_dir(folder).into_iter()
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter_map(|entry| {
+            let language = subtitle_language(&stem, &entry.file_name().to_string_lossy())?;
+            Some(SubtitleFile { file_path: entry.path(), language })
+        })
+        .collect::<Vec<_>>();
+    subtitles.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    subtitles
+}
+
+/// `None` when the file isn't a subtitle for the video, otherwise its language, if named.
+fn subtitle_language(video_stem: &str, file_name: &str) -> Option<Option<String>> {
+    let dot = file_name.rfind('.')?;
+    let (stem, extension) = (&file_name[..dot], file_name[dot + 1..].to_lowercase());
+    if !SUBTITLE_TYPES.contains(&&*extension) {
+        return None;
+    }
+    if stem == video_stem {
+        return Some(None);
+    }
+    if stem.starts_with(video_stem) && stem[video_stem.len()..].starts_with('.') {
+        return Some(Some(stem[video_stem.len() + 1..].to_owned()).filter(|l| !l.is_empty()));
+    }
+    None
+}
+
+#[test]
+fn subtitle_names() {
+    let cases = vec![
+        ("Alien (1979).srt", Some(None)),
+        ("Alien (1979).SRT", Some(None)),
+        ("Alien (1979).en.srt", Some(Some("en"))),
+        ("Alien (1979).en.forced.vtt", Some(Some("en.forced"))),
+        ("Alien (1979).mp4", None),
+        ("Alien (1979) - Director's Cut.srt", None),
+        ("Aliens (1986).srt", None),
+        ("Alien (1979)", None),
+    ];
+
+    for (file_name, expected) in cases {
+        let expected = expected.map(|l: Option<&str>| l.map(str::to_owned));
+        assert_eq!(expected, subtitle_language("Alien (1979)", file_name), "{}", file_name);
+    }
+}
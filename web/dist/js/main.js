@@ -1,3 +1,20 @@
+(function () {
+    // Movies split over several files play their parts back-to-back.
+    var video = document.querySelector("video[data-parts]");
+    if (!video) {
+        return;
+    }
+    var source = video.querySelector("source");
+    var parts = Number(video.getAttribute("data-parts"));
+    var part = 0;
+    video.addEventListener("ended", function () {
+        if (++part < parts) {
+            video.src = source.getAttribute("src") + "&part=" + part;
+            video.play();
+        }
+    });
+})();
+
 (function () {
     function getResults(q, cb) {
         if (!q) {
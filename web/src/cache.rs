@@ -0,0 +1,20 @@
+/// Name of the directory in the cache for what `key` names. It has to come out the same on every
+/// platform and after every upgrade or everything cached is made again, which the hasher of the
+/// standard library doesn't promise, so this is 64-bit FNV-1a, wide enough for a library of
+/// hundreds of thousands of files not to have two share a directory.
+pub fn dir_name(key: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[test]
+fn dir_names() {
+    assert_eq!("cbf29ce484222325", dir_name(""));
+    assert_eq!("af63dc4c8601ec8c", dir_name("a"));
+    assert_eq!("85944171f73967e8", dir_name("foobar"));
+    assert_ne!(dir_name("/movies/Alien.mp4"), dir_name("/movies/Aliens.mp4"));
+}
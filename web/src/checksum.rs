@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix_web::actix::*;
+use log::warn;
+
+use data::error::Error;
+use index::FingerprintCache;
+
+/// Works out the checksums of files to download on its own thread, as that
+/// means reading every byte of them.
+pub struct ChecksumExecutor(pub Arc<FingerprintCache>);
+
+impl Actor for ChecksumExecutor {
+    type Context = SyncContext<Self>;
+}
+
+/// The CRC-32 of each file, `None` for those that can't be read.
+pub struct ChecksumMessage(pub Vec<PathBuf>);
+
+type ChecksumResult = Result<Vec<Option<String>>, Error>;
+
+impl Message for ChecksumMessage {
+    type Result = ChecksumResult;
+}
+
+impl Handler<ChecksumMessage> for ChecksumExecutor {
+    type Result = ChecksumResult;
+
+    fn handle(&mut self, msg: ChecksumMessage, _: &mut Self::Context) -> Self::Result {
+        let checksums = msg.0.iter().map(|path| self.0.checksum(path)).collect();
+        if let Err(err) = self.0.save() {
+            warn!("Could not save file checksums, err: {}", err);
+        }
+        Ok(checksums)
+    }
+}
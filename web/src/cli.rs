@@ -17,6 +17,61 @@ pub fn build_cli() -> App<'static, 'static> {
             .short("tp")
             .env("CAROLUS_TV_PATH")
             .help("Sets the tv directory"))
+        .arg(Arg::with_name("naming_patterns")
+            .long("naming-patterns")
+            .env("CAROLUS_NAMING_PATTERNS")
+            .help("Sets a JSON file of naming patterns tried before the built-in ones"))
+        .arg(Arg::with_name("no_follow_symlinks")
+            .long("no-follow-symlinks")
+            .help("Leaves symlinked files and folders out of the library"))
+        .arg(Arg::with_name("max_depth")
+            .long("max-depth")
+            .env("CAROLUS_MAX_DEPTH")
+            .help("Sets how many folders deep to look inside a movie or show folder"))
+        .arg(Arg::with_name("index_jobs")
+            .long("index-jobs")
+            .env("CAROLUS_INDEX_JOBS")
+            .default_value("4")
+            .help("Sets how many folders are scanned at the same time, keep it low for spinning disks"))
+        .arg(Arg::with_name("index_schedule")
+            .long("index-schedule")
+            .env("CAROLUS_INDEX_SCHEDULE")
+            .help("Sets how often, in seconds, the library is rescanned"))
+        .arg(Arg::with_name("cache_path")
+            .long("cache-path")
+            .env("CAROLUS_CACHE_PATH")
+            .default_value("./cache")
+            .help("Sets the directory used for generated files such as thumbnails"))
+        .arg(Arg::with_name("ffmpeg")
+            .long("ffmpeg")
+            .env("CAROLUS_FFMPEG")
+            .default_value("ffmpeg")
+            .help("Sets the ffmpeg executable used to generate thumbnails"))
+        .arg(Arg::with_name("ffprobe")
+            .long("ffprobe")
+            .env("CAROLUS_FFPROBE")
+            .default_value("ffprobe")
+            .help("Sets the ffprobe executable used to read video durations"))
+        .arg(Arg::with_name("thumbnail_interval")
+            .long("thumbnail-interval")
+            .default_value("10")
+            .validator(|value| match value.parse::<u32>() {
+                Ok(0) | Err(_) => Err(format!("the interval needs to be a whole number of seconds above 0, not '{}'", value)),
+                Ok(_) => Ok(()),
+            })
+            .help("Sets the number of seconds between frames in the preview strip"))
+        .arg(Arg::with_name("thumbnail_jobs")
+            .long("thumbnail-jobs")
+            .default_value("1")
+            .help("Sets how many thumbnail jobs can run at the same time"))
+        .arg(Arg::with_name("thumbnail_schedule")
+            .long("thumbnail-schedule")
+            .env("CAROLUS_THUMBNAIL_SCHEDULE")
+            .help("Sets how often, in seconds, the library is checked for missing thumbnails"))
+        .arg(Arg::with_name("tmdb_api_key")
+            .long("tmdb-api-key")
+            .env("CAROLUS_TMDB_API_KEY")
+            .help("Sets the API key used to fetch metadata and artwork from The Movie Database"))
         .arg(Arg::with_name("demo")
             .long("demo")
             .help("Uses demo data instead of real data"))
@@ -0,0 +1,477 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix_web::*;
+use actix_web::actix::*;
+use failure::Fail;
+use futures::future::{self, Future};
+
+use crate::controllers::*;
+use data::{AllPlaylistsMessage, AllProgressMessage, DownloadInfo, DownloadItem, ExportMessage, ImportProgressMessage, LibraryExport, LibraryQuery, MediaVersion, Movie, MovieMessage, MovieQueryMessage, Playlist, PlaylistMessage, ProgressMessage, SavePlaylistMessage, ScanReportMessage, SearchMessage, TvEpisodeMessage, TvEpisode, TvSeries, TvSeriesMessage, TvShow, TvShowMessage, TvShowQueryMessage, UpdateProgressMessage, WatchProgress, error::Error};
+use crate::checksum::{ChecksumExecutor, ChecksumMessage};
+use crate::export;
+use crate::jobs::{AllJobsMessage, JobKind, TriggerJobMessage};
+use crate::ServerState;
+
+#[derive(Fail, Debug)]
+#[fail(display = "JSON Error")]
+pub struct JsonError(Error);
+
+impl From<Error> for JsonError {
+    /// Transforms a JsonError into an actix_web HTTP Response.
+    fn from(f: Error) -> Self {
+        JsonError(f)
+    }
+}
+
+impl error::ResponseError for JsonError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(error_status(&self.0))
+        .json(ErrorPayload::from_error(&self.0))
+    }
+}
+
+impl From<MailboxError> for JsonError {
+    fn from(e: MailboxError) -> Self {
+        JsonError(Error::Actix {
+            cause: e.to_string(),
+        })
+    }
+}
+
+type AsyncJsonResponse = Box<dyn Future<Item = HttpResponse, Error = JsonError>>;
+
+pub fn all_movies((state, query, req): (State<ServerState>, Query<LibraryQuery>, HttpRequest<ServerState>)) -> AsyncJsonResponse {
+    let query = query.into_inner();
+    state
+        .data
+        .send(MovieQueryMessage {
+            query: query.clone(),
+            runtime: runtime_lookup(state.cache_path.to_owned()),
+            metadata: metadata_lookup(state.cache_path.to_owned(), TitleKind::Movie),
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(page) => Ok(HttpResponse::Ok().json(AllMoviesPayload::new(page, &query, &req))),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn all_progress((state,): (State<ServerState>,)) -> AsyncJsonResponse {
+    state
+        .data
+        .send(AllProgressMessage)
+        .from_err()
+        .and_then(|res| match res {
+            Ok(progress) => Ok(HttpResponse::Ok().json(AllProgressPayload { progress })),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+/// The largest export that can be imported, which is far more than a big library needs.
+pub const IMPORT_LIMIT: usize = 256 * 1024 * 1024;
+
+pub fn export((state, params): (State<ServerState>, Query<ExportParams>)) -> AsyncJsonResponse {
+    let format = params.format.clone().unwrap_or_else(|| "json".to_owned());
+    if format != "json" && format != "csv" {
+        return Box::new(future::err(JsonError(Error::ExportFormatNotFound { name: format })));
+    }
+
+    state
+        .data
+        .send(ExportMessage)
+        .from_err()
+        .and_then(move |res| -> Result<_, JsonError> {
+            let library = res?;
+            let attachment = format!("attachment; filename=\"carolus.{}\"", format);
+            Ok(match &*format {
+                "csv" => HttpResponse::Ok()
+                    .content_type("text/csv; charset=utf-8")
+                    .header(http::header::CONTENT_DISPOSITION, attachment)
+                    .body(export::csv(&library)),
+                _ => HttpResponse::Ok()
+                    .header(http::header::CONTENT_DISPOSITION, attachment)
+                    .json(library),
+            })
+        })
+        .responder()
+}
+
+/// Restores the watch state of a JSON export, following files that have moved since.
+pub fn import((state, library): (State<ServerState>, Json<LibraryExport>)) -> AsyncJsonResponse {
+    state
+        .data
+        .send(ImportProgressMessage(library.into_inner()))
+        .from_err()
+        .and_then(|res| match res {
+            Ok(report) => Ok(HttpResponse::Ok().json(report)),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn movie(req: &HttpRequest<ServerState>) -> AsyncJsonResponse {
+    let info = Path::<(String,)>::extract(req).unwrap();
+    let version = version_param(req);
+
+    let req = req.to_owned();
+    req.state().data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: year_param(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(result) => {
+            let payload = MoviePayload::new(&result, version, &req.drop_state())?;
+            Ok(HttpResponse::Ok().json(payload))
+        },
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+pub fn all_tv_shows((state, query, req): (State<ServerState>, Query<LibraryQuery>, HttpRequest<ServerState>)) -> AsyncJsonResponse {
+    let query = query.into_inner();
+    state
+        .data
+        .send(TvShowQueryMessage {
+            query: query.clone(),
+            runtime: runtime_lookup(state.cache_path.to_owned()),
+            metadata: metadata_lookup(state.cache_path.to_owned(), TitleKind::TvShow),
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(page) => Ok(HttpResponse::Ok().json(AllTvShowsPayload::new(page, &query, &req))),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn tv_show(req: &HttpRequest<ServerState>) -> AsyncJsonResponse {
+    let info = Path::<(String,)>::extract(req).unwrap();
+
+    let req = req.to_owned();
+    req.state().data.send(TvShowMessage {
+        title: info.0.to_owned(),
+        year: year_param(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(result) => Ok(HttpResponse::Ok().json(TvShowPayload::new(&result, &req.drop_state()))),
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+pub fn tv_series(req: &HttpRequest<ServerState>) -> AsyncJsonResponse {
+    let info = Path::<(String,u16)>::extract(req).unwrap();
+
+    let req = req.to_owned();
+    req.state().data.send(TvSeriesMessage {
+        title: info.0.to_owned(),
+        year: year_param(&req),
+        series: info.1,
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(result) => Ok(HttpResponse::Ok().json(TvSeriesPayload::new(&result.0, &result.1, &req.drop_state()))),
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+pub fn tv_episode(req: &HttpRequest<ServerState>) -> AsyncJsonResponse {
+    let info = Path::<(String,u16,u16)>::extract(req).unwrap();
+    let version = version_param(req);
+
+    let req = req.to_owned();
+    req.state().data.send(TvEpisodeMessage {
+        title: info.0.to_owned(),
+        year: year_param(&req),
+        series: info.1,
+        episode: info.2,
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(result) => {
+            let payload = TvEpisodePayload::new(&result.0, &result.1, &result.2, version, &req.drop_state())?;
+            Ok(HttpResponse::Ok().json(payload))
+        },
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+pub fn play_movie((state, info, params): (State<ServerState>, Path<(String,)>, Query<PlayParams>)) -> AsyncJsonResponse {
+    let data = state.data.clone();
+    let version_index = params.version.unwrap_or(0);
+
+    state.data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: params.year,
+    })
+    .from_err()
+    .and_then(move |res| -> Result<_, JsonError> {
+        let movie = res?;
+        let file_path = movie_version(&movie, version_index)?.file_path.to_owned();
+        Ok((movie, file_path))
+    })
+    .and_then(move |(movie, file_path)| data.send(ProgressMessage { file_path }).from_err().and_then(move |res| -> Result<_, JsonError> {
+        let version = movie_version(&movie, version_index)?;
+        Ok(HttpResponse::Ok().json(movie_play_info(&movie, version, version_index, res?)))
+    }))
+    .responder()
+}
+
+pub fn movie_progress((state, info, params, progress): (State<ServerState>, Path<(String,)>, Query<PlayParams>, Json<WatchProgress>)) -> AsyncJsonResponse {
+    let data = state.data.clone();
+    let version_index = params.version.unwrap_or(0);
+    let progress = progress.into_inner();
+
+    state.data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: params.year,
+    })
+    .from_err()
+    .and_then(move |res| -> Result<_, JsonError> {
+        let movie = res?;
+        let version = movie_version(&movie, version_index)?;
+        Ok(UpdateProgressMessage { file_path: version.file_path.to_owned(), progress })
+    })
+    .and_then(move |msg| data.send(msg).from_err())
+    .and_then(|res| match res {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+fn movie_version(movie: &Movie, version: usize) -> Result<&MediaVersion, JsonError> {
+    movie.version(Some(version)).ok_or_else(|| JsonError(Error::VersionNotFound {
+        title: movie.title.to_owned(),
+        version,
+    }))
+}
+
+pub fn play_tv_episode((state, info, params): (State<ServerState>, Path<(String,u16,u16)>, Query<PlayParams>)) -> AsyncJsonResponse {
+    let data = state.data.clone();
+    let version_index = params.version.unwrap_or(0);
+
+    state.data.send(TvEpisodeMessage {
+        title: info.0.to_owned(),
+        year: params.year,
+        series: info.1,
+        episode: info.2,
+    })
+    .from_err()
+    .and_then(move |res| -> Result<_, JsonError> {
+        let episode = res?;
+        let file_path = episode_version(&episode, version_index)?.file_path.to_owned();
+        Ok((episode, file_path))
+    })
+    .and_then(move |(episode, file_path)| data.send(ProgressMessage { file_path }).from_err().and_then(move |res| -> Result<_, JsonError> {
+        let (show, series, tv_episode) = &episode;
+        let version = episode_version(&episode, version_index)?;
+        Ok(HttpResponse::Ok().json(tv_episode_play_info(show, series, tv_episode, version, version_index, res?)))
+    }))
+    .responder()
+}
+
+pub fn tv_episode_progress((state, info, params, progress): (State<ServerState>, Path<(String,u16,u16)>, Query<PlayParams>, Json<WatchProgress>)) -> AsyncJsonResponse {
+    let data = state.data.clone();
+    let version_index = params.version.unwrap_or(0);
+    let progress = progress.into_inner();
+
+    state.data.send(TvEpisodeMessage {
+        title: info.0.to_owned(),
+        year: params.year,
+        series: info.1,
+        episode: info.2,
+    })
+    .from_err()
+    .and_then(move |res| -> Result<_, JsonError> {
+        let episode = res?;
+        let version = episode_version(&episode, version_index)?;
+        Ok(UpdateProgressMessage { file_path: version.file_path.to_owned(), progress })
+    })
+    .and_then(move |msg| data.send(msg).from_err())
+    .and_then(|res| match res {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+fn episode_version(episode: &(Arc<TvShow>, Arc<TvSeries>, Arc<TvEpisode>), version: usize) -> Result<&MediaVersion, JsonError> {
+    let (show, series, tv_episode) = episode;
+    tv_episode.version(Some(version)).ok_or_else(|| JsonError(Error::VersionNotFound {
+        title: format!("{} S{:02}E{:02}", show.title, series.series_number, tv_episode.episode_number),
+        version,
+    }))
+}
+
+pub fn download_movie((state, info, params): (State<ServerState>, Path<(String,)>, Query<DownloadParams>)) -> AsyncJsonResponse {
+    let checksums = state.checksums.clone();
+    let version_index = params.version.unwrap_or(0);
+
+    state.data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: params.year,
+    })
+    .from_err()
+    .and_then(move |res| -> Result<_, JsonError> {
+        let movie = res?;
+        Ok(vec![movie_download_item(&movie, movie_version(&movie, version_index)?, version_index)?])
+    })
+    .and_then(move |items| with_checksums(&checksums, items))
+    .responder()
+}
+
+pub fn download_tv_show((state, info, params): (State<ServerState>, Path<(String,)>, Query<DownloadParams>)) -> AsyncJsonResponse {
+    let checksums = state.checksums.clone();
+    let series = params.series;
+
+    state.data.send(TvShowMessage {
+        title: info.0.to_owned(),
+        year: params.year,
+    })
+    .from_err()
+    .and_then(move |res| -> Result<_, JsonError> {
+        let tv_show = res?;
+        let wanted = tv_show.series.iter().filter(|s| series.map_or(true, |n| n == s.series_number)).collect::<Vec<_>>();
+        if wanted.is_empty() {
+            return Err(JsonError(Error::TvShowNotFound { title: format!("{}: Series {}", tv_show.title, series.unwrap_or(0)) }));
+        }
+        let items = wanted.into_iter()
+            .flat_map(|tv_series| tv_series.episodes.iter().map(move |e| (tv_series, e)))
+            .filter_map(|(tv_series, tv_episode)| Some(tv_episode_download_item(&tv_show, tv_series, tv_episode, tv_episode.version(None)?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items)
+    })
+    .and_then(move |items| with_checksums(&checksums, items))
+    .responder()
+}
+
+/// Fills in the checksums of the files to download, which are worked out on their own thread.
+fn with_checksums(checksums: &Addr<ChecksumExecutor>, items: Vec<(DownloadItem, Vec<PathBuf>)>) -> impl Future<Item = HttpResponse, Error = JsonError> {
+    let (mut items, sources): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+    checksums.send(ChecksumMessage(sources.into_iter().flatten().collect()))
+        .from_err()
+        .and_then(move |res| -> Result<_, JsonError> {
+            let files = items.iter_mut().flat_map(|i| i.parts.iter_mut().chain(i.subtitles.iter_mut()));
+            for (file, crc32) in files.zip(res?) {
+                file.crc32 = crc32;
+            }
+            Ok(HttpResponse::Ok().json(DownloadInfo { items }))
+        })
+}
+
+pub fn search((state, params): (State<ServerState>, Query<SearchParams>)) -> AsyncJsonResponse {
+    state
+        .data
+        .send(SearchMessage { query: params.q.to_owned() })
+        .from_err()
+        .and_then(|res| match res {
+            Ok((movies, tv_shows)) => Ok(HttpResponse::Ok().json(SearchPayload { movies, tv_shows })),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn jobs((state,): (State<ServerState>,)) -> AsyncJsonResponse {
+    state
+        .jobs
+        .send(AllJobsMessage)
+        .from_err()
+        .and_then(|res| match res {
+            Ok(jobs) => Ok(HttpResponse::Ok().json(JobsPayload::new(jobs))),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn trigger_job(req: &HttpRequest<ServerState>) -> AsyncJsonResponse {
+    let info = Path::<(String,)>::extract(req).unwrap();
+    let kind = match info.0.parse::<JobKind>() {
+        Ok(kind) => kind,
+        Err(e) => return Box::new(future::err(JsonError(e))),
+    };
+
+    req.state().jobs.send(TriggerJobMessage { kind, target: None })
+    .from_err()
+    .and_then(|res| match res {
+        Ok(()) => Ok(HttpResponse::Accepted().finish()),
+        Err(e) => Err(JsonError(e)),
+    })
+    .responder()
+}
+
+pub fn scan_report((state,): (State<ServerState>,)) -> AsyncJsonResponse {
+    state
+        .data
+        .send(ScanReportMessage)
+        .from_err()
+        .and_then(|res| match res {
+            Ok(report) => Ok(HttpResponse::Ok().json(report)),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn rescan((state, params): (State<ServerState>, Query<RescanParams>)) -> AsyncJsonResponse {
+    state
+        .jobs
+        .send(TriggerJobMessage { kind: JobKind::Index, target: params.folder() })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(()) => Ok(HttpResponse::Accepted().finish()),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn all_playlists((state,): (State<ServerState>,)) -> AsyncJsonResponse {
+    state
+        .data
+        .send(AllPlaylistsMessage)
+        .from_err()
+        .and_then(|res| match res {
+            Ok(playlists) => Ok(HttpResponse::Ok().json(AllPlaylistsPayload { playlists })),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn playlist((state, info): (State<ServerState>, Path<(String,)>)) -> AsyncJsonResponse {
+    state
+        .data
+        .send(PlaylistMessage { name: info.0.to_owned() })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(playlist) => Ok(HttpResponse::Ok().json(playlist)),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
+
+pub fn save_playlist((state, info, playlist): (State<ServerState>, Path<(String,)>, Json<Playlist>)) -> AsyncJsonResponse {
+    save_or_delete_playlist(&state, info.0.to_owned(), Some(playlist.into_inner()))
+}
+
+pub fn delete_playlist((state, info): (State<ServerState>, Path<(String,)>)) -> AsyncJsonResponse {
+    save_or_delete_playlist(&state, info.0.to_owned(), None)
+}
+
+fn save_or_delete_playlist(state: &ServerState, name: String, playlist: Option<Playlist>) -> AsyncJsonResponse {
+    state
+        .data
+        .send(SavePlaylistMessage { name, playlist })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(()) => Ok(HttpResponse::NoContent().finish()),
+            Err(e) => Err(JsonError(e)),
+        })
+        .responder()
+}
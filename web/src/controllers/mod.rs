@@ -1,9 +1,19 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use actix_web::HttpRequest;
-use serde_derive::Serialize;
+use actix_web::{http::StatusCode, FromRequest, HttpRequest, Query};
+use serde_derive::{Deserialize, Serialize};
+use url::form_urlencoded;
+use url::percent_encoding::{PATH_SEGMENT_ENCODE_SET, utf8_percent_encode};
 
-use data::{Movie, TvEpisode, TvSeries, TvShow, {error::Error}};
+use data::{DownloadFile, DownloadItem, LibraryPage, LibraryQuery, MediaVersion, MetadataLookup, Movie, PlayInfo, Playlist, RuntimeLookup, ScanReport, SortKey, SubtitleInfo, TitleMetadata, TvEpisode, TvSeries, TvShow, WatchProgress, {error::Error}};
 
+use crate::jobs::{Job, JobKind, JobState};
+use crate::metadata::{self, TitleKind};
+use crate::thumbnail;
+
+pub mod api;
+pub mod playlist;
 pub mod view;
 
 /// Error payload for a view (HTML or JSON)
@@ -21,32 +31,204 @@ impl ErrorPayload {
     }
 }
 
+/// Picks the HTTP status code for an error, shared by the HTML and JSON views.
+fn error_status(e: &Error) -> StatusCode {
+    match e {
+        Error::Actix { .. } | Error::Template => StatusCode::INTERNAL_SERVER_ERROR,
+        Error::MovieNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::TvShowNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::VersionNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::ExtraNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::FileNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::SubtitleNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::ThumbnailNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::JobKindNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::ExportFormatNotFound { .. } => StatusCode::BAD_REQUEST,
+        Error::CursorNotValid { .. } => StatusCode::BAD_REQUEST,
+        Error::PlaylistNotFound { .. } => StatusCode::NOT_FOUND,
+        Error::PlaylistFormatNotFound { .. } => StatusCode::BAD_REQUEST,
+        Error::PlaylistNotSaved { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        Error::ProgressNotSaved { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        Error::FileNotReadable { .. } => StatusCode::NOT_FOUND,
+        Error::ScanReportNotFound => StatusCode::NOT_FOUND,
+    }
+}
+
+/// A page of the movies a query asks for (HTML or JSON).
 #[derive(Clone, Serialize, Debug)]
 pub struct AllMoviesPayload {
-    movies: Arc<Vec<Arc<Movie>>>,
+    movies: Vec<Arc<Movie>>,
+    #[serde(flatten)]
+    list: ListPayload,
+}
+
+impl AllMoviesPayload {
+    pub fn new<S>(page: LibraryPage<Arc<Movie>>, query: &LibraryQuery, req: &HttpRequest<S>) -> Self {
+        AllMoviesPayload {
+            list: ListPayload::new(page.total, page.next, query, req),
+            movies: page.items,
+        }
+    }
+}
+
+/// How far every movie and episode was watched, by the path of its file.
+#[derive(Clone, Serialize, Debug)]
+pub struct AllProgressPayload {
+    progress: HashMap<String, WatchProgress>,
+}
+
+/// Every playlist that was saved, by name.
+#[derive(Clone, Serialize, Debug)]
+pub struct AllPlaylistsPayload {
+    playlists: BTreeMap<String, Playlist>,
 }
 
 /// Represents a movie payload (HTML or JSON).
 #[derive(Clone, Serialize, Debug)]
 pub struct MoviePayload<'a> {
     movie: &'a Movie,
+    version: &'a MediaVersion,
+    version_index: usize,
+    version_options: Vec<VersionOption<'a>>,
+    has_versions: bool,
+    part_count: usize,
 }
 
 impl<'a> MoviePayload<'a> {
-    /// Creates a new payload for the movie page.
+    /// Creates a new payload for the movie page, showing the requested version.
     pub fn new(
         movie: &'a Movie,
+        version: Option<usize>,
         _req: &HttpRequest,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, Error> {
+        let version_index = version.unwrap_or(0);
+        let version = movie.version(Some(version_index)).ok_or_else(|| Error::VersionNotFound {
+            title: movie.title.to_owned(),
+            version: version_index,
+        })?;
+        Ok(Self {
             movie,
-        }
+            version,
+            version_index,
+            version_options: version_options(&movie.versions, version_index),
+            has_versions: movie.versions.len() > 1,
+            part_count: version.part_count(),
+        })
     }
 }
 
+/// A page of the tv shows a query asks for (HTML or JSON).
 #[derive(Clone, Serialize, Debug)]
 pub struct AllTvShowsPayload {
-    tv_shows: Arc<Vec<Arc<TvShow>>>,
+    tv_shows: Vec<Arc<TvShow>>,
+    #[serde(flatten)]
+    list: ListPayload,
+}
+
+impl AllTvShowsPayload {
+    pub fn new<S>(page: LibraryPage<Arc<TvShow>>, query: &LibraryQuery, req: &HttpRequest<S>) -> Self {
+        AllTvShowsPayload {
+            list: ListPayload::new(page.total, page.next, query, req),
+            tv_shows: page.items,
+        }
+    }
+}
+
+/// What a page of a list has besides what is on it: how many there are in all, and links to
+/// the next page and to the list sorted another way, which keep the filters it was asked with.
+#[derive(Clone, Serialize, Debug)]
+struct ListPayload {
+    total: usize,
+    next: Option<String>,
+    next_url: Option<String>,
+    sort_options: Vec<SortOption>,
+}
+
+/// An entry in the sort picker of a list.
+#[derive(Clone, Serialize, Debug)]
+struct SortOption {
+    name: &'static str,
+    url: String,
+    selected: bool,
+}
+
+/// The ways a list can be sorted: by name, key, and the `sort` and `descending` parameters.
+const SORT_OPTIONS: &[(&str, SortKey, &str, &str)] = &[
+    ("Title", SortKey::Title, "title", "false"),
+    ("Year", SortKey::Year, "year", "false"),
+    ("Recently added", SortKey::Added, "added", "true"),
+    ("Running time", SortKey::Runtime, "runtime", "false"),
+    ("Rating", SortKey::Rating, "rating", "true"),
+];
+
+impl ListPayload {
+    fn new<S>(total: usize, next: Option<String>, query: &LibraryQuery, req: &HttpRequest<S>) -> Self {
+        ListPayload {
+            total,
+            next_url: next.as_ref().map(|next| list_url(req, &["after"], &[("after", next)])),
+            next,
+            sort_options: SORT_OPTIONS.iter()
+                .map(|&(name, sort, sort_param, descending_param)| SortOption {
+                    name,
+                    url: list_url(req, &["after", "sort", "descending"], &[("sort", sort_param), ("descending", descending_param)]),
+                    selected: query.sort == sort,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Looks up how long videos are from their thumbnails, for the lists to be sorted and filtered by.
+fn runtime_lookup(cache_path: PathBuf) -> RuntimeLookup {
+    Arc::new(move |file_path: &str| thumbnail::duration(&cache_path, file_path))
+}
+
+/// Looks up the genres and rating fetched from The Movie Database, for the lists to be filtered
+/// and sorted by.
+fn metadata_lookup(cache_path: PathBuf, kind: TitleKind) -> MetadataLookup {
+    Arc::new(move |title: &str, year: Option<u16>| {
+        metadata::read_metadata(&cache_path, kind, title, year)
+            .map(|m| TitleMetadata { genres: m.genres, rating: m.rating })
+    })
+}
+
+/// The URL of the list that was asked for with some of its query parameters replaced.
+fn list_url<S>(req: &HttpRequest<S>, replaced: &[&str], added: &[(&str, &str)]) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    for (name, value) in form_urlencoded::parse(req.query_string().as_bytes()) {
+        if !replaced.contains(&&*name) {
+            query.append_pair(&name, &value);
+        }
+    }
+    query.extend_pairs(added);
+    format!("{}?{}", req.path(), query.finish())
+}
+
+/// An entry in the version picker.
+#[derive(Clone, Serialize, Debug)]
+struct VersionOption<'a> {
+    index: usize,
+    name: &'a str,
+    selected: bool,
+}
+
+fn version_options(versions: &[MediaVersion], selected: usize) -> Vec<VersionOption> {
+    versions.iter().enumerate()
+        .map(|(index, v)| VersionOption { index, name: &v.name, selected: index == selected })
+        .collect()
+}
+
+/// Represents the search results payload (JSON).
+#[derive(Clone, Serialize, Debug)]
+pub struct SearchPayload {
+    movies: Vec<Arc<Movie>>,
+    tv_shows: Vec<Arc<TvShow>>,
+}
+
+/// Parameters for a search of the library by title.
+#[derive(Clone, Deserialize, Debug)]
+pub struct SearchParams {
+    pub q: String,
 }
 
 /// Represents a tv show payload (HTML or JSON).
@@ -88,30 +270,252 @@ impl<'a> TvSeriesPayload<'a> {
     }
 }
 
-/// Represents a tv series payload (HTML or JSON).
+/// Represents a tv episode payload (HTML or JSON).
 #[derive(Clone, Serialize, Debug)]
 pub struct TvEpisodePayload<'a> {
     tv_show: &'a TvShow,
     tv_series: &'a TvSeries,
     tv_episode: &'a TvEpisode,
+    version: &'a MediaVersion,
+    version_index: usize,
+    version_options: Vec<VersionOption<'a>>,
+    has_versions: bool,
 }
 
 impl<'a> TvEpisodePayload<'a> {
-    /// Creates a new payload for the tv series page.
+    /// Creates a new payload for the tv episode page, showing the requested version.
     pub fn new(
         tv_show: &'a TvShow,
         tv_series: &'a TvSeries,
         tv_episode: &'a TvEpisode,
+        version: Option<usize>,
         _req: &HttpRequest,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, Error> {
+        let version_index = version.unwrap_or(0);
+        let version = tv_episode.version(Some(version_index)).ok_or_else(|| Error::VersionNotFound {
+            title: format!("{} S{:02}E{:02}", tv_show.title, tv_series.series_number, tv_episode.episode_number),
+            version: version_index,
+        })?;
+        Ok(Self {
             tv_show,
             tv_series,
             tv_episode,
+            version,
+            version_index,
+            version_options: version_options(&tv_episode.versions, version_index),
+            has_versions: tv_episode.versions.len() > 1,
+        })
+    }
+}
+
+/// Represents the background jobs payload (HTML or JSON).
+#[derive(Clone, Serialize, Debug)]
+pub struct JobsPayload {
+    kinds: Vec<JobKind>,
+    running: Vec<Job>,
+    queued: Vec<Job>,
+    failed: Vec<Job>,
+}
+
+impl JobsPayload {
+    /// Creates a new payload for the jobs page, grouping the jobs by state.
+    pub fn new(jobs: Vec<Job>) -> Self {
+        let (running, rest): (Vec<_>, Vec<_>) = jobs.into_iter().partition(|j| j.state == JobState::Running);
+        let (queued, failed) = rest.into_iter().partition(|j| j.state == JobState::Queued);
+        Self {
+            kinds: JobKind::all(),
+            running,
+            queued,
+            failed,
         }
     }
 }
 
+/// Represents the scan report page payload.
+#[derive(Clone, Serialize, Debug)]
+pub struct ScanReportPayload {
+    report: Arc<ScanReport>,
+    added_count: usize,
+    removed_count: usize,
+    moved_count: usize,
+    failure_count: usize,
+    conflict_count: usize,
+}
+
+impl ScanReportPayload {
+    /// Creates a new payload for the scan report page.
+    pub fn new(report: Arc<ScanReport>) -> Self {
+        Self {
+            added_count: report.added.len(),
+            removed_count: report.removed.len(),
+            moved_count: report.moved.len(),
+            failure_count: report.failures.len(),
+            conflict_count: report.conflicts.len(),
+            report,
+        }
+    }
+}
+
+/// Parameters for a rescan, an empty or missing folder rescans the whole library.
+#[derive(Clone, Deserialize, Debug)]
+pub struct RescanParams {
+    folder: Option<String>,
+}
+
+impl RescanParams {
+    pub fn folder(&self) -> Option<String> {
+        self.folder.clone().filter(|f| !f.is_empty())
+    }
+}
+
+/// Picks the format of an export, `json` when missing.
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExportParams {
+    pub format: Option<String>,
+}
+
+/// Picks one of the versions of a movie or episode, the best one when missing,
+/// and which of its parts to play, the first when missing.
+#[derive(Clone, Deserialize, Debug)]
+pub struct VersionParams {
+    pub version: Option<usize>,
+    pub part: Option<usize>,
+}
+
+/// Picks a movie or episode to play, telling movies of the same name apart by year.
+#[derive(Clone, Deserialize, Debug)]
+pub struct PlayParams {
+    pub year: Option<u16>,
+    pub version: Option<usize>,
+}
+
+/// Encodes a title for use as one segment of a URL path.
+fn path_segment(s: &str) -> String {
+    utf8_percent_encode(s, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+/// Lists the subtitles next to the first file of a version, served from under `base` with `query`.
+fn subtitle_infos(version: &MediaVersion, base: &str, query: &str) -> Vec<SubtitleInfo> {
+    index::subtitle_files(std::path::Path::new(&version.file_path)).into_iter().enumerate()
+        .map(|(i, subtitle)| SubtitleInfo {
+            language: subtitle.language,
+            url: format!("{}/{}?{}", base, i, query),
+        })
+        .collect()
+}
+
+/// What a player needs to play a version of a movie, every part of it in turn.
+fn movie_play_info(movie: &Movie, version: &MediaVersion, version_index: usize, progress: Option<WatchProgress>) -> PlayInfo {
+    let title = path_segment(&movie.title);
+    let year = movie.year.map_or(String::new(), |y| format!("&year={}", y));
+    PlayInfo {
+        title: movie.year.map_or(movie.title.to_owned(), |y| format!("{} ({})", movie.title, y)),
+        streams: (0..version.part_count())
+            .map(|part| format!("/play/movie/{}?version={}&part={}{}", title, version_index, part, year))
+            .collect(),
+        subtitles: subtitle_infos(version, &format!("/subtitle/movie/{}", title), &format!("version={}{}", version_index, year)),
+        progress_url: format!("/api/movies/progress/{}?version={}{}", title, version_index, year),
+        progress,
+    }
+}
+
+/// What a player needs to play a version of a tv episode, every part of it in turn.
+fn tv_episode_play_info(tv_show: &TvShow, tv_series: &TvSeries, tv_episode: &TvEpisode, version: &MediaVersion, version_index: usize, progress: Option<WatchProgress>) -> PlayInfo {
+    let episode = format!("{}/{}/{}", path_segment(&tv_show.title), tv_series.series_number, tv_episode.episode_number);
+    let year = tv_show.year.map_or(String::new(), |y| format!("&year={}", y));
+    PlayInfo {
+        title: format!("{} S{:02}E{:02}", tv_show.title, tv_series.series_number, tv_episode.episode_number),
+        streams: (0..version.part_count())
+            .map(|part| format!("/play/tv/{}?version={}&part={}{}", episode, version_index, part, year))
+            .collect(),
+        subtitles: subtitle_infos(version, &format!("/subtitle/tv/{}", episode), &format!("version={}{}", version_index, year)),
+        progress_url: format!("/api/tv/progress/{}?version={}{}", episode, version_index, year),
+        progress,
+    }
+}
+
+/// Picks what to download: a movie's version, or a series of a tv show rather than all of it.
+#[derive(Clone, Deserialize, Debug)]
+pub struct DownloadParams {
+    pub year: Option<u16>,
+    pub version: Option<usize>,
+    pub series: Option<u16>,
+}
+
+/// Makes a title safe to use as a folder name on any file system.
+fn folder_name(title: &str) -> String {
+    title.chars().map(|c| if "<>:\"/\\|?*".contains(c) || c.is_control() { '_' } else { c }).collect()
+}
+
+/// Describes the files of a version and its subtitles, to be kept in `folder`, along with the
+/// files they are served from. Their checksums are filled in later, as they take a while.
+fn download_files(version: &MediaVersion, folder: &str, part_url: &dyn Fn(usize) -> String, subtitle_url: &dyn Fn(usize) -> String) -> Result<(Vec<DownloadFile>, Vec<DownloadFile>, Vec<PathBuf>), Error> {
+    let mut names = HashSet::new();
+    let mut file = |source: &std::path::Path, index: usize, url: String| -> Result<DownloadFile, Error> {
+        let name = source.file_name().map_or("".into(), |n| n.to_string_lossy().into_owned());
+        // Parts kept in folders of their own, such as `CD1/Alien.mp4` and `CD2/Alien.mp4`, can share a name.
+        let name = if names.insert(name.clone()) { name } else { format!("{} - {}", index + 1, name) };
+        let size = std::fs::metadata(source)
+            .map_err(|e| Error::FileNotReadable { path: source.display().to_string(), cause: e.to_string() })?
+            .len();
+        Ok(DownloadFile { url, path: format!("{}/{}", folder, name), size, crc32: None })
+    };
+    let parts = (0..version.part_count()).filter_map(|i| version.part(i)).map(PathBuf::from).collect::<Vec<_>>();
+    let subtitles = index::subtitle_files(std::path::Path::new(&version.file_path)).into_iter().map(|s| s.file_path).collect::<Vec<_>>();
+    let part_files = parts.iter().enumerate().map(|(i, p)| file(p, i, part_url(i))).collect::<Result<Vec<_>, _>>()?;
+    let subtitle_files = subtitles.iter().enumerate().map(|(i, s)| file(s, i, subtitle_url(i))).collect::<Result<Vec<_>, _>>()?;
+    Ok((part_files, subtitle_files, parts.into_iter().chain(subtitles).collect()))
+}
+
+/// What to download for a movie, and the files it comes from.
+fn movie_download_item(movie: &Movie, version: &MediaVersion, version_index: usize) -> Result<(DownloadItem, Vec<PathBuf>), Error> {
+    let info = movie_play_info(movie, version, version_index, None);
+    let folder = format!("Movies/{}", folder_name(&info.title));
+    let (parts, subtitles, sources) = download_files(version, &folder, &|i| info.streams[i].to_owned(), &|i| info.subtitles[i].url.to_owned())?;
+    let item = DownloadItem {
+        title: movie.title.to_owned(),
+        year: movie.year,
+        series: None,
+        episode: None,
+        parts,
+        subtitles,
+        progress_url: info.progress_url,
+    };
+    Ok((item, sources))
+}
+
+/// What to download for the best version of a tv episode, and the files it comes from.
+fn tv_episode_download_item(tv_show: &TvShow, tv_series: &TvSeries, tv_episode: &TvEpisode, version: &MediaVersion) -> Result<(DownloadItem, Vec<PathBuf>), Error> {
+    let info = tv_episode_play_info(tv_show, tv_series, tv_episode, version, 0, None);
+    let folder = format!("TV/{}/Season {:02}", folder_name(&tv_show.title), tv_series.series_number);
+    let (parts, subtitles, sources) = download_files(version, &folder, &|i| info.streams[i].to_owned(), &|i| info.subtitles[i].url.to_owned())?;
+    let item = DownloadItem {
+        title: tv_show.title.to_owned(),
+        year: tv_show.year,
+        series: Some(tv_series.series_number),
+        episode: Some(tv_episode.episode_number),
+        parts,
+        subtitles,
+        progress_url: info.progress_url,
+    };
+    Ok((item, sources))
+}
+
+/// Reads the `version` query parameter, ignoring it when it is not a number.
+fn version_param<S>(req: &HttpRequest<S>) -> Option<usize> {
+    Query::<VersionParams>::extract(req).ok().and_then(|q| q.version)
+}
+
+/// Reads the `year` query parameter, ignoring it when it is not a number.
+fn year_param<S>(req: &HttpRequest<S>) -> Option<u16> {
+    Query::<PlayParams>::extract(req).ok().and_then(|q| q.year)
+}
+
+/// Reads the `part` query parameter, ignoring it when it is not a number.
+fn part_param<S>(req: &HttpRequest<S>) -> usize {
+    Query::<VersionParams>::extract(req).ok().and_then(|q| q.part).unwrap_or(0)
+}
+
 #[derive(Clone, Serialize, Debug)]
 pub struct Meta {
     description: String,
@@ -196,6 +600,22 @@ impl Meta {
         }
     }
 
+    fn for_jobs() -> Self {
+        Self {
+            description: "Background Jobs".to_string(),
+            title: format!(title_format!(), "Jobs"),
+            url: format!(url_format!(), "/admin/jobs"),
+        }
+    }
+
+    fn for_scan_report() -> Self {
+        Self {
+            description: "Library Scan Report".to_string(),
+            title: format!(title_format!(), "Scan Report"),
+            url: format!(url_format!(), "/admin/scan"),
+        }
+    }
+
     fn for_error() -> Self {
         Self {
             description: "Error page".to_string(),
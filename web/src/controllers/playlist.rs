@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix_web::*;
+use actix_web::actix::{Addr, MailboxError};
+use futures::future::{self, Future};
+use log::warn;
+use url::form_urlencoded;
+
+use data::{AllMoviesMessage, AllProgressMessage, DataExecutor, MediaVersion, Movie, MovieMessage, PlayInfo, PlaylistItem, PlaylistMessage, TvShow, TvShowMessage, WatchProgress, error::Error};
+
+use crate::controllers::*;
+use crate::controllers::api::JsonError;
+use crate::playlist::{Entry, m3u8, xspf};
+use crate::thumbnail;
+use crate::ServerState;
+
+type AsyncPlaylistResponse = Box<dyn Future<Item = HttpResponse, Error = JsonError>>;
+
+/// The formats a playlist can be had in, by the extension asked for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    fn from_extension(extension: &str) -> Result<Self, Error> {
+        match extension {
+            "m3u8" | "m3u" => Ok(PlaylistFormat::M3u8),
+            "xspf" => Ok(PlaylistFormat::Xspf),
+            _ => Err(Error::PlaylistFormatNotFound { name: extension.to_owned() }),
+        }
+    }
+}
+
+/// Turns the paths of streams into URLs the player asking for the playlist can open by themselves,
+/// passing on the token it asked with for whatever checks it in front of the server.
+struct Links {
+    base: String,
+    token: Option<String>,
+    cache_path: PathBuf,
+}
+
+impl Links {
+    fn new(req: &HttpRequest<ServerState>) -> Self {
+        let base = {
+            let info = req.connection_info();
+            format!("{}://{}", info.scheme(), info.host())
+        };
+        let bearer = req.headers().get(http::header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .filter(|h| h.starts_with("Bearer "))
+            .map(|h| h[7..].trim().to_owned());
+        Links {
+            base,
+            token: req.query().get("token").cloned().or(bearer),
+            cache_path: req.state().cache_path.to_owned(),
+        }
+    }
+
+    /// A stream's URL from its path, which already has a query with the version and year in it.
+    fn url(&self, path: &str) -> String {
+        let mut url = format!("{}{}", self.base, path);
+        if let Some(ref token) = self.token {
+            url.push_str("&token=");
+            url.extend(form_urlencoded::byte_serialize(token.as_bytes()));
+        }
+        url
+    }
+
+    /// How long a part of a version is: from its thumbnails, which are only made of the first part,
+    /// or from what the player said when it was played.
+    fn duration(&self, version: &MediaVersion, part: usize, progress: Option<&WatchProgress>) -> Option<f64> {
+        let played = progress.filter(|p| p.part == part).and_then(|p| p.duration);
+        if part == 0 {
+            thumbnail::duration(&self.cache_path, &version.file_path).or(played)
+        } else {
+            played
+        }
+    }
+}
+
+/// How far everything was watched, which every playlist gives the lengths it knows from.
+fn progress(data: &Addr<DataExecutor>) -> impl Future<Item = Arc<HashMap<String, WatchProgress>>, Error = JsonError> {
+    data.send(AllProgressMessage)
+        .from_err()
+        .and_then(|res| -> Result<_, JsonError> { Ok(Arc::new(res?)) })
+}
+
+/// Every part of the best version of a movie.
+fn movie_entries(movie: &Movie, progress: &HashMap<String, WatchProgress>, links: &Links) -> Vec<Entry> {
+    let version = match movie.version(None) {
+        Some(version) => version,
+        None => return vec![],
+    };
+    version_entries(&movie_play_info(movie, version, 0, None), version, progress, links)
+}
+
+/// Every part of a version, in the order they are played.
+fn version_entries(info: &PlayInfo, version: &MediaVersion, progress: &HashMap<String, WatchProgress>, links: &Links) -> Vec<Entry> {
+    let watched = progress.get(&version.file_path);
+    let parts = info.streams.len();
+    info.streams.iter().enumerate()
+        .map(|(part, stream)| Entry {
+            title: if parts > 1 { format!("{}, part {}", info.title, part + 1) } else { info.title.to_owned() },
+            url: links.url(stream),
+            duration: links.duration(version, part, watched),
+        })
+        .collect()
+}
+
+/// The best version of every episode of a tv show, or of one series or episode of it.
+fn tv_show_entries(tv_show: &TvShow, series: Option<u16>, episode: Option<u16>, progress: &HashMap<String, WatchProgress>, links: &Links) -> Result<Vec<Entry>, Error> {
+    let mut entries = vec![];
+    let mut found = false;
+    for tv_series in tv_show.series.iter().filter(|s| series.map_or(true, |n| n == s.series_number)) {
+        for tv_episode in tv_series.episodes.iter().filter(|e| episode.map_or(true, |n| n == e.episode_number)) {
+            found = true;
+            if let Some(version) = tv_episode.version(None) {
+                let info = tv_episode_play_info(tv_show, tv_series, tv_episode, version, 0, None);
+                entries.extend(version_entries(&info, version, progress, links));
+            }
+        }
+    }
+    if !found {
+        let title = match (series, episode) {
+            (Some(series), Some(episode)) => format!("{} S{:02}E{:02}", tv_show.title, series, episode),
+            (Some(series), None) => format!("{}: Series {}", tv_show.title, series),
+            _ => tv_show.title.to_owned(),
+        };
+        return Err(Error::TvShowNotFound { title });
+    }
+    Ok(entries)
+}
+
+type ItemEntries = Box<dyn Future<Item = Result<Vec<Entry>, Error>, Error = MailboxError>>;
+
+/// What is on the playlist for an item, looking its title up the way the rest of the server does:
+/// the one of that year, or else the first with the title. A title that is no longer in the
+/// library is the inner error, so that saved playlists can leave it out.
+fn item_entries(data: &Addr<DataExecutor>, item: PlaylistItem, progress: Arc<HashMap<String, WatchProgress>>, links: Arc<Links>) -> ItemEntries {
+    match item {
+        PlaylistItem::Movie { movie: title, year } => Box::new(
+            data.send(MovieMessage { title, year })
+                .map(move |movie| -> Result<_, Error> { Ok(movie_entries(&movie?, &progress, &links)) })
+        ),
+        PlaylistItem::TvShow { tv_show: title, year, series, episode } => Box::new(
+            data.send(TvShowMessage { title, year })
+                .map(move |tv_show| tv_show_entries(&tv_show?, series, episode, &progress, &links))
+        ),
+    }
+}
+
+fn playlist_response(format: PlaylistFormat, title: &str, entries: &[Entry]) -> HttpResponse {
+    match format {
+        PlaylistFormat::M3u8 => HttpResponse::Ok().content_type("audio/x-mpegurl; charset=utf-8").body(m3u8(title, entries)),
+        PlaylistFormat::Xspf => HttpResponse::Ok().content_type("application/xspf+xml; charset=utf-8").body(xspf(title, entries)),
+    }
+}
+
+fn format_param(extension: &str) -> Result<PlaylistFormat, JsonError> {
+    Ok(PlaylistFormat::from_extension(extension)?)
+}
+
+pub fn all_movies(req: &HttpRequest<ServerState>) -> AsyncPlaylistResponse {
+    let info = Path::<(String,)>::extract(req).unwrap();
+    let format = match format_param(&info.0) {
+        Ok(format) => format,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let links = Links::new(req);
+    let movies = req.state().data.send(AllMoviesMessage)
+        .from_err()
+        .and_then(|res| -> Result<_, JsonError> { Ok(res?) });
+
+    movies.join(progress(&req.state().data))
+        .and_then(move |(movies, progress)| {
+            let entries = movies.iter().flat_map(|m| movie_entries(m, &progress, &links)).collect::<Vec<_>>();
+            Ok(playlist_response(format, "Movies", &entries))
+        })
+        .responder()
+}
+
+pub fn tv_show(req: &HttpRequest<ServerState>) -> AsyncPlaylistResponse {
+    let info = Path::<(String,String)>::extract(req).unwrap();
+    let item = PlaylistItem::TvShow { tv_show: info.0.to_owned(), year: year_param(req), series: None, episode: None };
+    tv_playlist(req, item, info.0.to_owned(), &info.1)
+}
+
+pub fn tv_series(req: &HttpRequest<ServerState>) -> AsyncPlaylistResponse {
+    let info = Path::<(String,u16,String)>::extract(req).unwrap();
+    let item = PlaylistItem::TvShow { tv_show: info.0.to_owned(), year: year_param(req), series: Some(info.1), episode: None };
+    tv_playlist(req, item, format!("{}: Series {}", info.0, info.1), &info.2)
+}
+
+fn tv_playlist(req: &HttpRequest<ServerState>, item: PlaylistItem, title: String, extension: &str) -> AsyncPlaylistResponse {
+    let format = match format_param(extension) {
+        Ok(format) => format,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let links = Arc::new(Links::new(req));
+    let data = req.state().data.clone();
+
+    progress(&data)
+        .and_then(move |progress| item_entries(&data, item, progress, links).from_err())
+        .and_then(move |entries| -> Result<_, JsonError> {
+            Ok(playlist_response(format, &title, &entries?))
+        })
+        .responder()
+}
+
+/// A playlist someone made, leaving out what is no longer in the library.
+pub fn saved(req: &HttpRequest<ServerState>) -> AsyncPlaylistResponse {
+    let info = Path::<(String,String)>::extract(req).unwrap();
+    let format = match format_param(&info.1) {
+        Ok(format) => format,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let links = Arc::new(Links::new(req));
+    let name = info.0.to_owned();
+    let data = req.state().data.clone();
+
+    data.send(PlaylistMessage { name: name.to_owned() })
+        .from_err()
+        .and_then(|res| -> Result<_, JsonError> { Ok(res?) })
+        .join(progress(&data))
+        .and_then(move |(playlist, progress)| {
+            let items = playlist.items.clone();
+            let lookups = playlist.items.into_iter()
+                .map(|item| item_entries(&data, item, progress.clone(), links.clone()))
+                .collect::<Vec<_>>();
+            future::join_all(lookups).from_err().map(move |found| (items, found))
+        })
+        .and_then(move |(items, found)| -> Result<_, JsonError> {
+            let entries = items.iter().zip(found)
+                .filter_map(|(item, entries)| match entries {
+                    Ok(entries) => Some(entries),
+                    Err(e) => {
+                        warn!("Leaving {:?} out of the playlist {:?}: {}", item, name, e);
+                        None
+                    },
+                })
+                .flatten()
+                .collect::<Vec<_>>();
+            Ok(playlist_response(format, &name, &entries))
+        })
+        .responder()
+}
+
+#[test]
+fn entry_urls() {
+    use actix_web::test::TestRequest;
+    use data::{Quality, TvEpisode, TvSeries};
+
+    let links = Links { base: "http://carolus".to_owned(), token: Some("a&b".to_owned()), cache_path: PathBuf::from("/nonexistent") };
+    let version = |file_path: &str, parts: &[&str]| MediaVersion {
+        name: "Original".to_owned(),
+        file_path: file_path.to_owned(),
+        parts: parts.iter().map(|p| p.to_string()).collect(),
+        edition: None,
+        quality: Quality::default(),
+        fingerprint: None,
+        added: None,
+    };
+    let movie = Movie { title: "Alien".to_owned(), year: Some(1979), versions: vec![version("/movies/Alien cd1.mp4", &["/movies/Alien cd1.mp4", "/movies/Alien cd2.mp4"])], extras: vec![] };
+    let tv_show = TvShow {
+        title: "Doctor Who".to_owned(),
+        year: Some(2005),
+        series: vec![Arc::new(TvSeries {
+            series_number: 1,
+            episodes: vec![Arc::new(TvEpisode { episode_number: 1, versions: vec![version("/tv/Doctor Who (2005)/S01E01.mp4", &[])] })],
+            extras: vec![],
+        })],
+        extras: vec![],
+    };
+
+    let progress = HashMap::new();
+    let entries = movie_entries(&movie, &progress, &links).into_iter()
+        .chain(tv_show_entries(&tv_show, None, None, &progress, &links).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(3, entries.len());
+    for (entry, year) in entries.iter().zip(&[1979, 1979, 2005]) {
+        assert_eq!(1, entry.url.matches("year=").count(), "{}", entry.url);
+        assert!(entry.url.ends_with("&token=a%26b"), "{}", entry.url);
+        let req = TestRequest::with_uri(&entry.url).finish();
+        let params = Query::<PlayParams>::extract(&req).unwrap_or_else(|e| panic!("{}: {}", entry.url, e));
+        assert_eq!(Some(*year), params.year, "{}", entry.url);
+    }
+}
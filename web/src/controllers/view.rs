@@ -1,17 +1,19 @@
 use actix_web::*;
 use actix_web::actix::*;
 use failure::{self, Fail};
-use futures::future::Future;
+use futures::future::{self, Future};
 use handlebars::Handlebars;
 use lazy_static::lazy_static;
 use log::error;
 use serde::Serialize;
 use serde_derive::Serialize;
 
-use data::{AllMoviesMessage, AllTvShowsMessage, MovieMessage, TvEpisodeMessage, TvSeriesMessage, TvShowMessage};
+use data::{Extra, LibraryQuery, MediaVersion, Movie, MovieMessage, MovieQueryMessage, ScanReportMessage, TvEpisode, TvEpisodeMessage, TvSeriesMessage, TvShow, TvShowMessage, TvShowQueryMessage};
 use crate::controllers::*;
 use data::error::Error;
+use crate::jobs::{AllJobsMessage, JobKind, TriggerJobMessage};
 use crate::ServerState;
+use crate::thumbnail;
 
 lazy_static! {
     static ref ERR_TPL: Handlebars = {
@@ -62,13 +64,7 @@ impl error::ResponseError for HtmlError {
             .to_html("error", &ERR_TPL)
             .unwrap();
 
-        match self.0 {
-            Error::Actix { .. } | Error::Template => {
-                HttpResponse::InternalServerError()
-            }
-            Error::MovieNotFound { .. } => HttpResponse::NotFound(),
-            Error::TvShowNotFound { .. } => HttpResponse::NotFound(),
-        }
+        HttpResponse::build(error_status(&self.0))
         .content_type("text/html")
         .body(body)
     }
@@ -109,14 +105,19 @@ pub fn about((state,): (State<ServerState>,)) -> Result<HttpResponse, HtmlError>
     Ok(HttpResponse::Ok().content_type("text/html").body(body))
 }
 
-pub fn all_movies((state,): (State<ServerState>,)) -> AsyncResponse {
+pub fn all_movies((state, query, req): (State<ServerState>, Query<LibraryQuery>, HttpRequest<ServerState>)) -> AsyncResponse {
+    let query = query.into_inner();
     state
         .data
-        .send(AllMoviesMessage)
+        .send(MovieQueryMessage {
+            query: query.clone(),
+            runtime: runtime_lookup(state.cache_path.to_owned()),
+            metadata: metadata_lookup(state.cache_path.to_owned(), TitleKind::Movie),
+        })
         .from_err()
         .and_then(move |res| match res {
-            Ok(movies) => {
-                let body = TemplatePayload::new(AllMoviesPayload { movies }, Meta::for_all_movies())
+            Ok(page) => {
+                let body = TemplatePayload::new(AllMoviesPayload::new(page, &query, &req), Meta::for_all_movies())
                     .to_html("all-movies", &state.template)?;
 
                 Ok(HttpResponse::Ok().content_type("text/html").body(body))
@@ -128,6 +129,7 @@ pub fn all_movies((state,): (State<ServerState>,)) -> AsyncResponse {
 
 pub fn movie(req: &HttpRequest<ServerState>) -> AsyncResponse {
     let info = Path::<(String,)>::extract(req).unwrap();
+    let version = version_param(req);
     let data = &req.state().data;
 
     let req = req.to_owned();
@@ -138,7 +140,7 @@ pub fn movie(req: &HttpRequest<ServerState>) -> AsyncResponse {
     .from_err()
     .and_then(move |res| match res {
         Ok(result) => {
-            let payload = MoviePayload::new(&result, &req.drop_state());
+            let payload = MoviePayload::new(&result, version, &req.drop_state())?;
             let body = TemplatePayload::new(
                 &payload,
                 Meta::for_movie(&payload.movie),
@@ -156,6 +158,64 @@ type AsyncFileResponse = Box<dyn Future<Item = fs::NamedFile, Error = HtmlError>
 
 pub fn play_movie(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
     let info = Path::<(String,)>::extract(req).unwrap();
+    let version = version_param(req);
+    let part = part_param(req);
+    let data = &req.state().data;
+
+    data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: year_param(req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(movie) => {
+            let file_path = movie_version(&movie, version)?.part(part)
+                .ok_or_else(|| HtmlError(Error::VersionNotFound { title: movie.title.to_owned(), version: version.unwrap_or(0) }))?;
+            Ok(fs::NamedFile::open(file_path)?)
+        },
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn movie_thumbnail(req: &HttpRequest<ServerState>) -> AsyncResponse {
+    let info = Path::<(String,String)>::extract(req).unwrap();
+    let version = version_param(req);
+    let data = &req.state().data;
+    let cache_path = req.state().cache_path.to_owned();
+    let req = req.to_owned();
+
+    data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: year_param(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(movie) => thumbnail_response(&req, &cache_path, &movie_version(&movie, version)?.file_path, &info.1),
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn movie_subtitle(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
+    let info = Path::<(String,usize)>::extract(req).unwrap();
+    let version = version_param(req);
+    let data = &req.state().data;
+
+    data.send(MovieMessage {
+        title: info.0.to_owned(),
+        year: year_param(req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(movie) => open_subtitle(&movie.title, &movie_version(&movie, version)?.file_path, info.1),
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn play_movie_extra(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
+    let info = Path::<(String,usize)>::extract(req).unwrap();
     let data = &req.state().data;
 
     data.send(MovieMessage {
@@ -164,20 +224,60 @@ pub fn play_movie(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
     })
     .from_err()
     .and_then(move |res| match res {
-        Ok(movie) => Ok(fs::NamedFile::open(movie.file_path.to_owned())?),
+        Ok(movie) => open_extra(&movie.title, &movie.extras, info.1),
         Err(e) => Err(HtmlError(e)),
     })
     .responder()
 }
 
-pub fn all_tv_shows((state,): (State<ServerState>,)) -> AsyncResponse {
+fn open_extra(title: &str, extras: &[Extra], index: usize) -> Result<fs::NamedFile, HtmlError> {
+    let extra = extras.get(index).ok_or_else(|| HtmlError(Error::ExtraNotFound { title: title.to_owned(), extra: index }))?;
+    Ok(fs::NamedFile::open(&extra.file_path)?)
+}
+
+fn movie_version(movie: &Movie, version: Option<usize>) -> Result<&MediaVersion, HtmlError> {
+    movie.version(version).ok_or_else(|| HtmlError(Error::VersionNotFound {
+        title: movie.title.to_owned(),
+        version: version.unwrap_or(0),
+    }))
+}
+
+/// Opens one of the subtitle files next to a video.
+fn open_subtitle(title: &str, file_path: &str, subtitle: usize) -> Result<fs::NamedFile, HtmlError> {
+    let file = index::subtitle_files(std::path::Path::new(file_path)).into_iter().nth(subtitle)
+        .ok_or_else(|| HtmlError(Error::SubtitleNotFound { title: title.to_owned(), subtitle }))?;
+    Ok(fs::NamedFile::open(file.file_path)?)
+}
+
+/// Serves one of the files generated by the [Thumbnailer](../thumbnail/struct.Thumbnailer.html).
+/// The sprite track is given the query it was asked for with, which its cues pass on to the
+/// sprite sheet, or they would get the sheet of the first version.
+fn thumbnail_response(req: &HttpRequest<ServerState>, cache_path: &std::path::Path, file_path: &str, name: &str) -> Result<HttpResponse, HtmlError> {
+    let not_found = || HtmlError(Error::ThumbnailNotFound { name: name.to_owned() });
+    if ![thumbnail::POSTER, thumbnail::SPRITE, thumbnail::SPRITE_TRACK].contains(&name) {
+        return Err(not_found());
+    }
+    let path = thumbnail::cache_dir(cache_path, file_path).join(name);
+    if name == thumbnail::SPRITE_TRACK {
+        let track = std::fs::read_to_string(path).map_err(|_| not_found())?;
+        return Ok(HttpResponse::Ok().content_type("text/vtt").body(thumbnail::with_sprite_query(&track, req.query_string())));
+    }
+    Ok(fs::NamedFile::open(path).map_err(|_| not_found())?.respond_to(req)?)
+}
+
+pub fn all_tv_shows((state, query, req): (State<ServerState>, Query<LibraryQuery>, HttpRequest<ServerState>)) -> AsyncResponse {
+    let query = query.into_inner();
     state
         .data
-        .send(AllTvShowsMessage)
+        .send(TvShowQueryMessage {
+            query: query.clone(),
+            runtime: runtime_lookup(state.cache_path.to_owned()),
+            metadata: metadata_lookup(state.cache_path.to_owned(), TitleKind::TvShow),
+        })
         .from_err()
         .and_then(move |res| match res {
-            Ok(tv_shows) => {
-                let body = TemplatePayload::new(AllTvShowsPayload { tv_shows }, Meta::for_all_tv_shows())
+            Ok(page) => {
+                let body = TemplatePayload::new(AllTvShowsPayload::new(page, &query, &req), Meta::for_all_tv_shows())
                     .to_html("all-tv-shows", &state.template)?;
 
                 Ok(HttpResponse::Ok().content_type("text/html").body(body))
@@ -242,6 +342,7 @@ pub fn tv_series(req: &HttpRequest<ServerState>) -> AsyncResponse {
 
 pub fn tv_episode(req: &HttpRequest<ServerState>) -> AsyncResponse {
     let info = Path::<(String,u16,u16)>::extract(req).unwrap();
+    let version = version_param(req);
     let data = &req.state().data;
 
     let req = req.to_owned();
@@ -254,7 +355,7 @@ pub fn tv_episode(req: &HttpRequest<ServerState>) -> AsyncResponse {
     .from_err()
     .and_then(move |res| match res {
         Ok(result) => {
-            let payload = TvEpisodePayload::new(&result.0, &result.1, &result.2, &req.drop_state());
+            let payload = TvEpisodePayload::new(&result.0, &result.1, &result.2, version, &req.drop_state())?;
             let body = TemplatePayload::new(
                 &payload,
                 Meta::for_tv_episode(&payload.tv_show, &payload.tv_series, &payload.tv_episode),
@@ -270,18 +371,166 @@ pub fn tv_episode(req: &HttpRequest<ServerState>) -> AsyncResponse {
 
 pub fn play_tv_episode(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
     let info = Path::<(String,u16,u16)>::extract(req).unwrap();
+    let version = version_param(req);
+    let part = part_param(req);
     let data = &req.state().data;
 
     data.send(TvEpisodeMessage {
+        title: info.0.to_owned(),
+        year: year_param(req),
+        series: info.1,
+        episode: info.2,
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok((show,_,episode)) => {
+            let file_path = episode_version(&show, &episode, version)?.part(part)
+                .ok_or_else(|| HtmlError(Error::VersionNotFound { title: show.title.to_owned(), version: version.unwrap_or(0) }))?;
+            Ok(fs::NamedFile::open(file_path)?)
+        },
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn play_tv_show_extra(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
+    let info = Path::<(String,usize)>::extract(req).unwrap();
+    let data = &req.state().data;
+
+    data.send(TvShowMessage {
+        title: info.0.to_owned(),
+        year: None,
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(show) => open_extra(&show.title, &show.extras, info.1),
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn play_tv_series_extra(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
+    let info = Path::<(String,u16,usize)>::extract(req).unwrap();
+    let data = &req.state().data;
+
+    data.send(TvSeriesMessage {
         title: info.0.to_owned(),
         year: None,
         series: info.1,
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok((show, series)) => open_extra(&format!("{}: Series {}", show.title, series.series_number), &series.extras, info.2),
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn tv_episode_subtitle(req: &HttpRequest<ServerState>) -> AsyncFileResponse {
+    let info = Path::<(String,u16,u16,usize)>::extract(req).unwrap();
+    let version = version_param(req);
+    let data = &req.state().data;
+
+    data.send(TvEpisodeMessage {
+        title: info.0.to_owned(),
+        year: year_param(req),
+        series: info.1,
         episode: info.2,
     })
     .from_err()
     .and_then(move |res| match res {
-        Ok((_,_,episode)) => Ok(fs::NamedFile::open(episode.file_path)?),
+        Ok((show,_,episode)) => open_subtitle(&show.title, &episode_version(&show, &episode, version)?.file_path, info.3),
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+pub fn tv_episode_thumbnail(req: &HttpRequest<ServerState>) -> AsyncResponse {
+    let info = Path::<(String,u16,u16,String)>::extract(req).unwrap();
+    let version = version_param(req);
+    let data = &req.state().data;
+    let cache_path = req.state().cache_path.to_owned();
+    let req = req.to_owned();
+
+    data.send(TvEpisodeMessage {
+        title: info.0.to_owned(),
+        year: year_param(&req),
+        series: info.1,
+        episode: info.2,
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok((show,_,episode)) => thumbnail_response(&req, &cache_path, &episode_version(&show, &episode, version)?.file_path, &info.3),
+        Err(e) => Err(HtmlError(e)),
+    })
+    .responder()
+}
+
+fn episode_version<'a>(show: &TvShow, episode: &'a TvEpisode, version: Option<usize>) -> Result<&'a MediaVersion, HtmlError> {
+    episode.version(version).ok_or_else(|| HtmlError(Error::VersionNotFound {
+        title: format!("{} E{:02}", show.title, episode.episode_number),
+        version: version.unwrap_or(0),
+    }))
+}
+
+pub fn jobs((state,): (State<ServerState>,)) -> AsyncResponse {
+    state
+        .jobs
+        .send(AllJobsMessage)
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(jobs) => {
+                let body = TemplatePayload::new(JobsPayload::new(jobs), Meta::for_jobs())
+                    .to_html("jobs", &state.template)?;
+
+                Ok(HttpResponse::Ok().content_type("text/html").body(body))
+            }
+            Err(e) => Err(HtmlError(e)),
+        })
+        .responder()
+}
+
+pub fn trigger_job(req: &HttpRequest<ServerState>) -> AsyncResponse {
+    let info = Path::<(String,)>::extract(req).unwrap();
+    let kind = match info.0.parse::<JobKind>() {
+        Ok(kind) => kind,
+        Err(e) => return Box::new(future::err(HtmlError(e))),
+    };
+
+    req.state().jobs.send(TriggerJobMessage { kind, target: None })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(()) => Ok(HttpResponse::SeeOther().header(http::header::LOCATION, "/admin/jobs").finish()),
         Err(e) => Err(HtmlError(e)),
     })
     .responder()
 }
+
+pub fn scan_report((state,): (State<ServerState>,)) -> AsyncResponse {
+    state
+        .data
+        .send(ScanReportMessage)
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(report) => {
+                let body = TemplatePayload::new(ScanReportPayload::new(report), Meta::for_scan_report())
+                    .to_html("scan-report", &state.template)?;
+
+                Ok(HttpResponse::Ok().content_type("text/html").body(body))
+            }
+            Err(e) => Err(HtmlError(e)),
+        })
+        .responder()
+}
+
+pub fn rescan((state, params): (State<ServerState>, Form<RescanParams>)) -> AsyncResponse {
+    state
+        .jobs
+        .send(TriggerJobMessage { kind: JobKind::Index, target: params.folder() })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(()) => Ok(HttpResponse::SeeOther().header(http::header::LOCATION, "/admin/jobs").finish()),
+            Err(e) => Err(HtmlError(e)),
+        })
+        .responder()
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use failure::{Error, format_err};
+use log::{info, trace, warn};
+
+use data::{LibraryExport, MediaVersion, Movie, TvShow, WatchProgress};
+
+/// Columns of the CSV export, which has a row for each version of a movie or episode.
+const CSV_COLUMNS: &[&str] = &[
+    "type", "title", "year", "series", "episode", "version", "edition", "resolution", "source",
+    "file_path", "parts", "watched", "part", "position", "duration",
+];
+
+/// Marks the .nfo files written here, which are the only ones written over.
+const NFO_MARKER: &str = "<!-- Written by Carolus, remove this line to keep it from being written over. -->";
+
+/// The library as CSV, leaving out the extras, which are only in the JSON export.
+pub fn csv(export: &LibraryExport) -> String {
+    let mut csv = csv_row(CSV_COLUMNS.iter().map(|c| c.to_string()).collect());
+    for movie in export.movies.iter() {
+        for version in &movie.versions {
+            csv.push_str(&version_row("movie", &movie.title, movie.year, None, version, export.progress.get(&version.file_path)));
+        }
+    }
+    for tv_show in export.tv_shows.iter() {
+        for tv_series in &tv_show.series {
+            for tv_episode in &tv_series.episodes {
+                let episode = Some((tv_series.series_number, tv_episode.episode_number));
+                for version in &tv_episode.versions {
+                    csv.push_str(&version_row("episode", &tv_show.title, tv_show.year, episode, version, export.progress.get(&version.file_path)));
+                }
+            }
+        }
+    }
+    csv
+}
+
+fn version_row(kind: &str, title: &str, year: Option<u16>, episode: Option<(u16, u16)>, version: &MediaVersion, progress: Option<&WatchProgress>) -> String {
+    csv_row(vec![
+        kind.to_owned(),
+        title.to_owned(),
+        optional(year),
+        optional(episode.map(|e| e.0)),
+        optional(episode.map(|e| e.1)),
+        version.name.to_owned(),
+        version.edition.clone().unwrap_or_default(),
+        optional(version.quality.resolution),
+        version.quality.source.clone().unwrap_or_default(),
+        version.file_path.to_owned(),
+        version.part_count().to_string(),
+        progress.map_or(false, |p| p.watched).to_string(),
+        optional(progress.map(|p| p.part)),
+        optional(progress.map(|p| p.position)),
+        optional(progress.and_then(|p| p.duration)),
+    ])
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |v| v.to_string())
+}
+
+/// A line of CSV, quoting the fields that need it as RFC 4180 has it.
+fn csv_row(fields: Vec<String>) -> String {
+    let fields = fields.into_iter()
+        .map(|f| if f.contains(&[',', '"', '\n', '\r'][..]) { format!("\"{}\"", f.replace('"', "\"\"")) } else { f })
+        .collect::<Vec<_>>();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Writes Kodi .nfo files next to every version of a movie or episode, and a `tvshow.nfo` in the
+/// folder of each show under `tv_path`, with how far they were watched. Files that weren't written
+/// here are left alone.
+pub fn write_nfo_files(export: &LibraryExport, tv_path: Option<&str>, progress: &dyn Fn(u8)) -> Result<(), Error> {
+    let mut files = BTreeMap::new();
+    for movie in export.movies.iter() {
+        for version in &movie.versions {
+            files.insert(nfo_path(&version.file_path), vec![movie_nfo(movie, version, export.progress.get(&version.file_path))]);
+        }
+    }
+    for tv_show in export.tv_shows.iter() {
+        let mut show_folder = None;
+        for tv_series in &tv_show.series {
+            for tv_episode in &tv_series.episodes {
+                for version in &tv_episode.versions {
+                    // A file with several episodes in it has the details of each of them in its .nfo.
+                    files.entry(nfo_path(&version.file_path)).or_insert_with(Vec::new)
+                        .push(episode_nfo(tv_show, tv_series.series_number, tv_episode.episode_number, export.progress.get(&version.file_path)));
+                    show_folder = show_folder.or_else(|| tv_path.and_then(|p| show_folder_of(Path::new(p), &version.file_path)));
+                }
+            }
+        }
+        if let Some(folder) = show_folder {
+            files.insert(folder.join("tvshow.nfo"), vec![tv_show_nfo(tv_show)]);
+        }
+    }
+
+    let (total, mut written, mut failed) = (files.len(), 0, 0);
+    for (i, (path, details)) in files.into_iter().enumerate() {
+        let contents = format!("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n{}\n{}", NFO_MARKER, details.concat());
+        match write_nfo(&path, &contents) {
+            Ok(true) => written += 1,
+            Ok(false) => (),
+            Err(err) => {
+                warn!("Could not write {:?}, err: {}", path, err);
+                failed += 1;
+            },
+        }
+        progress((i * 100 / total.max(1)) as u8);
+    }
+
+    info!("Wrote {} of {} .nfo files", written, total);
+    if failed > 0 {
+        return Err(format_err!("could not write {} of {} .nfo files", failed, total));
+    }
+    Ok(())
+}
+
+/// Writes an .nfo file unless it was written by someone else or is already up to date.
+fn write_nfo(path: &Path, contents: &str) -> Result<bool, Error> {
+    match fs::read_to_string(path) {
+        Ok(ref existing) if existing == contents => Ok(false),
+        Ok(ref existing) if !existing.contains(NFO_MARKER) => {
+            trace!("Leaving {:?} alone as it wasn't written by Carolus", path);
+            Ok(false)
+        },
+        _ => {
+            fs::write(path, contents)?;
+            Ok(true)
+        },
+    }
+}
+
+fn nfo_path(file_path: &str) -> PathBuf {
+    Path::new(file_path).with_extension("nfo")
+}
+
+/// The folder directly under `tv_path` that an episode is in.
+fn show_folder_of(tv_path: &Path, file_path: &str) -> Option<PathBuf> {
+    match Path::new(file_path).strip_prefix(tv_path).ok()?.components().next()? {
+        Component::Normal(name) => Some(tv_path.join(name)).filter(|f| f.is_dir()),
+        _ => None,
+    }
+}
+
+fn movie_nfo(movie: &Movie, version: &MediaVersion, progress: Option<&WatchProgress>) -> String {
+    let mut nfo = format!("<movie>\n{}", element("title", &movie.title));
+    if let Some(year) = movie.year {
+        nfo.push_str(&element("year", &year.to_string()));
+    }
+    if let Some(ref edition) = version.edition {
+        nfo.push_str(&element("edition", edition));
+    }
+    nfo.push_str(&watch_state(progress));
+    nfo.push_str("</movie>\n");
+    nfo
+}
+
+fn episode_nfo(tv_show: &TvShow, series: u16, episode: u16, progress: Option<&WatchProgress>) -> String {
+    let mut nfo = format!("<episodedetails>\n{}{}{}{}",
+        element("title", &format!("Episode {}", episode)),
+        element("showtitle", &tv_show.title),
+        element("season", &series.to_string()),
+        element("episode", &episode.to_string()));
+    nfo.push_str(&watch_state(progress));
+    nfo.push_str("</episodedetails>\n");
+    nfo
+}
+
+fn tv_show_nfo(tv_show: &TvShow) -> String {
+    let mut nfo = format!("<tvshow>\n{}", element("title", &tv_show.title));
+    if let Some(year) = tv_show.year {
+        nfo.push_str(&element("year", &year.to_string()));
+    }
+    nfo.push_str("</tvshow>\n");
+    nfo
+}
+
+/// Whether it was watched, or else where to resume it. Kodi resumes within the first file,
+/// so later parts of a version aren't resumed.
+fn watch_state(progress: Option<&WatchProgress>) -> String {
+    match progress {
+        Some(p) if p.watched => element("playcount", "1"),
+        Some(p) if p.part == 0 && p.position > 0.0 => format!("    <resume>\n    {}    {}    </resume>\n",
+            element("position", &p.position.to_string()),
+            element("total", &p.duration.unwrap_or(0.0).to_string())),
+        _ => String::new(),
+    }
+}
+
+fn element(name: &str, value: &str) -> String {
+    format!("    <{}>{}</{}>\n", name, xml_escape(value), name)
+}
+
+/// Escapes text to go in an XML element or attribute.
+pub fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+#[test]
+fn csv_rows() {
+    let row = |fields: &[&str]| csv_row(fields.iter().map(|f| f.to_string()).collect());
+
+    assert_eq!("movie,Alien,1979\r\n", row(&["movie", "Alien", "1979"]));
+    assert_eq!(",,\r\n", row(&["", "", ""]));
+    assert_eq!("\"Crouching Tiger, Hidden Dragon\",2000\r\n", row(&["Crouching Tiger, Hidden Dragon", "2000"]));
+    assert_eq!("\"The \"\"Best\"\" Cut\"\r\n", row(&["The \"Best\" Cut"]));
+    assert_eq!("\"one\ntwo\",\"three\r\"\r\n", row(&["one\ntwo", "three\r"]));
+}
+
+#[test]
+fn xml_elements() {
+    assert_eq!("    <title>Alien</title>\n", element("title", "Alien"));
+    assert_eq!("    <title>Tom &amp; Jerry &lt;1940&gt;</title>\n", element("title", "Tom & Jerry <1940>"));
+    assert_eq!("&quot;Director&apos;s&quot; &amp;amp;", xml_escape("\"Director's\" &amp;"));
+}
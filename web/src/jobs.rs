@@ -0,0 +1,473 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::actix::*;
+use failure::format_err;
+use futures::Future;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use data::{AllMoviesMessage, AllTvShowsMessage, DataExecutor, ExportMessage, UpdateLibraryMessage, error::Error};
+use index::{self, NamingPatterns, ScanOptions};
+
+use crate::export;
+use crate::metadata::MetadataFetcher;
+use crate::thumbnail::Thumbnailer;
+
+/// Number of times a job is run before it is marked as failed.
+const MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubled for every attempt after that.
+const RETRY_BACKOFF_SECONDS: u64 = 30;
+/// How long the queue waits after a change before saving, so that the thumbnails of a whole
+/// library finishing one after the other are saved every few seconds rather than after each file.
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Index,
+    Thumbnails,
+    Nfo,
+    Metadata,
+    Artwork,
+}
+
+impl JobKind {
+    /// Every kind of job that can be triggered.
+    pub fn all() -> Vec<JobKind> {
+        vec![JobKind::Index, JobKind::Thumbnails, JobKind::Nfo, JobKind::Metadata, JobKind::Artwork]
+    }
+}
+
+impl fmt::Display for JobKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobKind::Index => write!(f, "index"),
+            JobKind::Thumbnails => write!(f, "thumbnails"),
+            JobKind::Nfo => write!(f, "nfo"),
+            JobKind::Metadata => write!(f, "metadata"),
+            JobKind::Artwork => write!(f, "artwork"),
+        }
+    }
+}
+
+impl FromStr for JobKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(JobKind::Index),
+            "thumbnails" => Ok(JobKind::Thumbnails),
+            "nfo" => Ok(JobKind::Nfo),
+            "metadata" => Ok(JobKind::Metadata),
+            "artwork" => Ok(JobKind::Artwork),
+            _ => Err(Error::JobKindNotFound { name: s.to_owned() }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    /// What the job works on, e.g. the file path for thumbnails or the folder
+    /// to index. Empty when it covers the whole library.
+    pub target: String,
+    pub state: JobState,
+    /// Percentage complete.
+    pub progress: u8,
+    pub attempts: u32,
+    /// Unix timestamp before which a queued job will not be retried.
+    pub retry_at: u64,
+    pub error: Option<String>,
+}
+
+/// Keeps track of every queued, running and failed job and hands them to the
+/// [JobWorker](struct.JobWorker.html)s while respecting the concurrency limit of each job kind.
+///
+/// Finished jobs are dropped, everything else is saved to `state_path` so the
+/// queue survives a restart, a few seconds after it changed.
+pub struct JobQueue {
+    /// By id, which is the order they were queued in.
+    jobs: BTreeMap<u64, Job>,
+    /// The id of the job for each kind and target, as there is only ever one.
+    ids: HashMap<(JobKind, String), u64>,
+    next_id: u64,
+    limits: HashMap<JobKind, usize>,
+    schedules: Vec<(JobKind, Duration)>,
+    state_path: PathBuf,
+    data: Addr<DataExecutor>,
+    workers: Addr<JobWorker>,
+    retry_handle: Option<SpawnHandle>,
+    save_handle: Option<SpawnHandle>,
+}
+
+impl JobQueue {
+    pub fn new(
+        state_path: PathBuf,
+        limits: HashMap<JobKind, usize>,
+        schedules: Vec<(JobKind, Duration)>,
+        data: Addr<DataExecutor>,
+        workers: Addr<JobWorker>,
+    ) -> Self {
+        let saved: Vec<Job> = File::open(&state_path).ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default();
+        let mut jobs = BTreeMap::new();
+        let mut ids = HashMap::new();
+        for mut job in saved {
+            if job.state == JobState::Running {
+                job.state = JobState::Queued;
+                job.progress = 0;
+            }
+            ids.insert((job.kind, job.target.to_owned()), job.id);
+            jobs.insert(job.id, job);
+        }
+        let next_id = jobs.keys().next_back().map_or(0, |id| id + 1);
+
+        Self { jobs, ids, next_id, limits, schedules, state_path, data, workers, retry_handle: None, save_handle: None }
+    }
+
+    fn enqueue(&mut self, kind: JobKind, target: String) {
+        let key = (kind, target);
+        let id = self.ids.get(&key).cloned();
+        match id.and_then(|id| self.jobs.get_mut(&id)) {
+            Some(job) => {
+                // Triggering a failed job again is how it gets retried manually.
+                if job.state == JobState::Failed {
+                    job.state = JobState::Queued;
+                    job.attempts = 0;
+                    job.retry_at = 0;
+                }
+            },
+            None => {
+                let id = self.next_id;
+                self.jobs.insert(id, Job {
+                    id,
+                    kind,
+                    target: key.1.to_owned(),
+                    state: JobState::Queued,
+                    progress: 0,
+                    attempts: 0,
+                    retry_at: 0,
+                    error: None,
+                });
+                self.ids.insert(key, id);
+                self.next_id += 1;
+            },
+        }
+    }
+
+    fn trigger(&mut self, kind: JobKind, target: Option<String>, ctx: &mut Context<Self>) {
+        info!("Queueing {} jobs for {:?}", kind, target);
+        match (kind, target) {
+            (JobKind::Index, target) => {
+                self.enqueue(JobKind::Index, target.unwrap_or_default());
+                self.save_later(ctx);
+                self.dispatch(ctx);
+            },
+            // The .nfo files are always written for the whole library, as that takes no time, and
+            // metadata and artwork are only fetched for what doesn't have them yet.
+            (kind @ JobKind::Nfo, _) | (kind @ JobKind::Metadata, _) | (kind @ JobKind::Artwork, _) => {
+                self.enqueue(kind, String::new());
+                self.save_later(ctx);
+                self.dispatch(ctx);
+            },
+            (JobKind::Thumbnails, Some(target)) => {
+                self.enqueue(JobKind::Thumbnails, target);
+                self.save_later(ctx);
+                self.dispatch(ctx);
+            },
+            (JobKind::Thumbnails, None) => {
+                self.data.send(AllMoviesMessage)
+                    .join(self.data.send(AllTvShowsMessage))
+                    .into_actor(self)
+                    .then(move |res, act, ctx| {
+                        match res {
+                            Ok((Ok(movies), Ok(tv_shows))) => {
+                                for version in movies.iter().flat_map(|m|m.versions.iter()) {
+                                    act.enqueue(JobKind::Thumbnails, version.file_path.to_owned());
+                                }
+                                for version in tv_shows.iter().flat_map(|s|s.series.iter()).flat_map(|s|s.episodes.iter()).flat_map(|e|e.versions.iter()) {
+                                    act.enqueue(JobKind::Thumbnails, version.file_path.to_owned());
+                                }
+                                act.save_later(ctx);
+                                act.dispatch(ctx);
+                            },
+                            _ => warn!("Could not read the library to queue {} jobs", kind),
+                        }
+                        fut::ok(())
+                    })
+                    .spawn(ctx);
+            },
+        }
+    }
+
+    /// Starts as many queued jobs as the concurrency limits allow.
+    fn dispatch(&mut self, ctx: &mut Context<Self>) {
+        let now = unix_now();
+        let mut running = HashMap::new();
+        for job in self.jobs.values().filter(|j| j.state == JobState::Running) {
+            *running.entry(job.kind).or_insert(0) += 1;
+        }
+
+        let mut next_retry: Option<u64> = None;
+        let mut started = vec![];
+        for job in self.jobs.values_mut().filter(|j| j.state == JobState::Queued) {
+            if job.retry_at > now {
+                next_retry = Some(next_retry.map_or(job.retry_at, |r| r.min(job.retry_at)));
+                continue;
+            }
+            let count = running.entry(job.kind).or_insert(0);
+            if *count >= *self.limits.get(&job.kind).unwrap_or(&1) {
+                continue;
+            }
+            *count += 1;
+            job.state = JobState::Running;
+            job.progress = 0;
+            started.push(RunJobMessage { id: job.id, kind: job.kind, target: job.target.to_owned(), queue: ctx.address() });
+        }
+
+        for msg in started {
+            let id = msg.id;
+            self.workers.send(msg)
+                .into_actor(self)
+                .then(move |res, act, ctx| {
+                    act.finish(id, res.map_err(|e| e.to_string()).and_then(|r| r.map_err(|e| e.to_string())));
+                    act.save_later(ctx);
+                    act.dispatch(ctx);
+                    fut::ok(())
+                })
+                .spawn(ctx);
+        }
+
+        if let Some(handle) = self.retry_handle.take() {
+            ctx.cancel_future(handle);
+        }
+        if let Some(retry_at) = next_retry {
+            self.retry_handle = Some(ctx.run_later(Duration::from_secs(retry_at - now), |act, ctx| {
+                act.retry_handle = None;
+                act.dispatch(ctx);
+            }));
+        }
+    }
+
+    fn finish(&mut self, id: u64, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                if let Some(job) = self.jobs.remove(&id) {
+                    self.ids.remove(&(job.kind, job.target));
+                }
+            },
+            Err(err) => {
+                if let Some(job) = self.jobs.get_mut(&id) {
+                    job.attempts += 1;
+                    job.error = Some(err);
+                    if job.attempts < MAX_ATTEMPTS {
+                        job.state = JobState::Queued;
+                        job.retry_at = unix_now() + RETRY_BACKOFF_SECONDS * 2u64.pow(job.attempts - 1);
+                        warn!("{} job for {:?} failed, retrying at {}", job.kind, job.target, job.retry_at);
+                    } else {
+                        job.state = JobState::Failed;
+                        warn!("{} job for {:?} failed after {} attempts", job.kind, job.target, job.attempts);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Saves the jobs once the queue has gone [SAVE_DELAY](constant.SAVE_DELAY.html) without
+    /// being saved, taking in every change made until then.
+    fn save_later(&mut self, ctx: &mut Context<Self>) {
+        if self.save_handle.is_none() {
+            self.save_handle = Some(ctx.run_later(SAVE_DELAY, |act, _| {
+                act.save_handle = None;
+                act.save();
+            }));
+        }
+    }
+
+    /// Saves the jobs to a file of their own first and then puts it in place of the old one,
+    /// so the queue is never left half written.
+    fn save(&self) {
+        let temp = self.state_path.with_extension("json.tmp");
+        let result = self.state_path.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| File::create(&temp))
+            .map_err(failure::Error::from)
+            .and_then(|file| Ok(serde_json::to_writer(file, &self.jobs.values().collect::<Vec<_>>())?))
+            .and_then(|_| Ok(fs::rename(&temp, &self.state_path)?));
+        if let Err(err) = result {
+            warn!("Could not save job state to {:?}, err: {}", self.state_path, err);
+        }
+    }
+}
+
+impl Actor for JobQueue {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        for &(kind, interval) in &self.schedules {
+            ctx.run_interval(interval, move |act, ctx| act.trigger(kind, None, ctx));
+        }
+        self.dispatch(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        if self.save_handle.is_some() {
+            self.save();
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Queues jobs of `kind` for `target`, or for the whole library when there is no target.
+pub struct TriggerJobMessage {
+    pub kind: JobKind,
+    pub target: Option<String>,
+}
+
+type TriggerJobResult = Result<(), Error>;
+
+impl Message for TriggerJobMessage {
+    type Result = TriggerJobResult;
+}
+
+impl Handler<TriggerJobMessage> for JobQueue {
+    type Result = TriggerJobResult;
+
+    fn handle(&mut self, msg: TriggerJobMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.trigger(msg.kind, msg.target, ctx);
+        Ok(())
+    }
+}
+
+pub struct AllJobsMessage;
+
+type AllJobsResult = Result<Vec<Job>, Error>;
+
+impl Message for AllJobsMessage {
+    type Result = AllJobsResult;
+}
+
+impl Handler<AllJobsMessage> for JobQueue {
+    type Result = AllJobsResult;
+
+    fn handle(&mut self, _: AllJobsMessage, _: &mut Self::Context) -> Self::Result {
+        Ok(self.jobs.values().cloned().collect())
+    }
+}
+
+pub struct JobProgressMessage {
+    pub id: u64,
+    pub progress: u8,
+}
+
+impl Message for JobProgressMessage {
+    type Result = ();
+}
+
+impl Handler<JobProgressMessage> for JobQueue {
+    type Result = ();
+
+    fn handle(&mut self, msg: JobProgressMessage, _: &mut Self::Context) -> Self::Result {
+        if let Some(job) = self.jobs.get_mut(&msg.id) {
+            job.progress = msg.progress;
+        }
+    }
+}
+
+/// Runs jobs handed out by the [JobQueue](struct.JobQueue.html) on a pool of threads.
+pub struct JobWorker {
+    pub thumbnailer: Thumbnailer,
+    pub metadata: MetadataFetcher,
+    pub data: Addr<DataExecutor>,
+    pub movie_path: Option<String>,
+    pub tv_path: Option<String>,
+    pub naming: Arc<NamingPatterns>,
+    pub scan: ScanOptions,
+}
+
+impl Actor for JobWorker {
+    type Context = SyncContext<Self>;
+}
+
+pub struct RunJobMessage {
+    pub id: u64,
+    pub kind: JobKind,
+    pub target: String,
+    pub queue: Addr<JobQueue>,
+}
+
+impl Message for RunJobMessage {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<RunJobMessage> for JobWorker {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: RunJobMessage, _: &mut Self::Context) -> Self::Result {
+        let (id, queue) = (msg.id, msg.queue);
+        let progress = {
+            let queue = queue.clone();
+            move |progress| queue.do_send(JobProgressMessage { id, progress })
+        };
+
+        match msg.kind {
+            JobKind::Index => {
+                let movie_path = self.movie_path.as_ref().map(String::as_str);
+                let tv_path = self.tv_path.as_ref().map(String::as_str);
+                let library = match msg.target.as_str() {
+                    "" => index::directories(movie_path, tv_path, &self.naming, &self.scan)?,
+                    folder => index::folder(movie_path, tv_path, folder, &self.naming, &self.scan)?,
+                };
+                progress(90);
+
+                let report = self.data.send(UpdateLibraryMessage(library)).wait().map_err(|e| format_err!("{}", e))??;
+                info!("Scanned {:?}: {} added, {} removed, {} moved, {} failed", report.folder, report.added.len(), report.removed.len(), report.moved.len(), report.failures.len());
+                if let Err(err) = self.scan.fingerprints.save() {
+                    warn!("Could not save file fingerprints, err: {}", err);
+                }
+                for moved in &report.moved {
+                    self.thumbnailer.moved(&moved.from, &moved.to);
+                }
+                // Moved files only need thumbnails if they never had any, which the job checks first.
+                for file_path in report.added.iter().chain(report.moved.iter().map(|m| &m.to)) {
+                    queue.do_send(TriggerJobMessage { kind: JobKind::Thumbnails, target: Some(file_path.to_owned()) });
+                }
+                Ok(())
+            },
+            JobKind::Thumbnails => self.thumbnailer.generate(&msg.target, &progress),
+            JobKind::Nfo => {
+                let library = self.data.send(ExportMessage).wait().map_err(|e| format_err!("{}", e))??;
+                export::write_nfo_files(&library, self.tv_path.as_ref().map(String::as_str), &progress)
+            },
+            JobKind::Metadata => {
+                let library = self.data.send(ExportMessage).wait().map_err(|e| format_err!("{}", e))??;
+                self.metadata.fetch_metadata(&library, &progress)?;
+                // Artwork can only be found once the metadata says where it is.
+                queue.do_send(TriggerJobMessage { kind: JobKind::Artwork, target: None });
+                Ok(())
+            },
+            JobKind::Artwork => {
+                let library = self.data.send(ExportMessage).wait().map_err(|e| format_err!("{}", e))??;
+                self.metadata.fetch_artwork(&library, &progress)
+            },
+        }
+    }
+}
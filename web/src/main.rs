@@ -1,5 +1,9 @@
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use actix_web::{
     actix::*,
@@ -8,19 +12,33 @@ use actix_web::{
 };
 use failure::Error;
 use handlebars::Handlebars;
-use log::{info, Level};
+use log::Level;
 
-use data::{DataExecutor, DataSet, Movie, TvShow, TvSeries, TvEpisode};
-use index;
+use data::{DataExecutor, DataSet, Library, MediaVersion, Movie, Quality, TvShow, TvSeries, TvEpisode};
+use index::{FingerprintCache, NamingPatterns, ScanOptions};
 
-use crate::controllers::{view};
+use crate::checksum::ChecksumExecutor;
+use crate::controllers::{api, playlist, view};
+use crate::jobs::{JobKind, JobQueue, JobWorker, TriggerJobMessage};
+use crate::metadata::MetadataFetcher;
+use crate::thumbnail::Thumbnailer;
 
+mod cache;
+mod checksum;
 mod cli;
 mod controllers;
+mod export;
+mod jobs;
+mod metadata;
+mod playlist;
+mod thumbnail;
 
 pub struct ServerState {
     pub data: Addr<DataExecutor>,
+    pub jobs: Addr<JobQueue>,
+    pub checksums: Addr<ChecksumExecutor>,
     pub template: Handlebars,
+    pub cache_path: PathBuf,
 }
 
 /// Registers the [Handlebars](handlebars.handlebars.html) templates for the application.
@@ -41,28 +59,53 @@ impl fs::StaticFileConfig for StaticFileConfig {
     }
 }
 
-fn get_demo_data_set() -> (Vec<Movie>, Vec<TvShow>) {
-    (vec![
-        Movie {
-            title: "Die Hard".to_owned(),
-            year: None,
-            file_path: "./web/dist/video/demo.mp4".to_owned(),
-        }
-    ], vec![
-        TvShow {
-            title: "Jonathan Creek".to_owned(),
-            year: None,
-            series: vec![TvSeries {
-                series_number: 1,
-                episodes: vec![
-                    TvEpisode {
-                        episode_number: 1,
-                        file_path: "./web/dist/video/demo.mp4".to_owned(),
-                    }
-                ],
-            }],
-        }
-    ])
+fn get_demo_data_set() -> Library {
+    Library {
+        folder: None,
+        movies: vec![
+            Movie {
+                title: "Die Hard".to_owned(),
+                year: None,
+                versions: vec![MediaVersion {
+                    name: "demo.mp4".to_owned(),
+                    file_path: "./web/dist/video/demo.mp4".to_owned(),
+                    parts: vec![],
+                    edition: None,
+                    quality: Quality::default(),
+                    fingerprint: None,
+                    added: None,
+                }],
+                extras: vec![],
+            }
+        ],
+        tv_shows: vec![
+            TvShow {
+                title: "Jonathan Creek".to_owned(),
+                year: None,
+                series: vec![Arc::new(TvSeries {
+                    series_number: 1,
+                    episodes: vec![
+                        Arc::new(TvEpisode {
+                            episode_number: 1,
+                            versions: vec![MediaVersion {
+                                name: "demo.mp4".to_owned(),
+                                file_path: "./web/dist/video/demo.mp4".to_owned(),
+                                parts: vec![],
+                                edition: None,
+                                quality: Quality::default(),
+                                fingerprint: None,
+                                added: None,
+                            }],
+                        })
+                    ],
+                    extras: vec![],
+                })],
+                extras: vec![],
+            }
+        ],
+        failures: vec![],
+        conflicts: vec![],
+    }
 }
 
 fn main() -> Result<(), Error> {
@@ -70,26 +113,105 @@ fn main() -> Result<(), Error> {
 
     init_logging(matches.occurrences_of("v"))?;
 
-    let (movies, tv_shows) = if matches.is_present("demo") {
-        get_demo_data_set()
-    } else {
-         index::directories(matches.value_of("movie_path"), matches.value_of("tv_path"))?
+    let movie_path = matches.value_of("movie_path").map(str::to_owned);
+    let tv_path = matches.value_of("tv_path").map(str::to_owned);
+    let naming = Arc::new(match matches.value_of("naming_patterns") {
+        Some(path) => NamingPatterns::load(Path::new(path))?,
+        None => NamingPatterns::default(),
+    });
+    let cache_path = PathBuf::from(matches.value_of("cache_path").unwrap());
+    let scan = ScanOptions {
+        follow_symlinks: !matches.is_present("no_follow_symlinks"),
+        max_depth: match matches.value_of("max_depth") {
+            Some(depth) => Some(depth.parse::<usize>()?),
+            None => None,
+        },
+        jobs: matches.value_of("index_jobs").unwrap().parse::<usize>()?,
+        fingerprints: Arc::new(FingerprintCache::load(cache_path.join("fingerprints.json"))),
     };
 
-    info!("finished indexing files");
-    
-    let movies = Arc::new(movies.into_iter().map(|m|Arc::new(m)).collect::<Vec<_>>());
-    let tv_shows = Arc::new(tv_shows.into_iter().map(|m|Arc::new(m)).collect::<Vec<_>>());
+    // The library is indexed by a job once the server is up, which can take a while.
+    let demo = matches.is_present("demo");
+    let mut data_set = DataSet::default();
+    if demo {
+        data_set.update(get_demo_data_set());
+    }
+    let playlists_path = cache_path.join("playlists.json");
+    if playlists_path.is_file() {
+        data_set.playlists = serde_json::from_reader(File::open(&playlists_path)?)?;
+    }
+    data_set.playlists_path = Some(playlists_path);
+    let progress_path = cache_path.join("progress.json");
+    if progress_path.is_file() {
+        data_set.progress = serde_json::from_reader(File::open(&progress_path)?)?;
+    }
+    data_set.progress_path = Some(progress_path);
+    let data_set = Arc::new(RwLock::new(data_set));
     
+    let interval = matches.value_of("thumbnail_interval").unwrap().parse::<u32>()?;
+
     let sys = System::new("carolus");
-    let addr = SyncArbiter::start(num_cpus::get(), move || DataExecutor(DataSet{movies: movies.clone(), tv_shows: tv_shows.clone()}));
+
+    let thumbnailer = Thumbnailer {
+        cache_path: cache_path.clone(),
+        ffmpeg: matches.value_of("ffmpeg").unwrap().to_owned(),
+        ffprobe: matches.value_of("ffprobe").unwrap().to_owned(),
+        interval,
+    };
+    let metadata = MetadataFetcher {
+        cache_path: cache_path.clone(),
+        api_key: matches.value_of("tmdb_api_key").map(str::to_owned),
+    };
+
+    let mut limits = HashMap::new();
+    limits.insert(JobKind::Index, 1);
+    limits.insert(JobKind::Thumbnails, matches.value_of("thumbnail_jobs").unwrap().parse::<usize>()?);
+    limits.insert(JobKind::Metadata, 1);
+    limits.insert(JobKind::Artwork, 1);
+
+    let mut schedules = vec![];
+    if let Some(seconds) = matches.value_of("index_schedule") {
+        schedules.push((JobKind::Index, Duration::from_secs(seconds.parse::<u64>()?)));
+    }
+    if let Some(seconds) = matches.value_of("thumbnail_schedule") {
+        schedules.push((JobKind::Thumbnails, Duration::from_secs(seconds.parse::<u64>()?)));
+    }
+
+    let addr = SyncArbiter::start(num_cpus::get(), move || DataExecutor(data_set.clone()));
+    let checksums = {
+        let fingerprints = scan.fingerprints.clone();
+        SyncArbiter::start(1, move || ChecksumExecutor(fingerprints.clone()))
+    };
+
+    let workers = {
+        let data = addr.clone();
+        SyncArbiter::start(limits.values().sum::<usize>(), move || JobWorker {
+            thumbnailer: thumbnailer.clone(),
+            metadata: metadata.clone(),
+            data: data.clone(),
+            movie_path: movie_path.clone(),
+            tv_path: tv_path.clone(),
+            naming: naming.clone(),
+            scan: scan.clone(),
+        })
+    };
+    let jobs = JobQueue::new(cache_path.join("jobs.json"), limits, schedules, addr.clone(), workers).start();
+    if demo {
+        jobs.do_send(TriggerJobMessage { kind: JobKind::Thumbnails, target: None });
+    } else {
+        // Indexing queues thumbnails for every file it adds, which is all of them the first time.
+        jobs.do_send(TriggerJobMessage { kind: JobKind::Index, target: None });
+    }
 
     server::new(move || {
         let template = register_templates().unwrap();
 
         App::with_state(ServerState {
             data: addr.clone(),
+            jobs: jobs.clone(),
+            checksums: checksums.clone(),
             template,
+            cache_path: cache_path.clone(),
         })
         .handler(
             "/static",
@@ -108,6 +230,15 @@ fn main() -> Result<(), Error> {
         .resource("play/movie/{movie}", |r| {
             r.get().f(view::play_movie)
         })
+        .resource("/thumbnail/movie/{movie}/{file}", |r| {
+            r.get().f(view::movie_thumbnail)
+        })
+        .resource("/subtitle/movie/{movie}/{subtitle}", |r| {
+            r.get().f(view::movie_subtitle)
+        })
+        .resource("/extra/movie/{movie}/{extra}", |r| {
+            r.get().f(view::play_movie_extra)
+        })
         .resource("/tv", |r| {
             r.name("all_tv_shows");
             r.get().with(view::all_tv_shows)
@@ -127,6 +258,110 @@ fn main() -> Result<(), Error> {
         .resource("/play/tv/{tv_show}/{series}/{episode}", |r| {
             r.get().f(view::play_tv_episode)
         })
+        .resource("/thumbnail/tv/{tv_show}/{series}/{episode}/{file}", |r| {
+            r.get().f(view::tv_episode_thumbnail)
+        })
+        .resource("/subtitle/tv/{tv_show}/{series}/{episode}/{subtitle}", |r| {
+            r.get().f(view::tv_episode_subtitle)
+        })
+        .resource("/extra/tv/{tv_show}/{extra}", |r| {
+            r.get().f(view::play_tv_show_extra)
+        })
+        .resource("/extra/tv/{tv_show}/{series}/{extra}", |r| {
+            r.get().f(view::play_tv_series_extra)
+        })
+        .resource("/playlist/movies.{format}", |r| {
+            r.get().f(playlist::all_movies)
+        })
+        .resource("/playlist/tv/{tv_show}.{format}", |r| {
+            r.get().f(playlist::tv_show)
+        })
+        .resource("/playlist/tv/{tv_show}/{series}.{format}", |r| {
+            r.get().f(playlist::tv_series)
+        })
+        .resource("/playlists/{name}.{format}", |r| {
+            r.get().f(playlist::saved)
+        })
+        .resource("/admin/jobs", |r| {
+            r.name("jobs");
+            r.get().with(view::jobs)
+        })
+        .resource("/admin/jobs/{kind}", |r| {
+            r.post().f(view::trigger_job)
+        })
+        .resource("/admin/scan", |r| {
+            r.name("scan_report");
+            r.get().with(view::scan_report);
+            r.post().with(view::rescan)
+        })
+        .resource("/api/scan", |r| {
+            r.get().with(api::scan_report);
+            r.post().with(api::rescan)
+        })
+        .resource("/api/movies", |r| {
+            r.get().with(api::all_movies)
+        })
+        .resource("/api/movie/{movie}", |r| {
+            r.get().f(api::movie)
+        })
+        .resource("/api/movies/play/{movie}", |r| {
+            r.get().with(api::play_movie)
+        })
+        .resource("/api/progress", |r| {
+            r.get().with(api::all_progress)
+        })
+        .resource("/api/export", |r| {
+            r.get().with(api::export)
+        })
+        .resource("/api/import", |r| {
+            r.post().with_config(api::import, |cfg| {
+                cfg.1.limit(api::IMPORT_LIMIT);
+            })
+        })
+        .resource("/api/movies/progress/{movie}", |r| {
+            r.post().with(api::movie_progress)
+        })
+        .resource("/api/movies/download/{movie}", |r| {
+            r.get().with(api::download_movie)
+        })
+        .resource("/api/tv/play/{tv_show}/{series}/{episode}", |r| {
+            r.get().with(api::play_tv_episode)
+        })
+        .resource("/api/tv/progress/{tv_show}/{series}/{episode}", |r| {
+            r.post().with(api::tv_episode_progress)
+        })
+        .resource("/api/tv/download/{tv_show}", |r| {
+            r.get().with(api::download_tv_show)
+        })
+        .resource("/api/tv", |r| {
+            r.get().with(api::all_tv_shows)
+        })
+        .resource("/api/tv/{tv_show}", |r| {
+            r.get().f(api::tv_show)
+        })
+        .resource("/api/tv/{tv_show}/{series}", |r| {
+            r.get().f(api::tv_series)
+        })
+        .resource("/api/tv/{tv_show}/{series}/{episode}", |r| {
+            r.get().f(api::tv_episode)
+        })
+        .resource("/api/playlists", |r| {
+            r.get().with(api::all_playlists)
+        })
+        .resource("/api/playlists/{name}", |r| {
+            r.get().with(api::playlist);
+            r.put().with(api::save_playlist);
+            r.delete().with(api::delete_playlist)
+        })
+        .resource("/api/search", |r| {
+            r.get().with(api::search)
+        })
+        .resource("/api/jobs", |r| {
+            r.get().with(api::jobs)
+        })
+        .resource("/api/jobs/{kind}", |r| {
+            r.post().f(api::trigger_job)
+        })
         .middleware(middleware::Logger::default())
     })
     .bind(format!("0.0.0.0:{}", matches.value_of("port").unwrap()))
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use failure::{Error, format_err};
+use log::{info, trace, warn};
+use reqwest::Client;
+use serde_derive::{Deserialize, Serialize};
+use url::Url;
+
+use data::LibraryExport;
+
+use crate::cache;
+
+const API_URL: &str = "https://api.themoviedb.org/3";
+const IMAGE_URL: &str = "https://image.tmdb.org/t/p/original";
+
+/// What The Movie Database has on a title, or `null` when it has nothing.
+pub const METADATA: &str = "metadata.json";
+pub const POSTER: &str = "poster.jpg";
+pub const BACKDROP: &str = "backdrop.jpg";
+
+/// Fetches details and artwork of movies and tv shows from The Movie Database into the cache,
+/// once for each title.
+#[derive(Clone)]
+pub struct MetadataFetcher {
+    pub cache_path: PathBuf,
+    pub api_key: Option<String>,
+}
+
+/// What is kept of a movie or tv show from The Movie Database.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub id: u64,
+    pub overview: String,
+    pub poster_path: Option<String>,
+    pub backdrop_path: Option<String>,
+    pub genres: Vec<String>,
+    /// Out of 10.
+    pub rating: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct SearchResults {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    id: u64,
+    #[serde(default)]
+    overview: String,
+    poster_path: Option<String>,
+    backdrop_path: Option<String>,
+    #[serde(default)]
+    genre_ids: Vec<u64>,
+    vote_average: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct Genres {
+    genres: Vec<Genre>,
+}
+
+#[derive(Deserialize)]
+struct Genre {
+    id: u64,
+    name: String,
+}
+
+/// Whether a title is of a movie or a tv show, which The Movie Database has apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TitleKind {
+    Movie,
+    TvShow,
+}
+
+impl TitleKind {
+    fn api_name(self) -> &'static str {
+        match self {
+            TitleKind::Movie => "movie",
+            TitleKind::TvShow => "tv",
+        }
+    }
+
+    fn year_param(self) -> &'static str {
+        match self {
+            TitleKind::Movie => "year",
+            TitleKind::TvShow => "first_air_date_year",
+        }
+    }
+}
+
+/// Every movie and tv show in the library.
+pub fn titles(export: &LibraryExport) -> Vec<(TitleKind, &str, Option<u16>)> {
+    export.movies.iter().map(|m| (TitleKind::Movie, &*m.title, m.year))
+        .chain(export.tv_shows.iter().map(|s| (TitleKind::TvShow, &*s.title, s.year)))
+        .collect()
+}
+
+/// Directory holding the metadata and artwork of a title.
+pub fn cache_dir(cache_path: &Path, kind: TitleKind, title: &str, year: Option<u16>) -> PathBuf {
+    let key = format!("{}\0{}", title.to_lowercase(), year.map_or_else(String::new, |y| y.to_string()));
+    cache_path.join("metadata").join(kind.api_name()).join(cache::dir_name(&key))
+}
+
+/// The metadata of a title, when it was fetched and The Movie Database had it.
+pub fn read_metadata(cache_path: &Path, kind: TitleKind, title: &str, year: Option<u16>) -> Option<Metadata> {
+    let file = File::open(cache_dir(cache_path, kind, title, year).join(METADATA)).ok()?;
+    serde_json::from_reader::<_, Option<Metadata>>(file).ok()?
+}
+
+impl MetadataFetcher {
+    /// Looks up every title that hasn't been looked up yet, reporting progress as a percentage.
+    pub fn fetch_metadata(&self, export: &LibraryExport, progress: &dyn Fn(u8)) -> Result<(), Error> {
+        let missing = titles(export).into_iter()
+            .filter(|&(kind, title, year)| !cache_dir(&self.cache_path, kind, title, year).join(METADATA).is_file())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            trace!("Metadata already fetched for every title");
+            return Ok(());
+        }
+        let client = Client::new();
+        let mut genres = HashMap::new();
+        for kind in &[TitleKind::Movie, TitleKind::TvShow] {
+            let list: Genres = self.get(&client, &format!("/genre/{}/list", kind.api_name()), &[])?;
+            genres.insert(*kind, list.genres.into_iter().map(|g| (g.id, g.name)).collect::<HashMap<_, _>>());
+        }
+
+        let (total, mut failed) = (missing.len(), 0);
+        for (i, (kind, title, year)) in missing.into_iter().enumerate() {
+            let year = year.map(|y| y.to_string());
+            let mut query = vec![("query", title)];
+            if let Some(ref year) = year {
+                query.push((kind.year_param(), year));
+            }
+            let found = self.get::<SearchResults>(&client, &format!("/search/{}", kind.api_name()), &query)
+                .map(|results| results.results.into_iter().next().map(|r| Metadata {
+                    id: r.id,
+                    overview: r.overview,
+                    poster_path: r.poster_path,
+                    backdrop_path: r.backdrop_path,
+                    genres: r.genre_ids.iter().filter_map(|id| genres[&kind].get(id).cloned()).collect(),
+                    rating: r.vote_average,
+                }));
+            let dir = cache_dir(&self.cache_path, kind, title, year.as_ref().and_then(|y| y.parse().ok()));
+            let saved = found.and_then(|metadata| {
+                fs::create_dir_all(&dir)?;
+                write_file(&dir.join(METADATA), &serde_json::to_vec(&metadata)?)
+            });
+            if let Err(err) = saved {
+                warn!("Could not fetch the metadata of {:?}, err: {}", title, err);
+                failed += 1;
+            }
+            progress((i * 100 / total) as u8);
+        }
+
+        info!("Fetched the metadata of {} titles", total - failed);
+        if failed > 0 {
+            return Err(format_err!("could not fetch the metadata of {} of {} titles", failed, total));
+        }
+        Ok(())
+    }
+
+    /// Downloads the poster and backdrop of every title that has metadata but not its artwork yet.
+    pub fn fetch_artwork(&self, export: &LibraryExport, progress: &dyn Fn(u8)) -> Result<(), Error> {
+        let client = Client::new();
+        let titles = titles(export);
+        let (total, mut downloaded, mut failed) = (titles.len(), 0, 0);
+        for (i, (kind, title, year)) in titles.into_iter().enumerate() {
+            let metadata = match read_metadata(&self.cache_path, kind, title, year) {
+                Some(metadata) => metadata,
+                None => continue,
+            };
+            let dir = cache_dir(&self.cache_path, kind, title, year);
+            for (image, name) in &[(metadata.poster_path, POSTER), (metadata.backdrop_path, BACKDROP)] {
+                let image = match image {
+                    Some(image) if !dir.join(name).is_file() => image,
+                    _ => continue,
+                };
+                match download(&client, &format!("{}{}", IMAGE_URL, image), &dir.join(name)) {
+                    Ok(()) => downloaded += 1,
+                    Err(err) => {
+                        warn!("Could not download the {} of {:?}, err: {}", name, title, err);
+                        failed += 1;
+                    },
+                }
+            }
+            progress((i * 100 / total) as u8);
+        }
+
+        info!("Downloaded {} images", downloaded);
+        if failed > 0 {
+            return Err(format_err!("could not download {} images", failed));
+        }
+        Ok(())
+    }
+
+    fn get<T: serde::de::DeserializeOwned>(&self, client: &Client, path: &str, query: &[(&str, &str)]) -> Result<T, Error> {
+        let key = self.api_key.as_ref()
+            .ok_or_else(|| format_err!("no API key for The Movie Database, set CAROLUS_TMDB_API_KEY"))?;
+        let mut url = Url::parse(&format!("{}{}", API_URL, path))?;
+        url.query_pairs_mut().append_pair("api_key", key).extend_pairs(query);
+        Ok(client.get(url).send()?.error_for_status()?.json()?)
+    }
+}
+
+fn download(client: &Client, url: &str, path: &Path) -> Result<(), Error> {
+    let mut response = client.get(url).send()?.error_for_status()?;
+    let temp = path.with_extension("part");
+    response.copy_to(&mut File::create(&temp)?)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Writes a file whole by writing it next to where it goes first.
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
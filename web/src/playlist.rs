@@ -0,0 +1,62 @@
+use crate::export::xml_escape;
+
+/// One stream of a playlist.
+pub struct Entry {
+    pub title: String,
+    pub url: String,
+    /// In seconds, when it is known.
+    pub duration: Option<f64>,
+}
+
+/// An extended M3U playlist, with the length and title of every stream, -1 being an unknown length.
+pub fn m3u8(title: &str, entries: &[Entry]) -> String {
+    let one_line = |s: &str| s.replace(|c: char| c == '\r' || c == '\n', " ");
+    let mut m3u8 = format!("#EXTM3U\n#PLAYLIST:{}\n", one_line(title));
+    for entry in entries {
+        let duration = entry.duration.map_or(-1, |d| d.round() as i64);
+        m3u8.push_str(&format!("#EXTINF:{},{}\n{}\n", duration, one_line(&entry.title), entry.url));
+    }
+    m3u8
+}
+
+/// An XSPF playlist, which has lengths in milliseconds.
+pub fn xspf(title: &str, entries: &[Entry]) -> String {
+    let mut xspf = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <title>{}</title>\n  <trackList>\n", xml_escape(title));
+    for entry in entries {
+        xspf.push_str(&format!("    <track>\n      <location>{}</location>\n      <title>{}</title>\n", xml_escape(&entry.url), xml_escape(&entry.title)));
+        if let Some(duration) = entry.duration {
+            xspf.push_str(&format!("      <duration>{}</duration>\n", (duration * 1000.0).round() as u64));
+        }
+        xspf.push_str("    </track>\n");
+    }
+    xspf.push_str("  </trackList>\n</playlist>\n");
+    xspf
+}
+
+#[cfg(test)]
+fn test_entries() -> Vec<Entry> {
+    vec![
+        Entry { title: "Alien (1979)".to_owned(), url: "http://carolus/play/movie/Alien?version=0&part=0&year=1979".to_owned(), duration: Some(7020.4) },
+        Entry { title: "Tom & Jerry <1940>\nPart \"1\"".to_owned(), url: "http://carolus/play/movie/Tom%20%26%20Jerry?version=0&part=0&token=a%26b".to_owned(), duration: None },
+    ]
+}
+
+#[test]
+fn m3u8_playlists() {
+    assert_eq!("#EXTM3U\n#PLAYLIST:Movies\n\
+        #EXTINF:7020,Alien (1979)\nhttp://carolus/play/movie/Alien?version=0&part=0&year=1979\n\
+        #EXTINF:-1,Tom & Jerry <1940> Part \"1\"\nhttp://carolus/play/movie/Tom%20%26%20Jerry?version=0&part=0&token=a%26b\n",
+        m3u8("Movies", &test_entries()));
+    assert_eq!("#EXTM3U\n#PLAYLIST:Friday night\n", m3u8("Friday\nnight", &[]));
+}
+
+#[test]
+fn xspf_playlists() {
+    let xspf = xspf("Tom & Jerry", &test_entries());
+    assert!(xspf.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <title>Tom &amp; Jerry</title>\n"), "{}", xspf);
+    assert!(xspf.contains("    <track>\n      <location>http://carolus/play/movie/Alien?version=0&amp;part=0&amp;year=1979</location>\n      <title>Alien (1979)</title>\n      <duration>7020400</duration>\n    </track>\n"), "{}", xspf);
+    // Without a length the track has no duration at all.
+    assert!(xspf.contains("      <title>Tom &amp; Jerry &lt;1940&gt;\nPart &quot;1&quot;</title>\n    </track>\n"), "{}", xspf);
+    assert_eq!(2, xspf.matches("<track>").count());
+    assert!(xspf.ends_with("  </trackList>\n</playlist>\n"));
+}
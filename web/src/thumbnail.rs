@@ -0,0 +1,174 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use failure::{Error, format_err};
+use log::{info, trace, warn};
+
+use crate::cache;
+
+/// Representative frame shown before a video starts playing.
+pub const POSTER: &str = "poster.jpg";
+/// Sprite sheet of frames taken every `interval` seconds.
+pub const SPRITE: &str = "sprite.jpg";
+/// WebVTT track mapping time ranges onto regions of the sprite sheet.
+pub const SPRITE_TRACK: &str = "sprite.vtt";
+
+const FRAME_WIDTH: u32 = 160;
+const FRAME_HEIGHT: u32 = 90;
+const FRAMES_PER_ROW: u32 = 10;
+
+/// Generates thumbnails with a local ffmpeg.
+#[derive(Clone)]
+pub struct Thumbnailer {
+    pub cache_path: PathBuf,
+    pub ffmpeg: String,
+    pub ffprobe: String,
+    pub interval: u32,
+}
+
+/// Directory holding the generated thumbnails for a video file.
+pub fn cache_dir(cache_path: &Path, file_path: &str) -> PathBuf {
+    cache_path.join("thumbnails").join(cache::dir_name(file_path))
+}
+
+/// Length in seconds of a video that has had its thumbnails generated, which is where the
+/// last frame of its sprite track ends.
+pub fn duration(cache_path: &Path, file_path: &str) -> Option<f64> {
+    track_duration(&fs::read_to_string(cache_dir(cache_path, file_path).join(SPRITE_TRACK)).ok()?)
+}
+
+fn track_duration(track: &str) -> Option<f64> {
+    let end = track.lines().filter(|l| l.contains(" --> ")).last()?.split(" --> ").nth(1)?;
+    let mut seconds = 0.0;
+    for field in end.trim().split(':') {
+        seconds = seconds * 60.0 + field.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+impl Thumbnailer {
+    /// Generates the poster, sprite sheet and track for a video, reporting progress as a percentage.
+    pub fn generate(&self, file_path: &str, progress: &dyn Fn(u8)) -> Result<(), Error> {
+        let dir = cache_dir(&self.cache_path, file_path);
+        if [POSTER, SPRITE, SPRITE_TRACK].iter().all(|name| dir.join(name).is_file()) {
+            trace!("Thumbnails already generated for {:?}", file_path);
+            return Ok(());
+        }
+        fs::create_dir_all(&dir)?;
+
+        let duration = self.probe_duration(file_path)?;
+        let frames = ((duration / f64::from(self.interval)).ceil() as u32).max(1);
+        progress(10);
+
+        self.extract_poster(file_path, duration, &dir.join(POSTER))?;
+        progress(30);
+        self.extract_sprite(file_path, frames, &dir.join(SPRITE))?;
+        progress(90);
+        // The track is written last as its presence marks the set as complete.
+        File::create(dir.join(SPRITE_TRACK))?.write_all(sprite_track(duration, self.interval, frames).as_bytes())?;
+
+        info!("Generated thumbnails for {:?} in {:?}", file_path, dir);
+        Ok(())
+    }
+
+    /// Keeps the thumbnails of a file that was renamed or moved rather than generating them again.
+    pub fn moved(&self, from: &str, to: &str) {
+        let (from_dir, to_dir) = (cache_dir(&self.cache_path, from), cache_dir(&self.cache_path, to));
+        if from_dir.is_dir() && !to_dir.exists() {
+            match fs::rename(&from_dir, &to_dir) {
+                Ok(()) => trace!("Moved thumbnails of {:?} to {:?}", from, to),
+                Err(err) => warn!("Could not move thumbnails of {:?} to {:?}, err: {}", from, to, err),
+            }
+        }
+    }
+
+    fn probe_duration(&self, file_path: &str) -> Result<f64, Error> {
+        let output = run(Command::new(&self.ffprobe)
+            .args(&["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1"])
+            .arg(file_path))?;
+        Ok(String::from_utf8_lossy(&output).trim().parse::<f64>()?)
+    }
+
+    fn extract_poster(&self, file_path: &str, duration: f64, output: &Path) -> Result<(), Error> {
+        run(Command::new(&self.ffmpeg)
+            .args(&["-v", "error", "-y", "-ss", &format!("{:.3}", duration * 0.1), "-i"])
+            .arg(file_path)
+            .args(&["-vf", "thumbnail,scale=640:-2", "-frames:v", "1"])
+            .arg(output))?;
+        Ok(())
+    }
+
+    fn extract_sprite(&self, file_path: &str, frames: u32, output: &Path) -> Result<(), Error> {
+        let rows = (frames + FRAMES_PER_ROW - 1) / FRAMES_PER_ROW;
+        let filter = format!(
+            "fps=1/{interval},scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,tile={cols}x{rows}",
+            interval = self.interval, w = FRAME_WIDTH, h = FRAME_HEIGHT, cols = FRAMES_PER_ROW, rows = rows);
+        run(Command::new(&self.ffmpeg)
+            .args(&["-v", "error", "-y", "-i"])
+            .arg(file_path)
+            .args(&["-vf", &filter, "-frames:v", "1", "-q:v", "5"])
+            .arg(output))?;
+        Ok(())
+    }
+}
+
+fn run(command: &mut Command) -> Result<Vec<u8>, Error> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format_err!("{:?} failed: {}", command, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+fn sprite_track(duration: f64, interval: u32, frames: u32) -> String {
+    let mut track = "WEBVTT\n".to_owned();
+    for frame in 0..frames {
+        let start = f64::from(frame * interval);
+        let end = (start + f64::from(interval)).min(duration);
+        let x = (frame % FRAMES_PER_ROW) * FRAME_WIDTH;
+        let y = (frame / FRAMES_PER_ROW) * FRAME_HEIGHT;
+        track.push_str(&format!("\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            timestamp(start), timestamp(end), SPRITE, x, y, FRAME_WIDTH, FRAME_HEIGHT));
+    }
+    track
+}
+
+/// A sprite track whose cues reach the sprite sheet with `query`, as they name it relative to the
+/// track, so that it is the sheet of the same version and year the track was asked for.
+pub fn with_sprite_query(track: &str, query: &str) -> String {
+    if query.is_empty() {
+        return track.to_owned();
+    }
+    track.replace(&format!("\n{}#", SPRITE), &format!("\n{}?{}#", SPRITE, query))
+}
+
+fn timestamp(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!("{:02}:{:02}:{:02}.{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+}
+
+#[test]
+fn sprite_track_query() {
+    let track = sprite_track(25.0, 10, 3);
+    assert_eq!("WEBVTT\n\
+        \n00:00:00.000 --> 00:00:10.000\nsprite.jpg#xywh=0,0,160,90\n\
+        \n00:00:10.000 --> 00:00:20.000\nsprite.jpg#xywh=160,0,160,90\n\
+        \n00:00:20.000 --> 00:00:25.000\nsprite.jpg#xywh=320,0,160,90\n", track);
+
+    let versioned = with_sprite_query(&track, "version=1&year=1979");
+    assert_eq!(3, versioned.matches("\nsprite.jpg?version=1&year=1979#xywh=").count());
+    assert!(!versioned.contains("\nsprite.jpg#"));
+    assert_eq!(track, with_sprite_query(&track, ""));
+}
+
+#[test]
+fn track_durations() {
+    assert_eq!(Some(25.0), track_duration(&sprite_track(25.0, 10, 3)));
+    assert_eq!(Some(7020.5), track_duration(&sprite_track(7020.5, 10, 703)));
+    assert_eq!(Some(3723.25), track_duration("WEBVTT\n\n01:02:03.250 --> 01:02:03.250\nsprite.jpg#xywh=0,0,160,90\n"));
+    assert_eq!(Some(62.5), track_duration("WEBVTT\n\n00:00.000 --> 01:02.500\nsprite.jpg#xywh=0,0,160,90\n"));
+    assert_eq!(None, track_duration("WEBVTT\n"));
+    assert_eq!(None, track_duration("WEBVTT\n\n00:00:00.000 --> 00:00:1x.000\nsprite.jpg#xywh=0,0,160,90\n"));
+}